@@ -1,58 +1,81 @@
-use super::EventDatabase;
+use super::{
+    ContentIndex, EscrowedNtReceipt, EscrowedTReceipt, EventDatabase, SequenceIndex, SequenceTable,
+    WriteBatch, WriteOp,
+};
+use crate::prefix::{
+    AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfAddressingPrefix,
+    SelfSigningPrefix,
+};
 use crate::{
-    error::Error,
-    prefix::{
-        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfAddressingPrefix,
-        SelfSigningPrefix,
-    },
+    event::{event_data::EventData, sections::seal::EventSeal},
+    event_message::parse::message,
+    state::IdentifierState,
 };
 use bincode;
 use chrono::prelude::*;
 use rkv::{
     backend::{
         BackendDatabase, BackendEnvironment, SafeMode, SafeModeDatabase, SafeModeEnvironment,
+        SafeModeRwTransaction,
     },
     value::Type,
-    DataError, Manager, MultiStore, Rkv, SingleStore, StoreError, StoreOptions, Value,
+    DataError, Manager, MultiStore, Rkv, SingleStore, StoreError, StoreOptions, Value, Writer,
 };
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 pub struct LmdbEventDatabase {
     events: SingleStore<SafeModeDatabase>,
+    digests: SingleStore<SafeModeDatabase>,
     datetime_stamps: SingleStore<SafeModeDatabase>,
     signatures: MultiStore<SafeModeDatabase>,
     receipts_nt: MultiStore<SafeModeDatabase>,
-    escrowed_receipts_nt: MultiStore<SafeModeDatabase>,
+    escrowed_receipts_nt: SingleStore<SafeModeDatabase>,
     receipts_t: MultiStore<SafeModeDatabase>,
-    escrowed_receipts_t: MultiStore<SafeModeDatabase>,
-    key_event_logs: MultiStore<SafeModeDatabase>,
-    partially_signed_events: MultiStore<SafeModeDatabase>,
-    out_of_order_events: MultiStore<SafeModeDatabase>,
-    likely_duplicitous_events: MultiStore<SafeModeDatabase>,
-    duplicitous_events: MultiStore<SafeModeDatabase>,
+    escrowed_receipts_t: SingleStore<SafeModeDatabase>,
+    key_event_logs: SingleStore<SafeModeDatabase>,
+    partially_signed_events: SingleStore<SafeModeDatabase>,
+    partially_witnessed_events: SingleStore<SafeModeDatabase>,
+    out_of_order_events: SingleStore<SafeModeDatabase>,
+    likely_duplicitous_events: SingleStore<SafeModeDatabase>,
+    duplicitous_events: SingleStore<SafeModeDatabase>,
+    superseded_events: SingleStore<SafeModeDatabase>,
+    delegates: MultiStore<SafeModeDatabase>,
+    delegators: SingleStore<SafeModeDatabase>,
+    states: SingleStore<SafeModeDatabase>,
+    meta: SingleStore<SafeModeDatabase>,
     env: Arc<RwLock<Rkv<SafeModeEnvironment>>>,
 }
 
-pub struct ContentIndex<'a>(&'a IdentifierPrefix, &'a SelfAddressingPrefix);
-pub struct SequenceIndex<'a>(&'a IdentifierPrefix, u64);
+/// Schema Version
+///
+/// Version of the store layout, recorded in the meta store.
+/// Environments without one were written with the original
+/// layout, where the sequence tables and receipt escrows were
+/// DUPSORT stores keyed by prefix and sn
+const SCHEMA_VERSION: u64 = 1;
 
-// useful for using as an index type, but expensive
-// TODO: investigate using AsRef<[u8]>
-impl From<ContentIndex<'_>> for Vec<u8> {
-    fn from(ci: ContentIndex) -> Self {
-        [ci.0.to_str(), ".".into(), ci.1.to_str()]
-            .concat()
-            .into_bytes()
-    }
-}
+/// Stores of the current layout, plus the legacy ones
+/// opened while migrating
+const MAX_STORES: u32 = 26;
 
-impl From<SequenceIndex<'_>> for Vec<u8> {
-    fn from(si: SequenceIndex) -> Self {
-        format!("{}.{:032}", si.0.to_str(), si.1).into_bytes()
-    }
-}
+/// Sequence tables of the original layout, each migrated
+/// to the current store for the same table
+const LEGACY_SEQUENCE_STORES: [(&str, SequenceTable); 5] = [
+    ("kels", SequenceTable::KeyEventLog),
+    ("pses", SequenceTable::PartiallySigned),
+    ("ooes", SequenceTable::OutOfOrder),
+    ("ldes", SequenceTable::LikelyDuplicitous),
+    ("dels", SequenceTable::Duplicitous),
+];
+
+/// Receipt escrows of the original layout, keyed by the
+/// receipted prefix and digest, without the sn the current
+/// one needs. Entries for events this database never logged
+/// can't be placed, and are left where they are
+const LEGACY_RECEIPT_ESCROWS: [&str; 2] = ["ures", "vres"];
 
 impl LmdbEventDatabase {
     pub fn new<'p, P>(path: P) -> Result<Self, StoreError>
@@ -61,42 +84,517 @@ impl LmdbEventDatabase {
     {
         let mut m = Manager::<SafeModeEnvironment>::singleton().write()?;
         let created_arc =
-            m.get_or_create_with_capacity(path, 12, Rkv::with_capacity::<SafeMode>)?;
+            m.get_or_create_with_capacity(path, MAX_STORES, Rkv::with_capacity::<SafeMode>)?;
         let env = created_arc.read()?;
 
-        Ok(Self {
+        let db = Self {
             events: env.open_single("evts", StoreOptions::create())?,
+            digests: env.open_single("digs", StoreOptions::create())?,
             datetime_stamps: env.open_single("dtss", StoreOptions::create())?,
             signatures: env.open_multi("sigs", StoreOptions::create())?,
             receipts_nt: env.open_multi("rcts", StoreOptions::create())?,
-            escrowed_receipts_nt: env.open_multi("ures", StoreOptions::create())?,
+            escrowed_receipts_nt: env.open_single("ures.v1", StoreOptions::create())?,
             receipts_t: env.open_multi("vrcs", StoreOptions::create())?,
-            escrowed_receipts_t: env.open_multi("vres", StoreOptions::create())?,
-            key_event_logs: env.open_multi("kels", StoreOptions::create())?,
-            partially_signed_events: env.open_multi("pses", StoreOptions::create())?,
-            out_of_order_events: env.open_multi("ooes", StoreOptions::create())?,
-            likely_duplicitous_events: env.open_multi("ldes", StoreOptions::create())?,
-            duplicitous_events: env.open_multi("dels", StoreOptions::create())?,
+            escrowed_receipts_t: env.open_single("vres.v1", StoreOptions::create())?,
+            key_event_logs: env.open_single("kels.v1", StoreOptions::create())?,
+            partially_signed_events: env.open_single("pses.v1", StoreOptions::create())?,
+            partially_witnessed_events: env.open_single("pwes", StoreOptions::create())?,
+            out_of_order_events: env.open_single("ooes.v1", StoreOptions::create())?,
+            likely_duplicitous_events: env.open_single("ldes.v1", StoreOptions::create())?,
+            duplicitous_events: env.open_single("dels.v1", StoreOptions::create())?,
+            superseded_events: env.open_single("sdes", StoreOptions::create())?,
+            delegates: env.open_multi("dlgs", StoreOptions::create())?,
+            delegators: env.open_single("dlgr", StoreOptions::create())?,
+            states: env.open_single("stts", StoreOptions::create())?,
+            meta: env.open_single("meta", StoreOptions::create())?,
             env: created_arc.clone(),
-        })
+        };
+        db.migrate(&env)?;
+        Ok(db)
+    }
+
+    /// Migrate
+    ///
+    /// Brings an environment written with an older layout up to
+    /// the current one. The legacy DUPSORT stores can only be read
+    /// key by key, so their entries are looked up for the prefix
+    /// and sn of every logged event
+    fn migrate(&self, env: &Rkv<SafeModeEnvironment>) -> Result<(), StoreError> {
+        let version = match self.meta.get(&env.read()?, "version")? {
+            Some(Value::U64(version)) => version,
+            Some(_) => {
+                return Err(StoreError::DataError(DataError::UnexpectedType {
+                    expected: Type::U64,
+                    actual: Type::from_tag(0u8)?,
+                }))
+            }
+            None => 0,
+        };
+        if version >= SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        // stores have to be opened before the write transaction starts
+        let legacy_sequences = LEGACY_SEQUENCE_STORES
+            .iter()
+            .map(|(name, table)| Ok((env.open_multi(*name, StoreOptions::create())?, *table)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let legacy_escrows = LEGACY_RECEIPT_ESCROWS
+            .iter()
+            .map(|name| env.open_multi(*name, StoreOptions::create()))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let mut writer = env.write()?;
+
+        let mut indexes = BTreeMap::new();
+        let mut logged = vec![];
+        for entry in self.events.iter_start(&writer)? {
+            if let (key, Value::Blob(raw)) = entry? {
+                if let (Ok((_, parsed)), Some(dig)) = (message(raw), content_digest(key)) {
+                    let event = parsed.event.event;
+                    indexes.insert(
+                        Vec::from(SequenceIndex(&event.prefix, event.sn)),
+                        (event.prefix.clone(), event.sn),
+                    );
+                    logged.push((event.prefix, event.sn, dig, raw.to_vec()));
+                }
+            }
+        }
+
+        // duplicates come back sorted, inserting them in that order keeps
+        // the last one at an sn the same as the original layout returned
+        for (legacy, table) in legacy_sequences {
+            let mut entries = vec![];
+            for (index, (pref, sn)) in &indexes {
+                for entry in legacy.get(&writer, index)? {
+                    let dig: SelfAddressingPrefix = deserialize_blob(entry?.1)?;
+                    entries.push(WriteOp::insert(table, pref, *sn, &dig));
+                }
+            }
+            for op in entries {
+                self.apply(&mut writer, op)?;
+            }
+            legacy.clear(&mut writer)?;
+        }
+        // receipts are migrated after the KELs, which validator
+        // receipts are matched against
+        for legacy in legacy_escrows {
+            let mut entries = vec![];
+            for (pref, sn, dig, raw) in &logged {
+                let key = Vec::from(ContentIndex(pref, dig));
+                for entry in legacy.get(&writer, &key)? {
+                    let value = match entry?.1 {
+                        Value::Blob(value) => value.to_vec(),
+                        _ => {
+                            return Err(StoreError::DataError(DataError::UnexpectedType {
+                                expected: Type::Blob,
+                                actual: Type::from_tag(0u8)?,
+                            }))
+                        }
+                    };
+                    let op = self.legacy_receipt(&writer, pref, *sn, dig, raw, &value)?;
+                    entries.push((key.clone(), value, op));
+                }
+            }
+            for (key, value, op) in entries {
+                self.apply(&mut writer, op)?;
+                legacy.delete(&mut writer, key, &Value::Blob(&value))?;
+            }
+        }
+
+        self.meta
+            .put(&mut writer, "version", &Value::U64(SCHEMA_VERSION))?;
+        writer.commit()
     }
 
-    fn write_ref_multi<D: Serialize>(
+    /// Legacy Receipt
+    ///
+    /// Converts a receipt escrowed with the original layout, where
+    /// witness and validator receipts shared a store. Validator
+    /// receipts didn't record the validator's establishment event,
+    /// it's the one in the validator's KEL whose keys verify the
+    /// signature, and a receipt without one can't be migrated
+    fn legacy_receipt(
         &self,
-        table: &MultiStore<SafeModeDatabase>,
-        key: &[u8],
-        data: &D,
+        writer: &Writer<SafeModeRwTransaction>,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+        raw: &[u8],
+        value: &[u8],
+    ) -> Result<WriteOp, StoreError> {
+        if let Ok((signer, signature)) = bincode::deserialize(value) {
+            return Ok(WriteOp::EscrowNtReceipt(EscrowedNtReceipt {
+                prefix: pref.clone(),
+                sn,
+                digest: dig.clone(),
+                signer,
+                signature,
+            }));
+        }
+        let (validator, signature): (IdentifierPrefix, AttachedSignaturePrefix) =
+            deserialize_blob(Value::Blob(value))?;
+
+        let mut establishment = None;
+        for validator_sn in 0.. {
+            let event_digest = match entries_at_sn(
+                &self.key_event_logs,
+                writer,
+                &validator,
+                validator_sn,
+            )?
+            .pop()
+            {
+                Some((_, event_digest)) => event_digest,
+                None => break,
+            };
+            let event = match self
+                .events
+                .get(writer, Vec::from(ContentIndex(&validator, &event_digest)))?
+            {
+                Some(Value::Blob(event)) => message(event).map(|(_, p)| p.event.event.event_data),
+                _ => continue,
+            };
+            let keys = match event {
+                Ok(EventData::Icp(icp)) => icp.key_config,
+                Ok(EventData::Rot(rot)) => rot.key_config,
+                Ok(EventData::Dip(dip)) => dip.inception_data.key_config,
+                Ok(EventData::Drt(drt)) => drt.rotation_data.key_config,
+                _ => continue,
+            };
+            let key = keys.public_keys.get(signature.index as usize);
+            if matches!(
+                key.map(|k| k.verify(raw, &signature.signature)),
+                Some(Ok(true))
+            ) {
+                establishment = Some(event_digest);
+            }
+        }
+
+        match establishment {
+            Some(event_digest) => Ok(WriteOp::EscrowTReceipt(EscrowedTReceipt {
+                prefix: pref.clone(),
+                sn,
+                digest: dig.clone(),
+                validator_seal: EventSeal {
+                    prefix: validator,
+                    event_digest,
+                },
+                signature,
+            })),
+            None => Err(StoreError::DataError(DataError::DecodingError {
+                value_type: Type::Blob,
+                err: Box::new(bincode::ErrorKind::Custom(format!(
+                    "no establishment event of {} verifies its escrowed receipt",
+                    validator.to_str()
+                ))),
+            })),
+        }
+    }
+
+    fn sequence_table(&self, table: SequenceTable) -> &SingleStore<SafeModeDatabase> {
+        match table {
+            SequenceTable::KeyEventLog => &self.key_event_logs,
+            SequenceTable::PartiallySigned => &self.partially_signed_events,
+            SequenceTable::PartiallyWitnessed => &self.partially_witnessed_events,
+            SequenceTable::OutOfOrder => &self.out_of_order_events,
+            SequenceTable::LikelyDuplicitous => &self.likely_duplicitous_events,
+            SequenceTable::Duplicitous => &self.duplicitous_events,
+            SequenceTable::Superseded => &self.superseded_events,
+        }
+    }
+
+    fn apply(
+        &self,
+        writer: &mut Writer<SafeModeRwTransaction>,
+        op: WriteOp,
     ) -> Result<(), StoreError> {
+        match op {
+            WriteOp::LogEvent {
+                pref,
+                dig,
+                aliases,
+                raw,
+                sigs,
+            } => {
+                let key: Vec<u8> = ContentIndex(&pref, &dig).into();
+
+                // insert timestamp for event, unless it was seen before
+                if self.datetime_stamps.get(writer, &key)?.is_none() {
+                    self.datetime_stamps.put(
+                        writer,
+                        &key,
+                        &Value::Str(&Utc::now().to_rfc3339()),
+                    )?;
+                }
+
+                // index the event under its aliases, unless they were taken before
+                for alias in aliases {
+                    let alias_key = Vec::from(ContentIndex(&pref, &alias));
+                    if self.digests.get(writer, &alias_key)?.is_none() {
+                        self.digests.put(
+                            writer,
+                            &alias_key,
+                            &Value::Blob(&serialize_blob(&dig)),
+                        )?;
+                    }
+                }
+
+                // insert signatures for event
+                for sig in sigs.iter() {
+                    self.signatures
+                        .put(writer, &key, &Value::Blob(&serialize_blob(sig)))?;
+                }
+
+                // insert event itself
+                self.events.put(writer, &key, &Value::Blob(&raw))
+            }
+            WriteOp::Insert {
+                table,
+                pref,
+                sn,
+                dig,
+            } => {
+                let store = self.sequence_table(table);
+                let entries = entries_at_sn(store, writer, &pref, sn)?;
+
+                // a digest inserted again keeps its place
+                if entries.iter().any(|(_, d)| d == &dig) {
+                    return Ok(());
+                }
+                let position = entries
+                    .last()
+                    .and_then(|(key, _)| sequence_position(key))
+                    .map_or(0, |p| p + 1);
+                store.put(
+                    writer,
+                    sequence_key(&pref, sn, position),
+                    &Value::Blob(&serialize_blob(&dig)),
+                )
+            }
+            WriteOp::Remove {
+                table,
+                pref,
+                sn,
+                dig,
+            } => {
+                let store = self.sequence_table(table);
+                match entries_at_sn(store, writer, &pref, sn)?
+                    .into_iter()
+                    .find(|(_, d)| d == &dig)
+                {
+                    Some((key, _)) => delete_single(store, writer, key),
+                    // removing something which isn't there is fine
+                    None => Ok(()),
+                }
+            }
+            WriteOp::NtReceipt {
+                pref,
+                dig,
+                signer,
+                sig,
+            } => self.receipts_nt.put(
+                writer,
+                Vec::from(ContentIndex(&pref, &dig)),
+                &Value::Blob(&serialize_blob(&(signer, sig))),
+            ),
+            WriteOp::TReceipt {
+                pref,
+                dig,
+                signer,
+                sig,
+            } => self.receipts_t.put(
+                writer,
+                Vec::from(ContentIndex(&pref, &dig)),
+                &Value::Blob(&serialize_blob(&(signer, sig))),
+            ),
+            WriteOp::EscrowNtReceipt(receipt) => self.escrowed_receipts_nt.put(
+                writer,
+                nt_receipt_key(&receipt),
+                &Value::Blob(&serialize_blob(&receipt)),
+            ),
+            WriteOp::RemoveNtReceipt(receipt) => {
+                delete_single(&self.escrowed_receipts_nt, writer, nt_receipt_key(&receipt))
+            }
+            WriteOp::EscrowTReceipt(receipt) => self.escrowed_receipts_t.put(
+                writer,
+                t_receipt_key(&receipt),
+                &Value::Blob(&serialize_blob(&receipt)),
+            ),
+            WriteOp::RemoveTReceipt(receipt) => {
+                delete_single(&self.escrowed_receipts_t, writer, t_receipt_key(&receipt))
+            }
+            WriteOp::LogDelegation {
+                delegator,
+                delegate,
+            } => {
+                let delegate_key = delegate.to_str();
+
+                // a delegate keeps the delegator it was incepted with
+                if self.delegators.get(writer, &delegate_key)?.is_none() {
+                    self.delegators.put(
+                        writer,
+                        &delegate_key,
+                        &Value::Blob(&serialize_blob(&delegator)),
+                    )?;
+                }
+                self.delegates.put(
+                    writer,
+                    delegator.to_str(),
+                    &Value::Blob(&serialize_blob(&delegate)),
+                )
+            }
+            WriteOp::UpdateState { state, dig } => self.states.put(
+                writer,
+                state.prefix.to_str(),
+                &Value::Blob(&serialize_blob(&(&state, dig))),
+            ),
+        }
+    }
+
+    /// Scan
+    ///
+    /// Reads every entry of a single store whose key starts with
+    /// Start, in key order
+    fn scan<T: DeserializeOwned>(
+        &self,
+        store: &SingleStore<SafeModeDatabase>,
+        start: &[u8],
+    ) -> Result<Vec<(Vec<u8>, T)>, StoreError> {
         let lock = self.env.read()?;
-        let mut writer = lock.write()?;
+        let reader = lock.read()?;
+        if start.is_empty() {
+            collect_prefixed(store.iter_start(&reader)?, start)
+        } else {
+            collect_prefixed(store.iter_from(&reader, start)?, start)
+        }
+    }
+}
 
-        table.put(
-            &mut writer,
-            &key,
-            &Value::Blob(&bincode::serialize(data).unwrap()),
-        )?;
+fn collect_prefixed<'i, T: DeserializeOwned>(
+    iter: impl Iterator<Item = Result<(&'i [u8], Value<'i>), StoreError>>,
+    start: &[u8],
+) -> Result<Vec<(Vec<u8>, T)>, StoreError> {
+    let mut entries = vec![];
+    for entry in iter {
+        let (key, value) = entry?;
+        if !key.starts_with(start) {
+            break;
+        }
+        entries.push((key.to_vec(), deserialize_blob(value)?));
+    }
+    Ok(entries)
+}
 
-        writer.commit()
+// sequence tables hold one entry per digest, keyed by sn and then
+// by insertion position, so that a prefix's entries are read in
+// order and the last one at an sn is the one inserted last
+fn sequence_key(pref: &IdentifierPrefix, sn: u64, position: u64) -> Vec<u8> {
+    [sn_start(pref, sn), format!("{:020}", position).into_bytes()].concat()
+}
+
+fn sn_start(pref: &IdentifierPrefix, sn: u64) -> Vec<u8> {
+    [Vec::from(SequenceIndex(pref, sn)), b".".to_vec()].concat()
+}
+
+/// Content Digest
+///
+/// Recovers the digest from a key which is a ContentIndex
+fn content_digest(key: &[u8]) -> Option<SelfAddressingPrefix> {
+    std::str::from_utf8(key)
+        .ok()?
+        .rsplit('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn sequence_position(key: &[u8]) -> Option<u64> {
+    std::str::from_utf8(key)
+        .ok()?
+        .rsplit('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Entries at SN
+///
+/// Reads the digests at an sn of a sequence table through the
+/// writer, so that earlier operations of the batch are seen
+fn entries_at_sn(
+    store: &SingleStore<SafeModeDatabase>,
+    writer: &Writer<SafeModeRwTransaction>,
+    pref: &IdentifierPrefix,
+    sn: u64,
+) -> Result<Vec<(Vec<u8>, SelfAddressingPrefix)>, StoreError> {
+    let start = sn_start(pref, sn);
+    collect_prefixed(store.iter_from(writer, &start)?, &start)
+}
+
+fn nt_receipt_key(receipt: &EscrowedNtReceipt) -> Vec<u8> {
+    [
+        Vec::from(SequenceIndex(&receipt.prefix, receipt.sn)),
+        [
+            "".into(),
+            receipt.digest.to_str(),
+            receipt.signer.to_str(),
+            receipt.signature.to_str(),
+        ]
+        .join(".")
+        .into_bytes(),
+    ]
+    .concat()
+}
+
+fn t_receipt_key(receipt: &EscrowedTReceipt) -> Vec<u8> {
+    [
+        Vec::from(SequenceIndex(&receipt.prefix, receipt.sn)),
+        [
+            "".into(),
+            receipt.digest.to_str(),
+            receipt.validator_seal.prefix.to_str(),
+            receipt.validator_seal.event_digest.to_str(),
+            receipt.signature.to_str(),
+        ]
+        .join(".")
+        .into_bytes(),
+    ]
+    .concat()
+}
+
+fn prefix_start(pref: Option<&IdentifierPrefix>) -> Vec<u8> {
+    pref.map_or_else(Vec::new, |p| format!("{}.", p.to_str()).into_bytes())
+}
+
+fn delete_single(
+    store: &SingleStore<SafeModeDatabase>,
+    writer: &mut Writer<SafeModeRwTransaction>,
+    key: Vec<u8>,
+) -> Result<(), StoreError> {
+    match store.delete(writer, key) {
+        // removing something which isn't there is fine
+        Err(StoreError::KeyValuePairNotFound) => Ok(()),
+        res => res,
+    }
+}
+
+fn serialize_blob<T: Serialize>(data: &T) -> Vec<u8> {
+    bincode::serialize(data).unwrap()
+}
+
+fn deserialize_blob<T: DeserializeOwned>(value: Value) -> Result<T, StoreError> {
+    match value {
+        Value::Blob(b) => bincode::deserialize(b).map_err(|e| {
+            StoreError::DataError(DataError::DecodingError {
+                value_type: Type::Blob,
+                err: e,
+            })
+        }),
+        _ => Err(StoreError::DataError(DataError::UnexpectedType {
+            expected: Type::Blob,
+            actual: Type::from_tag(0u8)?,
+        })),
     }
 }
 
@@ -108,30 +606,73 @@ impl EventDatabase for LmdbEventDatabase {
         pref: &IdentifierPrefix,
         sn: u64,
     ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let dig: SelfAddressingPrefix =
+            match self.scan(&self.key_event_logs, &sn_start(pref, sn))?.pop() {
+                Some((_, dig)) => dig,
+                None => return Ok(None),
+            };
+
+        self.get_event(pref, &dig)
+    }
+
+    fn get_cached_state(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<(IdentifierState, SelfAddressingPrefix)>, Self::Error> {
         let lock = self.env.read()?;
         let reader = lock.read()?;
-        let seq_index: Vec<u8> = SequenceIndex(pref, sn).into();
-
-        let dig: SelfAddressingPrefix = match self.key_event_logs.get(&reader, &seq_index)?.last() {
-            Some(v) => match v?.1 {
-                Value::Blob(b) => {
-                    bincode::deserialize(b).map_err(|e| DataError::DecodingError {
-                        value_type: Type::Blob,
-                        err: e,
-                    })?
-                }
-                _ => {
-                    return Err(StoreError::DataError(DataError::UnexpectedType {
-                        expected: Type::Blob,
-                        actual: Type::from_tag(0u8)?,
-                    }))
-                }
-            },
-            None => return Ok(None),
-        };
 
-        let dig_index: Vec<u8> = ContentIndex(pref, &dig).into();
-        match self.events.get(&reader, &dig_index)? {
+        self.states
+            .get(&reader, pref.to_str())?
+            .map(deserialize_blob)
+            .transpose()
+    }
+
+    fn get_children_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<Vec<IdentifierPrefix>>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        let children = self
+            .delegates
+            .get(&reader, pref.to_str())?
+            .map(|entry| deserialize_blob(entry?.1))
+            .collect::<Result<Vec<IdentifierPrefix>, StoreError>>()?;
+
+        Ok(if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        })
+    }
+
+    fn get_parent_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierPrefix>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        self.delegators
+            .get(&reader, pref.to_str())?
+            .map(deserialize_blob)
+            .transpose()
+    }
+
+    fn get_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        match self
+            .events
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+        {
             Some(v) => match v {
                 Value::Blob(b) => Ok(Some(b.to_vec())),
                 _ => Err(StoreError::DataError(DataError::UnexpectedType {
@@ -143,155 +684,144 @@ impl EventDatabase for LmdbEventDatabase {
         }
     }
 
-    fn log_event(
+    fn get_event_timestamp(
         &self,
         pref: &IdentifierPrefix,
         dig: &SelfAddressingPrefix,
-        raw: &[u8],
-        sigs: &[AttachedSignaturePrefix],
-    ) -> Result<(), Self::Error> {
+    ) -> Result<Option<String>, Self::Error> {
         let lock = self.env.read()?;
-        let mut writer = lock.write()?;
-        let key: Vec<u8> = ContentIndex(pref, dig).into();
-
-        // insert timestamp for event
-        self.datetime_stamps
-            .put(&mut writer, &key, &Value::Str(&Utc::now().to_rfc3339()))?;
-
-        // insert signatures for event
-        for sig in sigs.iter() {
-            self.signatures.put(
-                &mut writer,
-                &key,
-                &Value::Blob(&bincode::serialize(&sig).unwrap()),
-            )?;
-        }
-
-        // insert event itself
-        self.events.put(&mut writer, &key, &Value::Blob(raw))?;
+        let reader = lock.read()?;
 
-        writer.commit()
+        match self
+            .datetime_stamps
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+        {
+            Some(v) => match v {
+                Value::Str(s) => Ok(Some(s.to_string())),
+                _ => Err(StoreError::DataError(DataError::UnexpectedType {
+                    expected: Type::Str,
+                    actual: Type::from_tag(0u8)?,
+                })),
+            },
+            None => Ok(None),
+        }
     }
 
-    fn finalise_event(
+    fn resolve_digest(
         &self,
         pref: &IdentifierPrefix,
-        sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.key_event_logs,
-            &Vec::from(SequenceIndex(pref, sn)),
-            dig,
-        )
-    }
+    ) -> Result<Option<SelfAddressingPrefix>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
 
-    fn escrow_partially_signed_event(
-        &self,
-        pref: &IdentifierPrefix,
-        sn: u64,
-        dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.partially_signed_events,
-            &Vec::from(SequenceIndex(pref, sn)),
-            dig,
-        )
+        self.digests
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+            .map(deserialize_blob)
+            .transpose()
     }
 
-    fn escrow_out_of_order_event(
+    fn get_signatures_for_event(
         &self,
         pref: &IdentifierPrefix,
-        sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.out_of_order_events,
-            &Vec::from(SequenceIndex(pref, sn)),
-            dig,
-        )
+    ) -> Result<Option<Vec<AttachedSignaturePrefix>>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        let sigs = self
+            .signatures
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+            .map(|entry| deserialize_blob(entry?.1))
+            .collect::<Result<Vec<AttachedSignaturePrefix>, StoreError>>()?;
+
+        Ok(if sigs.is_empty() { None } else { Some(sigs) })
     }
 
-    fn likely_duplicitous_event(
+    fn get_nt_receipts_for_event(
         &self,
         pref: &IdentifierPrefix,
-        sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.likely_duplicitous_events,
-            &Vec::from(SequenceIndex(pref, sn)),
-            dig,
-        )
+    ) -> Result<Option<Vec<(BasicPrefix, SelfSigningPrefix)>>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        let receipts = self
+            .receipts_nt
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+            .map(|entry| deserialize_blob(entry?.1))
+            .collect::<Result<Vec<(BasicPrefix, SelfSigningPrefix)>, StoreError>>()?;
+
+        Ok(if receipts.is_empty() {
+            None
+        } else {
+            Some(receipts)
+        })
     }
 
-    fn duplicitous_event(
+    fn get_t_receipts_for_event(
         &self,
         pref: &IdentifierPrefix,
-        sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.duplicitous_events,
-            &Vec::from(SequenceIndex(pref, sn)),
-            dig,
-        )
+    ) -> Result<Option<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>>, Self::Error> {
+        let lock = self.env.read()?;
+        let reader = lock.read()?;
+
+        let receipts = self
+            .receipts_t
+            .get(&reader, Vec::from(ContentIndex(pref, dig)))?
+            .map(|entry| deserialize_blob(entry?.1))
+            .collect::<Result<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>, StoreError>>()?;
+
+        Ok(if receipts.is_empty() {
+            None
+        } else {
+            Some(receipts)
+        })
     }
 
-    fn add_nt_receipt_for_event(
+    fn get_sequence_entries(
         &self,
-        pref: &IdentifierPrefix,
-        dig: &SelfAddressingPrefix,
-        signer: &BasicPrefix,
-        sig: &SelfSigningPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.receipts_nt,
-            &Vec::from(ContentIndex(pref, dig)),
-            &(signer, sig),
-        )
+        table: SequenceTable,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<(IdentifierPrefix, u64, SelfAddressingPrefix)>, Self::Error> {
+        Ok(self
+            .scan(self.sequence_table(table), &prefix_start(pref))?
+            .into_iter()
+            .filter_map(|(key, dig)| SequenceIndex::parse(&key).map(|(p, sn)| (p, sn, dig)))
+            .collect())
     }
 
-    fn add_t_receipt_for_event(
+    fn get_escrowed_nt_receipts(
         &self,
-        pref: &IdentifierPrefix,
-        dig: &SelfAddressingPrefix,
-        signer: &IdentifierPrefix,
-        sig: &AttachedSignaturePrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.receipts_t,
-            &Vec::from(ContentIndex(pref, dig)),
-            &(signer, sig),
-        )
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedNtReceipt>, Self::Error> {
+        Ok(self
+            .scan(&self.escrowed_receipts_nt, &prefix_start(pref))?
+            .into_iter()
+            .map(|(_, receipt)| receipt)
+            .collect())
     }
 
-    fn escrow_nt_receipt(
+    fn get_escrowed_t_receipts(
         &self,
-        pref: &IdentifierPrefix,
-        dig: &SelfAddressingPrefix,
-        signer: &BasicPrefix,
-        sig: &SelfSigningPrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.escrowed_receipts_nt,
-            &Vec::from(ContentIndex(pref, dig)),
-            &(signer, sig),
-        )
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedTReceipt>, Self::Error> {
+        Ok(self
+            .scan(&self.escrowed_receipts_t, &prefix_start(pref))?
+            .into_iter()
+            .map(|(_, receipt)| receipt)
+            .collect())
     }
 
-    fn escrow_t_receipt(
-        &self,
-        pref: &IdentifierPrefix,
-        dig: &SelfAddressingPrefix,
-        signer: &IdentifierPrefix,
-        sig: &AttachedSignaturePrefix,
-    ) -> Result<(), Self::Error> {
-        self.write_ref_multi(
-            &self.escrowed_receipts_nt,
-            &Vec::from(ContentIndex(pref, dig)),
-            &(signer, sig),
-        )
+    fn commit(&self, batch: WriteBatch) -> Result<(), Self::Error> {
+        let lock = self.env.read()?;
+        let mut writer = lock.write()?;
+        for op in batch {
+            // dropping the writer without committing aborts the transaction
+            self.apply(&mut writer, op)?;
+        }
+        writer.commit()
     }
 }
 
@@ -308,3 +838,212 @@ fn basic() -> Result<(), StoreError> {
 
     test_db(db)
 }
+
+#[test]
+fn migrate_legacy_layout() -> Result<(), StoreError> {
+    use crate::derivation::{self_addressing::SelfAddressing, self_signing::SelfSigning};
+    use std::fs;
+    use tempfile::Builder;
+
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+    fs::create_dir_all(root.path()).unwrap();
+
+    let icp = br#"{"vs":"KERI10JSON000159_","pre":"ECui-E44CqN2U7uffCikRCp_YKLkPrA4jsTZ_A0XRLzc","sn":"0","ilk":"icp","sith":"2","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA","DVcuJOOJF1IE8svqEtrSuyQjGTd2HhfAkt9y2QkUtFJI","DT1iAhBWCkvChxNWsby2J0pJyxBIxbAtbLA0Ljx-Grh8"],"nxt":"Evhf3437ZRRnVhT0zOxo_rBX_GxpGoAnLuzrVlDK8ZdM","toad":"0","wits":[],"cnfg":[]}"#;
+    let ixn = br#"{"vs":"KERI10JSON0000a3_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"2","ilk":"ixn","dig":"EkH8Pm-Fv6QDawC4rDulf6X9anQ_AETbNdUh4HCjB0Co","data":[]}"#;
+    let icp_pref: IdentifierPrefix = "ECui-E44CqN2U7uffCikRCp_YKLkPrA4jsTZ_A0XRLzc"
+        .parse()
+        .unwrap();
+    let ixn_pref: IdentifierPrefix = "EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc"
+        .parse()
+        .unwrap();
+    let icp_dig = SelfAddressing::Blake3_256.derive(icp);
+    let ixn_dig = SelfAddressing::Blake3_256.derive(ixn);
+
+    // a validator, which receipted its own inception
+    let validator_icp = br#"{"vs":"KERI10JSON0000fb_","pre":"EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI","sn":"0","ilk":"icp","sith":"1","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"],"nxt":"EPYuj8mq_PYYsoBKkzX1kxSPGYBWaIya3slgCOyOtlqU","toad":"0","wits":[],"cnfg":[]}"#;
+    let validator: IdentifierPrefix = "EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI"
+        .parse()
+        .unwrap();
+    let validator_dig = SelfAddressing::Blake3_256.derive(validator_icp);
+    let validator_sig: AttachedSignaturePrefix =
+        "AApYcYd1cppVg7Inh2YCslWKhUwh59TrPpIoqWxN2A38NCbTljvmBPBjSGIFDBNOvVjHpdZlty3Hgk6ilF8pVpAQ"
+            .parse()
+            .unwrap();
+    let witness: BasicPrefix = "BSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"
+        .parse()
+        .unwrap();
+    let witness_sig = SelfSigning::Ed25519Sha512.derive(vec![0; 64]);
+    let unknown_dig = SelfAddressing::Blake3_256.derive(b"unknown");
+
+    // write finalised and out of order events, and receipts escrowed
+    // for them and for an unknown event, the way the original layout did
+    {
+        let mut m = Manager::<SafeModeEnvironment>::singleton().write()?;
+        let created_arc =
+            m.get_or_create_with_capacity(root.path(), MAX_STORES, Rkv::with_capacity::<SafeMode>)?;
+        let env = created_arc.read()?;
+        let events = env.open_single("evts", StoreOptions::create())?;
+        let kels = env.open_multi("kels", StoreOptions::create())?;
+        let ooes = env.open_multi("ooes", StoreOptions::create())?;
+        let ures = env.open_multi("ures", StoreOptions::create())?;
+
+        let mut writer = env.write()?;
+        events.put(
+            &mut writer,
+            Vec::from(ContentIndex(&icp_pref, &icp_dig)),
+            &Value::Blob(icp),
+        )?;
+        events.put(
+            &mut writer,
+            Vec::from(ContentIndex(&ixn_pref, &ixn_dig)),
+            &Value::Blob(ixn),
+        )?;
+        kels.put(
+            &mut writer,
+            Vec::from(SequenceIndex(&icp_pref, 0)),
+            &Value::Blob(&serialize_blob(&icp_dig)),
+        )?;
+        ooes.put(
+            &mut writer,
+            Vec::from(SequenceIndex(&ixn_pref, 2)),
+            &Value::Blob(&serialize_blob(&ixn_dig)),
+        )?;
+        events.put(
+            &mut writer,
+            Vec::from(ContentIndex(&validator, &validator_dig)),
+            &Value::Blob(validator_icp),
+        )?;
+        kels.put(
+            &mut writer,
+            Vec::from(SequenceIndex(&validator, 0)),
+            &Value::Blob(&serialize_blob(&validator_dig)),
+        )?;
+        ures.put(
+            &mut writer,
+            Vec::from(ContentIndex(&ixn_pref, &ixn_dig)),
+            &Value::Blob(&serialize_blob(&(&witness, &witness_sig))),
+        )?;
+        ures.put(
+            &mut writer,
+            Vec::from(ContentIndex(&validator, &validator_dig)),
+            &Value::Blob(&serialize_blob(&(&validator, &validator_sig))),
+        )?;
+        ures.put(
+            &mut writer,
+            Vec::from(ContentIndex(&ixn_pref, &unknown_dig)),
+            &Value::Blob(&serialize_blob(&(&witness, &witness_sig))),
+        )?;
+        writer.commit()?;
+    }
+
+    // opening it again after the migration finds nothing left to do
+    for _ in 0..2 {
+        let db = LmdbEventDatabase::new(root.path())?;
+
+        assert_eq!(db.last_event_at_sn(&icp_pref, 0)?, Some(icp.to_vec()));
+        assert_eq!(
+            db.get_sequence_entries(SequenceTable::KeyEventLog, Some(&icp_pref))?,
+            vec![(icp_pref.clone(), 0, icp_dig.clone())]
+        );
+        assert_eq!(
+            db.get_sequence_entries(SequenceTable::OutOfOrder, None)?,
+            vec![(ixn_pref.clone(), 2, ixn_dig.clone())]
+        );
+        assert_eq!(
+            db.get_escrowed_nt_receipts(None)?,
+            vec![EscrowedNtReceipt {
+                prefix: ixn_pref.clone(),
+                sn: 2,
+                digest: ixn_dig.clone(),
+                signer: witness.clone(),
+                signature: witness_sig.clone(),
+            }]
+        );
+        assert_eq!(
+            db.get_escrowed_t_receipts(None)?,
+            vec![EscrowedTReceipt {
+                prefix: validator.clone(),
+                sn: 0,
+                digest: validator_dig.clone(),
+                validator_seal: EventSeal {
+                    prefix: validator.clone(),
+                    event_digest: validator_dig.clone(),
+                },
+                signature: validator_sig.clone(),
+            }]
+        );
+    }
+
+    // the migrated entries are removed, the one for an unknown event is kept
+    let lock = Manager::<SafeModeEnvironment>::singleton()
+        .write()?
+        .get_or_create_with_capacity(root.path(), MAX_STORES, Rkv::with_capacity::<SafeMode>)?;
+    let env = lock.read()?;
+    let kels = env.open_multi("kels", StoreOptions::create())?;
+    let ures = env.open_multi("ures", StoreOptions::create())?;
+    let reader = env.read()?;
+    assert!(kels
+        .get(&reader, Vec::from(SequenceIndex(&icp_pref, 0)))?
+        .next()
+        .is_none());
+    assert!(ures
+        .get(&reader, Vec::from(ContentIndex(&ixn_pref, &ixn_dig)))?
+        .next()
+        .is_none());
+    assert!(ures
+        .get(&reader, Vec::from(ContentIndex(&ixn_pref, &unknown_dig)))?
+        .next()
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn migrate_unverifiable_legacy_receipt() -> Result<(), StoreError> {
+    use crate::derivation::self_addressing::SelfAddressing;
+    use std::fs;
+    use tempfile::Builder;
+
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+    fs::create_dir_all(root.path()).unwrap();
+
+    let icp = br#"{"vs":"KERI10JSON0000fb_","pre":"EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI","sn":"0","ilk":"icp","sith":"1","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"],"nxt":"EPYuj8mq_PYYsoBKkzX1kxSPGYBWaIya3slgCOyOtlqU","toad":"0","wits":[],"cnfg":[]}"#;
+    let pref: IdentifierPrefix = "EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI"
+        .parse()
+        .unwrap();
+    let dig = SelfAddressing::Blake3_256.derive(icp);
+    // a validator without a KEL in this database
+    let validator: IdentifierPrefix = "EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc"
+        .parse()
+        .unwrap();
+    let sig: AttachedSignaturePrefix =
+        "AApYcYd1cppVg7Inh2YCslWKhUwh59TrPpIoqWxN2A38NCbTljvmBPBjSGIFDBNOvVjHpdZlty3Hgk6ilF8pVpAQ"
+            .parse()
+            .unwrap();
+    let key = Vec::from(ContentIndex(&pref, &dig));
+
+    let lock = Manager::<SafeModeEnvironment>::singleton()
+        .write()?
+        .get_or_create_with_capacity(root.path(), MAX_STORES, Rkv::with_capacity::<SafeMode>)?;
+    {
+        let env = lock.read()?;
+        let events = env.open_single("evts", StoreOptions::create())?;
+        let ures = env.open_multi("ures", StoreOptions::create())?;
+        let mut writer = env.write()?;
+        events.put(&mut writer, &key, &Value::Blob(icp))?;
+        ures.put(
+            &mut writer,
+            &key,
+            &Value::Blob(&serialize_blob(&(&validator, &sig))),
+        )?;
+        writer.commit()?;
+    }
+
+    // the receipt can't be migrated, so opening fails and leaves it be
+    assert!(LmdbEventDatabase::new(root.path()).is_err());
+    let env = lock.read()?;
+    let ures = env.open_multi("ures", StoreOptions::create())?;
+    assert!(ures.get(&env.read()?, &key)?.next().is_some());
+
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use super::{
+    ContentIndex, EscrowedNtReceipt, EscrowedTReceipt, EventDatabase, SequenceIndex, SequenceTable,
+    WriteBatch, WriteOp,
+};
+use crate::{
+    error::Error,
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
+    state::IdentifierState,
+};
+use chrono::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+type Table<T> = HashMap<Vec<u8>, T>;
+// ordered by key like an lmdb store, so it can be range scanned
+type MultiTable<T> = BTreeMap<Vec<u8>, Vec<T>>;
+
+/// Memory Event Database
+///
+/// An `EventDatabase` which keeps every table in memory. Nothing
+/// is persisted, so it is suitable for tests, wasm targets and
+/// ephemeral nodes.
+#[derive(Default)]
+pub struct MemoryEventDatabase {
+    tables: RwLock<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    events: Table<Vec<u8>>,
+    digests: Table<SelfAddressingPrefix>,
+    datetime_stamps: Table<String>,
+    signatures: MultiTable<AttachedSignaturePrefix>,
+    receipts_nt: MultiTable<(BasicPrefix, SelfSigningPrefix)>,
+    escrowed_receipts_nt: Vec<EscrowedNtReceipt>,
+    receipts_t: MultiTable<(IdentifierPrefix, AttachedSignaturePrefix)>,
+    escrowed_receipts_t: Vec<EscrowedTReceipt>,
+    key_event_logs: MultiTable<SelfAddressingPrefix>,
+    partially_signed_events: MultiTable<SelfAddressingPrefix>,
+    partially_witnessed_events: MultiTable<SelfAddressingPrefix>,
+    out_of_order_events: MultiTable<SelfAddressingPrefix>,
+    likely_duplicitous_events: MultiTable<SelfAddressingPrefix>,
+    duplicitous_events: MultiTable<SelfAddressingPrefix>,
+    superseded_events: MultiTable<SelfAddressingPrefix>,
+    delegates: MultiTable<IdentifierPrefix>,
+    delegators: Table<IdentifierPrefix>,
+    states: Table<(IdentifierState, SelfAddressingPrefix)>,
+}
+
+// multi tables hold each value only once per key, like a DUPSORT lmdb store
+fn put_multi<T: PartialEq>(table: &mut MultiTable<T>, key: Vec<u8>, data: T) {
+    let values = table.entry(key).or_default();
+    if !values.contains(&data) {
+        values.push(data);
+    }
+}
+
+impl Tables {
+    fn sequence_table(&self, table: SequenceTable) -> &MultiTable<SelfAddressingPrefix> {
+        match table {
+            SequenceTable::KeyEventLog => &self.key_event_logs,
+            SequenceTable::PartiallySigned => &self.partially_signed_events,
+            SequenceTable::PartiallyWitnessed => &self.partially_witnessed_events,
+            SequenceTable::OutOfOrder => &self.out_of_order_events,
+            SequenceTable::LikelyDuplicitous => &self.likely_duplicitous_events,
+            SequenceTable::Duplicitous => &self.duplicitous_events,
+            SequenceTable::Superseded => &self.superseded_events,
+        }
+    }
+
+    fn sequence_table_mut(
+        &mut self,
+        table: SequenceTable,
+    ) -> &mut MultiTable<SelfAddressingPrefix> {
+        match table {
+            SequenceTable::KeyEventLog => &mut self.key_event_logs,
+            SequenceTable::PartiallySigned => &mut self.partially_signed_events,
+            SequenceTable::PartiallyWitnessed => &mut self.partially_witnessed_events,
+            SequenceTable::OutOfOrder => &mut self.out_of_order_events,
+            SequenceTable::LikelyDuplicitous => &mut self.likely_duplicitous_events,
+            SequenceTable::Duplicitous => &mut self.duplicitous_events,
+            SequenceTable::Superseded => &mut self.superseded_events,
+        }
+    }
+
+    fn apply(&mut self, op: WriteOp) {
+        match op {
+            WriteOp::LogEvent {
+                pref,
+                dig,
+                aliases,
+                raw,
+                sigs,
+            } => {
+                let key: Vec<u8> = ContentIndex(&pref, &dig).into();
+
+                // insert timestamp for event, unless it was seen before
+                self.datetime_stamps
+                    .entry(key.clone())
+                    .or_insert_with(|| Utc::now().to_rfc3339());
+
+                // index the event under its aliases, unless they were taken before
+                for alias in aliases {
+                    self.digests
+                        .entry(ContentIndex(&pref, &alias).into())
+                        .or_insert_with(|| dig.clone());
+                }
+
+                // insert signatures for event
+                for sig in sigs {
+                    put_multi(&mut self.signatures, key.clone(), sig);
+                }
+
+                // insert event itself
+                self.events.insert(key, raw);
+            }
+            WriteOp::Insert {
+                table,
+                pref,
+                sn,
+                dig,
+            } => put_multi(
+                self.sequence_table_mut(table),
+                SequenceIndex(&pref, sn).into(),
+                dig,
+            ),
+            WriteOp::Remove {
+                table,
+                pref,
+                sn,
+                dig,
+            } => {
+                let table = self.sequence_table_mut(table);
+                let key: Vec<u8> = SequenceIndex(&pref, sn).into();
+                if let Some(digs) = table.get_mut(&key) {
+                    digs.retain(|d| d != &dig);
+                    if digs.is_empty() {
+                        table.remove(&key);
+                    }
+                }
+            }
+            WriteOp::NtReceipt {
+                pref,
+                dig,
+                signer,
+                sig,
+            } => put_multi(
+                &mut self.receipts_nt,
+                ContentIndex(&pref, &dig).into(),
+                (signer, sig),
+            ),
+            WriteOp::TReceipt {
+                pref,
+                dig,
+                signer,
+                sig,
+            } => put_multi(
+                &mut self.receipts_t,
+                ContentIndex(&pref, &dig).into(),
+                (signer, sig),
+            ),
+            WriteOp::EscrowNtReceipt(receipt) => {
+                if !self.escrowed_receipts_nt.contains(&receipt) {
+                    self.escrowed_receipts_nt.push(receipt);
+                }
+            }
+            WriteOp::RemoveNtReceipt(receipt) => {
+                self.escrowed_receipts_nt.retain(|r| r != &receipt);
+            }
+            WriteOp::EscrowTReceipt(receipt) => {
+                if !self.escrowed_receipts_t.contains(&receipt) {
+                    self.escrowed_receipts_t.push(receipt);
+                }
+            }
+            WriteOp::RemoveTReceipt(receipt) => {
+                self.escrowed_receipts_t.retain(|r| r != &receipt);
+            }
+            WriteOp::LogDelegation {
+                delegator,
+                delegate,
+            } => {
+                put_multi(
+                    &mut self.delegates,
+                    delegator.to_str().into_bytes(),
+                    delegate.clone(),
+                );
+                // a delegate keeps the delegator it was incepted with
+                self.delegators
+                    .entry(delegate.to_str().into_bytes())
+                    .or_insert(delegator);
+            }
+            WriteOp::UpdateState { state, dig } => {
+                self.states
+                    .insert(state.prefix.to_str().into_bytes(), (state, dig));
+            }
+        }
+    }
+}
+
+impl MemoryEventDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Tables>, Error> {
+        self.tables.read().map_err(|_| Error::StorageError)
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Tables>, Error> {
+        self.tables.write().map_err(|_| Error::StorageError)
+    }
+}
+
+impl EventDatabase for MemoryEventDatabase {
+    type Error = Error;
+
+    fn last_event_at_sn(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        let tables = self.read()?;
+
+        Ok(tables
+            .key_event_logs
+            .get(&Vec::from(SequenceIndex(pref, sn)))
+            .and_then(|digs| digs.last())
+            .and_then(|dig| tables.events.get(&Vec::from(ContentIndex(pref, dig))))
+            .cloned())
+    }
+
+    fn get_cached_state(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<(IdentifierState, SelfAddressingPrefix)>, Self::Error> {
+        Ok(self.read()?.states.get(pref.to_str().as_bytes()).cloned())
+    }
+
+    fn get_children_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<Vec<IdentifierPrefix>>, Self::Error> {
+        Ok(self
+            .read()?
+            .delegates
+            .get(pref.to_str().as_bytes())
+            .cloned())
+    }
+
+    fn get_parent_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierPrefix>, Self::Error> {
+        Ok(self
+            .read()?
+            .delegators
+            .get(pref.to_str().as_bytes())
+            .cloned())
+    }
+
+    fn get_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self
+            .read()?
+            .events
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn resolve_digest(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<SelfAddressingPrefix>, Self::Error> {
+        Ok(self
+            .read()?
+            .digests
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn get_signatures_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<AttachedSignaturePrefix>>, Self::Error> {
+        Ok(self
+            .read()?
+            .signatures
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn get_event_timestamp(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<String>, Self::Error> {
+        Ok(self
+            .read()?
+            .datetime_stamps
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn get_nt_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(BasicPrefix, SelfSigningPrefix)>>, Self::Error> {
+        Ok(self
+            .read()?
+            .receipts_nt
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn get_t_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>>, Self::Error> {
+        Ok(self
+            .read()?
+            .receipts_t
+            .get(&Vec::from(ContentIndex(pref, dig)))
+            .cloned())
+    }
+
+    fn get_sequence_entries(
+        &self,
+        table: SequenceTable,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<(IdentifierPrefix, u64, SelfAddressingPrefix)>, Self::Error> {
+        let tables = self.read()?;
+        let start = pref.map_or_else(Vec::new, |p| format!("{}.", p.to_str()).into_bytes());
+
+        Ok(tables
+            .sequence_table(table)
+            .range(start.clone()..)
+            .take_while(|(key, _)| key.starts_with(&start))
+            .filter_map(|(key, digs)| SequenceIndex::parse(key).map(|(p, sn)| (p, sn, digs)))
+            .flat_map(|(p, sn, digs)| digs.iter().map(move |dig| (p.clone(), sn, dig.clone())))
+            .collect())
+    }
+
+    fn get_escrowed_nt_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedNtReceipt>, Self::Error> {
+        Ok(self
+            .read()?
+            .escrowed_receipts_nt
+            .iter()
+            .filter(|r| pref.map_or(true, |p| &r.prefix == p))
+            .cloned()
+            .collect())
+    }
+
+    fn get_escrowed_t_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedTReceipt>, Self::Error> {
+        Ok(self
+            .read()?
+            .escrowed_receipts_t
+            .iter()
+            .filter(|r| pref.map_or(true, |p| &r.prefix == p))
+            .cloned()
+            .collect())
+    }
+
+    fn commit(&self, batch: WriteBatch) -> Result<(), Self::Error> {
+        // every op is infallible, so holding the lock is enough for atomicity
+        let mut tables = self.write()?;
+        for op in batch {
+            tables.apply(op);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn basic() -> Result<(), Error> {
+    use super::test_db;
+
+    test_db(MemoryEventDatabase::new())
+}
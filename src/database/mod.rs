@@ -1,13 +1,274 @@
 use crate::{
+    derivation::self_addressing::SelfAddressing,
+    event::sections::seal::EventSeal,
     event_message::parse::message,
     prefix::{
-        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, SelfAddressingPrefix,
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfAddressingPrefix,
         SelfSigningPrefix,
     },
     state::IdentifierState,
 };
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "lmdb")]
 pub mod lmdb;
+pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub struct ContentIndex<'a>(&'a IdentifierPrefix, &'a SelfAddressingPrefix);
+pub struct SequenceIndex<'a>(&'a IdentifierPrefix, u64);
+
+// useful for using as an index type, but expensive
+// TODO: investigate using AsRef<[u8]>
+impl From<ContentIndex<'_>> for Vec<u8> {
+    fn from(ci: ContentIndex) -> Self {
+        [ci.0.to_str(), ".".into(), ci.1.to_str()]
+            .concat()
+            .into_bytes()
+    }
+}
+
+impl From<SequenceIndex<'_>> for Vec<u8> {
+    fn from(si: SequenceIndex) -> Self {
+        format!("{}.{:032}", si.0.to_str(), si.1).into_bytes()
+    }
+}
+
+impl SequenceIndex<'_> {
+    /// Parse
+    ///
+    /// Recovers the prefix and sn from a key which starts
+    /// with a SequenceIndex
+    pub(crate) fn parse(key: &[u8]) -> Option<(IdentifierPrefix, u64)> {
+        let key = std::str::from_utf8(key).ok()?;
+        let mut parts = key.split('.');
+        let pref = parts.next()?.parse().ok()?;
+        let sn = parts.next()?.parse().ok()?;
+        Some((pref, sn))
+    }
+}
+
+/// Escrowed Event
+///
+/// An event held in one of the event escrows, together
+/// with the signatures it was received with
+#[derive(Debug, Clone, PartialEq)]
+pub struct EscrowedEvent {
+    pub prefix: IdentifierPrefix,
+    pub sn: u64,
+    pub digest: SelfAddressingPrefix,
+    pub raw: Vec<u8>,
+    pub signatures: Vec<AttachedSignaturePrefix>,
+}
+
+/// Escrowed Non-Transferrable Receipt
+///
+/// A witness receipt for an event which is not yet known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EscrowedNtReceipt {
+    pub prefix: IdentifierPrefix,
+    pub sn: u64,
+    pub digest: SelfAddressingPrefix,
+    pub signer: BasicPrefix,
+    pub signature: SelfSigningPrefix,
+}
+
+/// Escrowed Transferrable Receipt
+///
+/// A validator receipt for an event which is not yet known,
+/// the seal locates the validator's establishment event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EscrowedTReceipt {
+    pub prefix: IdentifierPrefix,
+    pub sn: u64,
+    pub digest: SelfAddressingPrefix,
+    pub validator_seal: EventSeal,
+    pub signature: AttachedSignaturePrefix,
+}
+
+/// Sequence Table
+///
+/// The tables which index event digests by prefix and sn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceTable {
+    KeyEventLog,
+    PartiallySigned,
+    PartiallyWitnessed,
+    OutOfOrder,
+    LikelyDuplicitous,
+    Duplicitous,
+    Superseded,
+}
+
+/// Indexed Digests
+///
+/// The digest algorithms a logged event can be looked up by,
+/// whichever one it is stored under, when every algorithm is
+/// allowed. Blake2b-256 and Blake2s-256 are left out until they
+/// are implemented
+pub const INDEXED_DIGESTS: [SelfAddressing; 7] = [
+    SelfAddressing::Blake3_256,
+    SelfAddressing::SHA3_256,
+    SelfAddressing::SHA2_256,
+    SelfAddressing::Blake3_512,
+    SelfAddressing::SHA3_512,
+    SelfAddressing::Blake2B512,
+    SelfAddressing::SHA2_512,
+];
+
+/// Write Operation
+///
+/// A single change to the database, to be committed
+/// as part of a `WriteBatch`
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    /// Logs an event stored under `dig`, which it can also be
+    /// looked up by with any of the `aliases`
+    LogEvent {
+        pref: IdentifierPrefix,
+        dig: SelfAddressingPrefix,
+        aliases: Vec<SelfAddressingPrefix>,
+        raw: Vec<u8>,
+        sigs: Vec<AttachedSignaturePrefix>,
+    },
+    Insert {
+        table: SequenceTable,
+        pref: IdentifierPrefix,
+        sn: u64,
+        dig: SelfAddressingPrefix,
+    },
+    Remove {
+        table: SequenceTable,
+        pref: IdentifierPrefix,
+        sn: u64,
+        dig: SelfAddressingPrefix,
+    },
+    NtReceipt {
+        pref: IdentifierPrefix,
+        dig: SelfAddressingPrefix,
+        signer: BasicPrefix,
+        sig: SelfSigningPrefix,
+    },
+    TReceipt {
+        pref: IdentifierPrefix,
+        dig: SelfAddressingPrefix,
+        signer: IdentifierPrefix,
+        sig: AttachedSignaturePrefix,
+    },
+    EscrowNtReceipt(EscrowedNtReceipt),
+    RemoveNtReceipt(EscrowedNtReceipt),
+    EscrowTReceipt(EscrowedTReceipt),
+    RemoveTReceipt(EscrowedTReceipt),
+    LogDelegation {
+        delegator: IdentifierPrefix,
+        delegate: IdentifierPrefix,
+    },
+    /// Replaces the cached current state of the state's prefix,
+    /// computed up to the event with the given storage digest
+    UpdateState {
+        state: IdentifierState,
+        dig: SelfAddressingPrefix,
+    },
+}
+
+impl WriteOp {
+    /// Log Event
+    ///
+    /// Logs an event to be looked up by its storage digest and
+    /// by its digest with each of the given algorithms
+    pub fn log_event(
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+        raw: &[u8],
+        sigs: &[AttachedSignaturePrefix],
+        codes: &[SelfAddressing],
+    ) -> Self {
+        let mut aliases = vec![dig.clone()];
+        for code in codes {
+            if *code != dig.derivation {
+                aliases.push(code.derive(raw));
+            }
+        }
+        Self::LogEvent {
+            pref: pref.clone(),
+            dig: dig.clone(),
+            aliases,
+            raw: raw.to_vec(),
+            sigs: sigs.to_vec(),
+        }
+    }
+
+    pub fn insert(
+        table: SequenceTable,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+    ) -> Self {
+        Self::Insert {
+            table,
+            pref: pref.clone(),
+            sn,
+            dig: dig.clone(),
+        }
+    }
+
+    pub fn remove(
+        table: SequenceTable,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+    ) -> Self {
+        Self::Remove {
+            table,
+            pref: pref.clone(),
+            sn,
+            dig: dig.clone(),
+        }
+    }
+}
+
+/// Write Batch
+///
+/// An ordered set of operations which an `EventDatabase`
+/// commits in a single transaction
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<WriteOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, op: WriteOp) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, WriteOp> {
+        self.ops.iter()
+    }
+}
+
+impl From<WriteOp> for WriteBatch {
+    fn from(op: WriteOp) -> Self {
+        Self { ops: vec![op] }
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = WriteOp;
+    type IntoIter = std::vec::IntoIter<WriteOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
 
 /// Event Database
 ///
@@ -71,16 +332,25 @@ pub trait EventDatabase {
         Ok(Some(state))
     }
 
+    /// Get Cached State
+    ///
+    /// Returns the state cached for the given Prefix when
+    /// its latest event was finalised, with the storage digest
+    /// of that event. The raw last event is not cached, it is
+    /// read from the KEL
+    fn get_cached_state(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<(IdentifierState, SelfAddressingPrefix)>, Self::Error>;
+
     /// Get Children of Prefix
     ///
     /// Returns the Identifiers delegated to by the
-    /// given Prefix
+    /// given Prefix, in no particular order
     fn get_children_of_prefix(
         &self,
-        _pref: &IdentifierPrefix,
-    ) -> Result<Option<Vec<IdentifierPrefix>>, Self::Error> {
-        todo!()
-    }
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<Vec<IdentifierPrefix>>, Self::Error>;
 
     /// Get Parent of Prefix
     ///
@@ -88,21 +358,47 @@ pub trait EventDatabase {
     /// if there is one
     fn get_parent_of_prefix(
         &self,
-        _pref: &IdentifierPrefix,
-    ) -> Result<Option<IdentifierPrefix>, Self::Error> {
-        todo!()
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierPrefix>, Self::Error>;
+
+    /// Commit
+    ///
+    /// Applies every operation in the batch atomically, either
+    /// all of them are persisted or none are
+    fn commit(&self, batch: WriteBatch) -> Result<(), Self::Error>;
+
+    /// Log Delegation
+    ///
+    /// Records that Delegator has delegated to Delegate, used
+    /// when a delegated inception event is finalised
+    fn log_delegation(
+        &self,
+        delegator: &IdentifierPrefix,
+        delegate: &IdentifierPrefix,
+    ) -> Result<(), Self::Error> {
+        self.commit(
+            WriteOp::LogDelegation {
+                delegator: delegator.clone(),
+                delegate: delegate.clone(),
+            }
+            .into(),
+        )
     }
 
     /// Log Event
     ///
-    /// Adds the raw event data to the database and a timestamp
+    /// Adds the raw event data to the database and a timestamp,
+    /// the timestamp of an event logged again is kept. The event
+    /// is only indexed by its storage digest
     fn log_event(
         &self,
         prefix: &IdentifierPrefix,
         dig: &SelfAddressingPrefix,
         raw: &[u8],
         sigs: &[AttachedSignaturePrefix],
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::log_event(prefix, dig, raw, sigs, &[]).into())
+    }
 
     /// Finalise Event
     ///
@@ -112,7 +408,9 @@ pub trait EventDatabase {
         prefix: &IdentifierPrefix,
         sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::KeyEventLog, prefix, sn, dig).into())
+    }
 
     /// Escrow Partially Signed Event
     ///
@@ -122,7 +420,22 @@ pub trait EventDatabase {
         pref: &IdentifierPrefix,
         sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::PartiallySigned, pref, sn, dig).into())
+    }
+
+    /// Escrow Partially Witnessed Event
+    ///
+    /// Escrows an Event which does not yet have receipts
+    /// from enough of its witnesses
+    fn escrow_partially_witnessed_event(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::PartiallyWitnessed, pref, sn, dig).into())
+    }
 
     /// Escrow Out of Order Event
     ///
@@ -132,7 +445,9 @@ pub trait EventDatabase {
         pref: &IdentifierPrefix,
         sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::OutOfOrder, pref, sn, dig).into())
+    }
 
     /// Likely Duplicitous Event
     ///
@@ -142,7 +457,9 @@ pub trait EventDatabase {
         pref: &IdentifierPrefix,
         sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::LikelyDuplicitous, pref, sn, dig).into())
+    }
 
     /// Duplicitous Event
     ///
@@ -152,7 +469,9 @@ pub trait EventDatabase {
         pref: &IdentifierPrefix,
         sn: u64,
         dig: &SelfAddressingPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::insert(SequenceTable::Duplicitous, pref, sn, dig).into())
+    }
 
     /// Add Non-Transferrable Receipt
     ///
@@ -163,7 +482,17 @@ pub trait EventDatabase {
         dig: &SelfAddressingPrefix,
         signer: &BasicPrefix,
         sig: &SelfSigningPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(
+            WriteOp::NtReceipt {
+                pref: pref.clone(),
+                dig: dig.clone(),
+                signer: signer.clone(),
+                sig: sig.clone(),
+            }
+            .into(),
+        )
+    }
 
     /// Add Transferrable Receipt
     ///
@@ -174,25 +503,220 @@ pub trait EventDatabase {
         dig: &SelfAddressingPrefix,
         signer: &IdentifierPrefix,
         sig: &AttachedSignaturePrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(
+            WriteOp::TReceipt {
+                pref: pref.clone(),
+                dig: dig.clone(),
+                signer: signer.clone(),
+                sig: sig.clone(),
+            }
+            .into(),
+        )
+    }
 
     /// Escrow Non-Transferrable Receipt
+    ///
+    /// Holds a witness receipt for the event at Sn with digest Dig
+    /// until that event is known
     fn escrow_nt_receipt(
         &self,
         pref: &IdentifierPrefix,
+        sn: u64,
         dig: &SelfAddressingPrefix,
         signer: &BasicPrefix,
         sig: &SelfSigningPrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(
+            WriteOp::EscrowNtReceipt(EscrowedNtReceipt {
+                prefix: pref.clone(),
+                sn,
+                digest: dig.clone(),
+                signer: signer.clone(),
+                signature: sig.clone(),
+            })
+            .into(),
+        )
+    }
 
     /// Escrow Transferrable Receipt
+    ///
+    /// Holds a validator receipt for the event at Sn with digest Dig
+    /// until that event is known
     fn escrow_t_receipt(
         &self,
         pref: &IdentifierPrefix,
+        sn: u64,
         dig: &SelfAddressingPrefix,
-        signer: &IdentifierPrefix,
+        validator_seal: &EventSeal,
         sig: &AttachedSignaturePrefix,
-    ) -> Result<(), Self::Error>;
+    ) -> Result<(), Self::Error> {
+        self.commit(
+            WriteOp::EscrowTReceipt(EscrowedTReceipt {
+                prefix: pref.clone(),
+                sn,
+                digest: dig.clone(),
+                validator_seal: validator_seal.clone(),
+                signature: sig.clone(),
+            })
+            .into(),
+        )
+    }
+
+    /// Get Event
+    ///
+    /// Returns the raw bytes of a logged event, whether it
+    /// was finalised or not
+    fn get_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Resolve Digest
+    ///
+    /// Returns the digest a logged event is stored under,
+    /// given its digest with any of the indexed algorithms
+    fn resolve_digest(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<SelfAddressingPrefix>, Self::Error>;
+
+    /// Get Signatures for Event
+    ///
+    /// Returns every signature logged for the event
+    fn get_signatures_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<AttachedSignaturePrefix>>, Self::Error>;
+
+    /// Get Event Timestamp
+    ///
+    /// Returns the RFC 3339 timestamp of when the event
+    /// was first logged
+    fn get_event_timestamp(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<String>, Self::Error>;
+
+    /// Get Non-Transferrable Receipts for Event
+    ///
+    /// Returns every witness receipt stored for the event
+    fn get_nt_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(BasicPrefix, SelfSigningPrefix)>>, Self::Error>;
+
+    /// Get Transferrable Receipts for Event
+    ///
+    /// Returns every validator receipt stored for the event,
+    /// each paired with the prefix of its validator
+    fn get_t_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>>, Self::Error>;
+
+    /// Get Sequence Entries
+    ///
+    /// Lists the (prefix, sn, digest) entries of a sequence table,
+    /// either for one prefix or for all of them, ordered by prefix and sn
+    fn get_sequence_entries(
+        &self,
+        table: SequenceTable,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<(IdentifierPrefix, u64, SelfAddressingPrefix)>, Self::Error>;
+
+    /// Get Escrowed Events
+    ///
+    /// Lists the events held in the given escrow along with
+    /// their raw bytes and signatures, for one prefix or all
+    fn get_escrowed_events(
+        &self,
+        escrow: SequenceTable,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedEvent>, Self::Error> {
+        let mut events = vec![];
+        for (prefix, sn, digest) in self.get_sequence_entries(escrow, pref)? {
+            // an escrowed event is always logged first, but be defensive
+            let raw = match self.get_event(&prefix, &digest)? {
+                Some(raw) => raw,
+                None => continue,
+            };
+            let signatures = self
+                .get_signatures_for_event(&prefix, &digest)?
+                .unwrap_or_default();
+            events.push(EscrowedEvent {
+                prefix,
+                sn,
+                digest,
+                raw,
+                signatures,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Get Escrowed Events at SN
+    ///
+    /// Lists the events held in the given escrow for
+    /// a prefix at a given sequence number
+    fn get_escrowed_events_at_sn(
+        &self,
+        escrow: SequenceTable,
+        pref: &IdentifierPrefix,
+        sn: u64,
+    ) -> Result<Vec<EscrowedEvent>, Self::Error> {
+        Ok(self
+            .get_escrowed_events(escrow, Some(pref))?
+            .into_iter()
+            .filter(|e| e.sn == sn)
+            .collect())
+    }
+
+    /// Remove Escrowed Event
+    ///
+    /// Takes a resolved event out of the given escrow, the
+    /// logged event itself is kept
+    fn remove_escrowed_event(
+        &self,
+        escrow: SequenceTable,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<(), Self::Error> {
+        self.commit(WriteOp::remove(escrow, pref, sn, dig).into())
+    }
+
+    /// Get Escrowed Non-Transferrable Receipts
+    ///
+    /// Lists escrowed witness receipts, for one prefix or all
+    fn get_escrowed_nt_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedNtReceipt>, Self::Error>;
+
+    /// Get Escrowed Transferrable Receipts
+    ///
+    /// Lists escrowed validator receipts, for one prefix or all
+    fn get_escrowed_t_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedTReceipt>, Self::Error>;
+
+    /// Remove Escrowed Non-Transferrable Receipt
+    fn remove_escrowed_nt_receipt(&self, receipt: &EscrowedNtReceipt) -> Result<(), Self::Error> {
+        self.commit(WriteOp::RemoveNtReceipt(receipt.clone()).into())
+    }
+
+    /// Remove Escrowed Transferrable Receipt
+    fn remove_escrowed_t_receipt(&self, receipt: &EscrowedTReceipt) -> Result<(), Self::Error> {
+        self.commit(WriteOp::RemoveTReceipt(receipt.clone()).into())
+    }
 }
 
 pub(crate) fn test_db<D: EventDatabase>(db: D) -> Result<(), D::Error> {
@@ -220,5 +744,297 @@ pub(crate) fn test_db<D: EventDatabase>(db: D) -> Result<(), D::Error> {
     let written = db.last_event_at_sn(&message.event.prefix, 0)?;
 
     assert_eq!(written, Some(raw.as_bytes().to_vec()));
+
+    // logging an event again keeps when it was first seen
+    let first_seen = db.get_event_timestamp(&message.event.prefix, &dig)?;
+    assert!(first_seen.is_some());
+    db.log_event(&message.event.prefix, &dig, raw.as_bytes(), &sigs)?;
+    assert_eq!(
+        db.get_event_timestamp(&message.event.prefix, &dig)?,
+        first_seen
+    );
+
+    // an empty batch is a no-op
+    db.commit(WriteBatch::new())?;
+
+    // the cached state round trips, except for the raw last event
+    assert_eq!(db.get_cached_state(&message.event.prefix)?, None);
+    let state = IdentifierState {
+        last: vec![],
+        ..IdentifierState::default().apply(&message).unwrap()
+    };
+    db.commit(
+        WriteOp::UpdateState {
+            state: state.clone(),
+            dig: dig.clone(),
+        }
+        .into(),
+    )?;
+    assert_eq!(
+        db.get_cached_state(&message.event.prefix)?,
+        Some((state, dig.clone()))
+    );
+
+    // log, escrow removal and finalisation of an event in one batch
+    let later_raw = &raw.as_bytes()[..raw.len() - "extra data".len()];
+    let later_dig = SelfAddressing::Blake3_256.derive(later_raw);
+    db.escrow_out_of_order_event(&message.event.prefix, 1, &later_dig)?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .push(WriteOp::log_event(
+            &message.event.prefix,
+            &later_dig,
+            later_raw,
+            &sigs,
+            &[SelfAddressing::SHA3_256],
+        ))
+        .push(WriteOp::remove(
+            SequenceTable::OutOfOrder,
+            &message.event.prefix,
+            1,
+            &later_dig,
+        ))
+        .push(WriteOp::insert(
+            SequenceTable::KeyEventLog,
+            &message.event.prefix,
+            1,
+            &later_dig,
+        ));
+    assert!(!batch.is_empty());
+    db.commit(batch)?;
+
+    assert_eq!(
+        db.last_event_at_sn(&message.event.prefix, 1)?,
+        Some(later_raw.to_vec())
+    );
+
+    // the event is indexed by the given algorithms only, and an
+    // alias keeps pointing at the digest it was first logged under
+    let sha3_dig = SelfAddressing::SHA3_256.derive(later_raw);
+    assert_eq!(
+        db.resolve_digest(&message.event.prefix, &sha3_dig)?,
+        Some(later_dig.clone())
+    );
+    assert_eq!(
+        db.resolve_digest(
+            &message.event.prefix,
+            &SelfAddressing::SHA2_256.derive(later_raw)
+        )?,
+        None
+    );
+    db.commit(
+        WriteOp::log_event(
+            &message.event.prefix,
+            &sha3_dig,
+            later_raw,
+            &sigs,
+            &[SelfAddressing::Blake3_256],
+        )
+        .into(),
+    )?;
+    assert_eq!(
+        db.resolve_digest(&message.event.prefix, &later_dig)?,
+        Some(later_dig.clone())
+    );
+
+    // the last event at an sn is the one inserted last, whatever its digest
+    let (greater, lesser) = if dig.to_str() > later_dig.to_str() {
+        ((&dig, raw.as_bytes()), (&later_dig, later_raw))
+    } else {
+        ((&later_dig, later_raw), (&dig, raw.as_bytes()))
+    };
+    db.finalise_event(&message.event.prefix, 2, greater.0)?;
+    db.finalise_event(&message.event.prefix, 2, lesser.0)?;
+    assert_eq!(
+        db.last_event_at_sn(&message.event.prefix, 2)?,
+        Some(lesser.1.to_vec())
+    );
+    // inserting a digest again doesn't move it
+    db.finalise_event(&message.event.prefix, 2, greater.0)?;
+    assert_eq!(
+        db.last_event_at_sn(&message.event.prefix, 2)?,
+        Some(lesser.1.to_vec())
+    );
+    db.commit(
+        WriteOp::remove(
+            SequenceTable::KeyEventLog,
+            &message.event.prefix,
+            2,
+            lesser.0,
+        )
+        .into(),
+    )?;
+    assert_eq!(
+        db.last_event_at_sn(&message.event.prefix, 2)?,
+        Some(greater.1.to_vec())
+    );
+    db.commit(
+        WriteOp::remove(
+            SequenceTable::KeyEventLog,
+            &message.event.prefix,
+            2,
+            greater.0,
+        )
+        .into(),
+    )?;
+    assert_eq!(db.last_event_at_sn(&message.event.prefix, 2)?, None);
+
+    // build a small delegation tree: root -> (mid -> leaf, other)
+    let root = &message.event.prefix;
+    let mid: IdentifierPrefix = "EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc"
+        .parse()
+        .unwrap();
+    let other: IdentifierPrefix = "EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI"
+        .parse()
+        .unwrap();
+    let leaf: IdentifierPrefix = "E0uTVILY2KXdcxX40MSM9Fr8EpGwfjMNap6ulAAzVt0M"
+        .parse()
+        .unwrap();
+
+    assert_eq!(db.get_children_of_prefix(root)?, None);
+    assert_eq!(db.get_parent_of_prefix(&mid)?, None);
+
+    db.log_delegation(root, &mid)?;
+    db.log_delegation(root, &other)?;
+    db.log_delegation(&mid, &leaf)?;
+    // logging the same delegation twice has no effect
+    db.log_delegation(root, &mid)?;
+
+    let children = db.get_children_of_prefix(root)?.unwrap();
+    assert_eq!(children.len(), 2);
+    assert!(children.contains(&mid) && children.contains(&other));
+    assert_eq!(db.get_children_of_prefix(&mid)?, Some(vec![leaf.clone()]));
+    assert_eq!(db.get_children_of_prefix(&leaf)?, None);
+
+    assert_eq!(db.get_parent_of_prefix(&leaf)?, Some(mid.clone()));
+    assert_eq!(db.get_parent_of_prefix(&mid)?, Some(root.clone()));
+    assert_eq!(db.get_parent_of_prefix(root)?, None);
+
+    // a delegate logged by a second delegator is a child of both,
+    // but keeps its first delegator as parent
+    db.log_delegation(&other, &leaf)?;
+    assert_eq!(db.get_children_of_prefix(&other)?, Some(vec![leaf.clone()]));
+    assert_eq!(db.get_children_of_prefix(&mid)?, Some(vec![leaf.clone()]));
+    assert_eq!(db.get_parent_of_prefix(&leaf)?, Some(mid.clone()));
+
+    // logged events can be fetched whatever their state
+    assert_eq!(db.get_event(root, &dig)?, Some(raw.as_bytes().to_vec()));
+    assert_eq!(db.get_event(&mid, &dig)?, None);
+    assert_eq!(db.get_signatures_for_event(root, &dig)?, Some(sigs.clone()));
+    assert_eq!(
+        db.get_sequence_entries(SequenceTable::KeyEventLog, Some(root))?,
+        vec![
+            (root.clone(), 0, dig.clone()),
+            (root.clone(), 1, later_dig.clone())
+        ]
+    );
+
+    // escrowed events are listed with their raw bytes and signatures
+    db.escrow_partially_signed_event(root, 1, &later_dig)?;
+    db.log_event(&mid, &dig, raw.as_bytes(), &sigs[..1])?;
+    db.escrow_out_of_order_event(&mid, 3, &dig)?;
+
+    assert_eq!(
+        db.get_escrowed_events(SequenceTable::PartiallySigned, Some(root))?,
+        vec![EscrowedEvent {
+            prefix: root.clone(),
+            sn: 1,
+            digest: later_dig.clone(),
+            raw: later_raw.to_vec(),
+            signatures: sigs.clone(),
+        }]
+    );
+    assert!(db
+        .get_escrowed_events(SequenceTable::PartiallySigned, Some(&mid))?
+        .is_empty());
+    let out_of_order = db.get_escrowed_events(SequenceTable::OutOfOrder, None)?;
+    assert_eq!(out_of_order.len(), 1);
+    assert_eq!(out_of_order[0].prefix, mid);
+    assert_eq!(out_of_order[0].signatures, sigs[..1].to_vec());
+    assert_eq!(
+        db.get_escrowed_events_at_sn(SequenceTable::OutOfOrder, &mid, 3)?,
+        out_of_order
+    );
+    assert!(db
+        .get_escrowed_events_at_sn(SequenceTable::OutOfOrder, &mid, 2)?
+        .is_empty());
+
+    // removal takes the entry out of the escrow only
+    db.remove_escrowed_event(SequenceTable::OutOfOrder, &mid, 3, &dig)?;
+    db.remove_escrowed_event(SequenceTable::OutOfOrder, &mid, 3, &dig)?;
+    assert!(db
+        .get_escrowed_events(SequenceTable::OutOfOrder, None)?
+        .is_empty());
+    assert_eq!(db.get_event(&mid, &dig)?, Some(raw.as_bytes().to_vec()));
+
+    // sns keep their order over the whole u64 range
+    db.finalise_event(&mid, u64::MAX, &dig)?;
+    db.finalise_event(&mid, 1, &dig)?;
+    assert_eq!(
+        db.last_event_at_sn(&mid, u64::MAX)?,
+        Some(raw.as_bytes().to_vec())
+    );
+    assert_eq!(
+        db.get_sequence_entries(SequenceTable::KeyEventLog, Some(&mid))?,
+        vec![
+            (mid.clone(), 1, dig.clone()),
+            (mid.clone(), u64::MAX, dig.clone())
+        ]
+    );
+
+    // escrowed receipts
+    let signer: BasicPrefix = "DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"
+        .parse()
+        .unwrap();
+    let nt_receipt = EscrowedNtReceipt {
+        prefix: mid.clone(),
+        sn: u64::MAX,
+        digest: dig.clone(),
+        signer: signer.clone(),
+        signature: sigs[0].signature.clone(),
+    };
+    let t_receipt = EscrowedTReceipt {
+        prefix: mid.clone(),
+        sn: 1,
+        digest: later_dig.clone(),
+        validator_seal: EventSeal {
+            prefix: other.clone(),
+            event_digest: dig.clone(),
+        },
+        signature: sigs[1].clone(),
+    };
+    db.escrow_nt_receipt(&mid, u64::MAX, &dig, &signer, &sigs[0].signature)?;
+    db.escrow_nt_receipt(&other, 0, &dig, &signer, &sigs[1].signature)?;
+    db.escrow_t_receipt(&mid, 1, &later_dig, &t_receipt.validator_seal, &sigs[1])?;
+
+    assert_eq!(
+        db.get_escrowed_nt_receipts(Some(&mid))?,
+        vec![nt_receipt.clone()]
+    );
+    assert_eq!(db.get_escrowed_nt_receipts(None)?.len(), 2);
+    assert_eq!(db.get_escrowed_t_receipts(None)?, vec![t_receipt.clone()]);
+    assert!(db.get_escrowed_t_receipts(Some(&other))?.is_empty());
+
+    db.remove_escrowed_nt_receipt(&nt_receipt)?;
+    db.remove_escrowed_t_receipt(&t_receipt)?;
+    assert!(db.get_escrowed_nt_receipts(Some(&mid))?.is_empty());
+    assert_eq!(db.get_escrowed_nt_receipts(None)?.len(), 1);
+    assert!(db.get_escrowed_t_receipts(None)?.is_empty());
+
+    // stored receipts
+    assert!(db.get_nt_receipts_for_event(&mid, &dig)?.is_none());
+    db.add_nt_receipt_for_event(&mid, &dig, &signer, &sigs[0].signature)?;
+    assert_eq!(
+        db.get_nt_receipts_for_event(&mid, &dig)?,
+        Some(vec![(signer, sigs[0].signature.clone())])
+    );
+    assert!(db.get_t_receipts_for_event(&mid, &dig)?.is_none());
+    db.add_t_receipt_for_event(&mid, &dig, &other, &sigs[1])?;
+    assert_eq!(
+        db.get_t_receipts_for_event(&mid, &dig)?,
+        Some(vec![(other, sigs[1].clone())])
+    );
+
     Ok(())
 }
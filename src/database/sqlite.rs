@@ -0,0 +1,575 @@
+use super::{
+    EscrowedNtReceipt, EscrowedTReceipt, EventDatabase, SequenceTable, WriteBatch, WriteOp,
+};
+use crate::{
+    event::sections::seal::EventSeal,
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
+    state::IdentifierState,
+};
+use chrono::prelude::*;
+use rusqlite::{params, types::Type, Connection, Error, OptionalExtension, Transaction};
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+/// Sqlite Event Database
+///
+/// An `EventDatabase` backed by a SQLite database, using the same
+/// logical tables as the LMDB implementation. Content tables are
+/// keyed by (prefix, digest) and sequence tables by (prefix, sn),
+/// with sns stored big-endian so they sort over the whole u64 range.
+pub struct SqliteEventDatabase {
+    conn: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS evts (pre TEXT NOT NULL, dig TEXT NOT NULL, raw BLOB NOT NULL, PRIMARY KEY (pre, dig));
+    CREATE TABLE IF NOT EXISTS digs (pre TEXT NOT NULL, alias TEXT NOT NULL, dig TEXT NOT NULL, PRIMARY KEY (pre, alias));
+    CREATE TABLE IF NOT EXISTS dtss (pre TEXT NOT NULL, dig TEXT NOT NULL, dts TEXT NOT NULL, PRIMARY KEY (pre, dig));
+    CREATE TABLE IF NOT EXISTS sigs (pre TEXT NOT NULL, dig TEXT NOT NULL, sig TEXT NOT NULL, UNIQUE (pre, dig, sig));
+    CREATE TABLE IF NOT EXISTS rcts (pre TEXT NOT NULL, dig TEXT NOT NULL, signer TEXT NOT NULL, sig TEXT NOT NULL, UNIQUE (pre, dig, signer, sig));
+    CREATE TABLE IF NOT EXISTS ures (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, signer TEXT NOT NULL, sig TEXT NOT NULL, UNIQUE (pre, sn, dig, signer, sig));
+    CREATE TABLE IF NOT EXISTS vrcs (pre TEXT NOT NULL, dig TEXT NOT NULL, signer TEXT NOT NULL, sig TEXT NOT NULL, UNIQUE (pre, dig, signer, sig));
+    CREATE TABLE IF NOT EXISTS vres (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, signer TEXT NOT NULL, signer_dig TEXT NOT NULL, sig TEXT NOT NULL, UNIQUE (pre, sn, dig, signer, signer_dig, sig));
+    CREATE TABLE IF NOT EXISTS kels (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS pses (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS pwes (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS ooes (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS ldes (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS dels (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS sdes (pre TEXT NOT NULL, sn BLOB NOT NULL, dig TEXT NOT NULL, UNIQUE (pre, sn, dig));
+    CREATE TABLE IF NOT EXISTS dlgs (delegator TEXT NOT NULL, delegate TEXT NOT NULL, PRIMARY KEY (delegator, delegate));
+    CREATE TABLE IF NOT EXISTS stts (pre TEXT NOT NULL PRIMARY KEY, state TEXT NOT NULL, dig TEXT NOT NULL);
+";
+
+impl SqliteEventDatabase {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// From Connection
+    ///
+    /// Uses an already open connection, creating any
+    /// missing tables
+    pub fn from_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // sqlite keeps the database consistent even if a holder panicked
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn sequence_table(table: SequenceTable) -> &'static str {
+    match table {
+        SequenceTable::KeyEventLog => "kels",
+        SequenceTable::PartiallySigned => "pses",
+        SequenceTable::PartiallyWitnessed => "pwes",
+        SequenceTable::OutOfOrder => "ooes",
+        SequenceTable::LikelyDuplicitous => "ldes",
+        SequenceTable::Duplicitous => "dels",
+        SequenceTable::Superseded => "sdes",
+    }
+}
+
+fn apply(tx: &Transaction, op: WriteOp) -> Result<(), Error> {
+    match op {
+        WriteOp::LogEvent {
+            pref,
+            dig,
+            aliases,
+            raw,
+            sigs,
+        } => {
+            let (pre, dig) = (pref.to_str(), dig.to_str());
+
+            // insert timestamp for event, unless it was seen before
+            tx.execute(
+                "INSERT OR IGNORE INTO dtss (pre, dig, dts) VALUES (?1, ?2, ?3)",
+                params![pre, dig, Utc::now().to_rfc3339()],
+            )?;
+
+            // index the event under its aliases, unless they were taken before
+            for alias in aliases {
+                tx.execute(
+                    "INSERT OR IGNORE INTO digs (pre, alias, dig) VALUES (?1, ?2, ?3)",
+                    params![pre, alias.to_str(), dig],
+                )?;
+            }
+
+            // insert signatures for event
+            for sig in sigs.iter() {
+                tx.execute(
+                    "INSERT OR IGNORE INTO sigs (pre, dig, sig) VALUES (?1, ?2, ?3)",
+                    params![pre, dig, sig.to_str()],
+                )?;
+            }
+
+            // insert event itself
+            tx.execute(
+                "INSERT OR REPLACE INTO evts (pre, dig, raw) VALUES (?1, ?2, ?3)",
+                params![pre, dig, raw],
+            )?;
+        }
+        WriteOp::Insert {
+            table,
+            pref,
+            sn,
+            dig,
+        } => {
+            tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO {} (pre, sn, dig) VALUES (?1, ?2, ?3)",
+                    sequence_table(table)
+                ),
+                params![pref.to_str(), sn_blob(sn), dig.to_str()],
+            )?;
+        }
+        WriteOp::Remove {
+            table,
+            pref,
+            sn,
+            dig,
+        } => {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE pre = ?1 AND sn = ?2 AND dig = ?3",
+                    sequence_table(table)
+                ),
+                params![pref.to_str(), sn_blob(sn), dig.to_str()],
+            )?;
+        }
+        WriteOp::NtReceipt {
+            pref,
+            dig,
+            signer,
+            sig,
+        } => {
+            tx.execute(
+                "INSERT OR IGNORE INTO rcts (pre, dig, signer, sig) VALUES (?1, ?2, ?3, ?4)",
+                params![pref.to_str(), dig.to_str(), signer.to_str(), sig.to_str()],
+            )?;
+        }
+        WriteOp::TReceipt {
+            pref,
+            dig,
+            signer,
+            sig,
+        } => {
+            tx.execute(
+                "INSERT OR IGNORE INTO vrcs (pre, dig, signer, sig) VALUES (?1, ?2, ?3, ?4)",
+                params![pref.to_str(), dig.to_str(), signer.to_str(), sig.to_str()],
+            )?;
+        }
+        WriteOp::EscrowNtReceipt(r) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO ures (pre, sn, dig, signer, sig) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    r.prefix.to_str(),
+                    sn_blob(r.sn),
+                    r.digest.to_str(),
+                    r.signer.to_str(),
+                    r.signature.to_str()
+                ],
+            )?;
+        }
+        WriteOp::RemoveNtReceipt(r) => {
+            tx.execute(
+                "DELETE FROM ures WHERE pre = ?1 AND sn = ?2 AND dig = ?3 AND signer = ?4 AND sig = ?5",
+                params![
+                    r.prefix.to_str(),
+                    sn_blob(r.sn),
+                    r.digest.to_str(),
+                    r.signer.to_str(),
+                    r.signature.to_str()
+                ],
+            )?;
+        }
+        WriteOp::EscrowTReceipt(r) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO vres (pre, sn, dig, signer, signer_dig, sig) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    r.prefix.to_str(),
+                    sn_blob(r.sn),
+                    r.digest.to_str(),
+                    r.validator_seal.prefix.to_str(),
+                    r.validator_seal.event_digest.to_str(),
+                    r.signature.to_str()
+                ],
+            )?;
+        }
+        WriteOp::RemoveTReceipt(r) => {
+            tx.execute(
+                "DELETE FROM vres WHERE pre = ?1 AND sn = ?2 AND dig = ?3 AND signer = ?4 AND signer_dig = ?5 AND sig = ?6",
+                params![
+                    r.prefix.to_str(),
+                    sn_blob(r.sn),
+                    r.digest.to_str(),
+                    r.validator_seal.prefix.to_str(),
+                    r.validator_seal.event_digest.to_str(),
+                    r.signature.to_str()
+                ],
+            )?;
+        }
+        WriteOp::LogDelegation {
+            delegator,
+            delegate,
+        } => {
+            tx.execute(
+                "INSERT OR IGNORE INTO dlgs (delegator, delegate) VALUES (?1, ?2)",
+                params![delegator.to_str(), delegate.to_str()],
+            )?;
+        }
+        WriteOp::UpdateState { state, dig } => {
+            let json = serde_json::to_string(&state)
+                .map_err(|e| Error::ToSqlConversionFailure(Box::new(e)))?;
+            tx.execute(
+                "INSERT OR REPLACE INTO stts (pre, state, dig) VALUES (?1, ?2, ?3)",
+                params![state.prefix.to_str(), json, dig.to_str()],
+            )?;
+        }
+    };
+    Ok(())
+}
+
+fn parse_prefix<P>(pref: &str) -> Result<P, Error>
+where
+    P: std::str::FromStr<Err = crate::error::Error>,
+{
+    pref.parse()
+        .map_err(|e| Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))
+}
+
+// sns are stored big-endian, so that they compare like the u64s they are
+fn sn_blob(sn: u64) -> Vec<u8> {
+    sn.to_be_bytes().to_vec()
+}
+
+fn parse_sn(blob: &[u8]) -> Result<u64, Error> {
+    blob.try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|e| Error::FromSqlConversionFailure(1, Type::Blob, Box::new(e)))
+}
+
+// builds a condition on the pre column which matches everything for None
+fn prefix_filter(pref: Option<&IdentifierPrefix>) -> (&'static str, String) {
+    match pref {
+        Some(p) => ("pre = ?1", p.to_str()),
+        None => ("?1 = ?1", String::new()),
+    }
+}
+
+impl EventDatabase for SqliteEventDatabase {
+    type Error = Error;
+
+    fn last_event_at_sn(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.conn()
+            .query_row(
+                "SELECT evts.raw FROM kels
+                    JOIN evts ON evts.pre = kels.pre AND evts.dig = kels.dig
+                    WHERE kels.pre = ?1 AND kels.sn = ?2
+                    ORDER BY kels.rowid DESC LIMIT 1",
+                params![pref.to_str(), sn_blob(sn)],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    fn get_cached_state(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<(IdentifierState, SelfAddressingPrefix)>, Self::Error> {
+        self.conn()
+            .query_row(
+                "SELECT state, dig FROM stts WHERE pre = ?1",
+                params![pref.to_str()],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+            .map(|(state, dig)| {
+                let state = serde_json::from_str(&state)
+                    .map_err(|e| Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))?;
+                Ok((state, parse_prefix(&dig)?))
+            })
+            .transpose()
+    }
+
+    fn get_children_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<Vec<IdentifierPrefix>>, Self::Error> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT delegate FROM dlgs WHERE delegator = ?1 ORDER BY rowid")?;
+        let children = stmt
+            .query_map(params![pref.to_str()], |row| row.get::<_, String>(0))?
+            .map(|delegate| parse_prefix(&delegate?))
+            .collect::<Result<Vec<IdentifierPrefix>, Error>>()?;
+
+        Ok(if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        })
+    }
+
+    fn get_parent_of_prefix(
+        &self,
+        pref: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierPrefix>, Self::Error> {
+        // a delegate keeps the delegator it was incepted with
+        self.conn()
+            .query_row(
+                "SELECT delegator FROM dlgs WHERE delegate = ?1 ORDER BY rowid LIMIT 1",
+                params![pref.to_str()],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .map(|delegator| parse_prefix(&delegator))
+            .transpose()
+    }
+
+    fn get_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.conn()
+            .query_row(
+                "SELECT raw FROM evts WHERE pre = ?1 AND dig = ?2",
+                params![pref.to_str(), dig.to_str()],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    fn get_event_timestamp(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<String>, Self::Error> {
+        self.conn()
+            .query_row(
+                "SELECT dts FROM dtss WHERE pre = ?1 AND dig = ?2",
+                params![pref.to_str(), dig.to_str()],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    fn resolve_digest(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<SelfAddressingPrefix>, Self::Error> {
+        self.conn()
+            .query_row(
+                "SELECT dig FROM digs WHERE pre = ?1 AND alias = ?2",
+                params![pref.to_str(), dig.to_str()],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .map(|dig| parse_prefix(&dig))
+            .transpose()
+    }
+
+    fn get_signatures_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<AttachedSignaturePrefix>>, Self::Error> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT sig FROM sigs WHERE pre = ?1 AND dig = ?2 ORDER BY rowid")?;
+        let sigs = stmt
+            .query_map(params![pref.to_str(), dig.to_str()], |row| {
+                row.get::<_, String>(0)
+            })?
+            .map(|sig| parse_prefix(&sig?))
+            .collect::<Result<Vec<AttachedSignaturePrefix>, Error>>()?;
+
+        Ok(if sigs.is_empty() { None } else { Some(sigs) })
+    }
+
+    fn get_nt_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(BasicPrefix, SelfSigningPrefix)>>, Self::Error> {
+        let conn = self.conn();
+        let mut stmt = conn
+            .prepare("SELECT signer, sig FROM rcts WHERE pre = ?1 AND dig = ?2 ORDER BY rowid")?;
+        let receipts = stmt
+            .query_map(params![pref.to_str(), dig.to_str()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|receipt| {
+                let (signer, sig) = receipt?;
+                Ok((parse_prefix(&signer)?, parse_prefix(&sig)?))
+            })
+            .collect::<Result<Vec<(BasicPrefix, SelfSigningPrefix)>, Error>>()?;
+
+        Ok(if receipts.is_empty() {
+            None
+        } else {
+            Some(receipts)
+        })
+    }
+
+    fn get_t_receipts_for_event(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Option<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>>, Self::Error> {
+        let conn = self.conn();
+        let mut stmt = conn
+            .prepare("SELECT signer, sig FROM vrcs WHERE pre = ?1 AND dig = ?2 ORDER BY rowid")?;
+        let receipts = stmt
+            .query_map(params![pref.to_str(), dig.to_str()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|receipt| {
+                let (signer, sig) = receipt?;
+                Ok((parse_prefix(&signer)?, parse_prefix(&sig)?))
+            })
+            .collect::<Result<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>, Error>>()?;
+
+        Ok(if receipts.is_empty() {
+            None
+        } else {
+            Some(receipts)
+        })
+    }
+
+    fn get_sequence_entries(
+        &self,
+        table: SequenceTable,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<(IdentifierPrefix, u64, SelfAddressingPrefix)>, Self::Error> {
+        let conn = self.conn();
+        let (filter, pre) = prefix_filter(pref);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT pre, sn, dig FROM {} WHERE {} ORDER BY pre, sn, rowid",
+            sequence_table(table),
+            filter
+        ))?;
+        let entries = stmt
+            .query_map(params![pre], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .map(|entry| {
+                let (pre, sn, dig) = entry?;
+                Ok((parse_prefix(&pre)?, parse_sn(&sn)?, parse_prefix(&dig)?))
+            })
+            .collect();
+        entries
+    }
+
+    fn get_escrowed_nt_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedNtReceipt>, Self::Error> {
+        let conn = self.conn();
+        let (filter, pre) = prefix_filter(pref);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT pre, sn, dig, signer, sig FROM ures WHERE {} ORDER BY rowid",
+            filter
+        ))?;
+        let receipts = stmt
+            .query_map(params![pre], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?
+            .map(|row| {
+                let (pre, sn, dig, signer, sig) = row?;
+                Ok(EscrowedNtReceipt {
+                    prefix: parse_prefix(&pre)?,
+                    sn: parse_sn(&sn)?,
+                    digest: parse_prefix(&dig)?,
+                    signer: parse_prefix(&signer)?,
+                    signature: parse_prefix(&sig)?,
+                })
+            })
+            .collect();
+        receipts
+    }
+
+    fn get_escrowed_t_receipts(
+        &self,
+        pref: Option<&IdentifierPrefix>,
+    ) -> Result<Vec<EscrowedTReceipt>, Self::Error> {
+        let conn = self.conn();
+        let (filter, pre) = prefix_filter(pref);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT pre, sn, dig, signer, signer_dig, sig FROM vres WHERE {} ORDER BY rowid",
+            filter
+        ))?;
+        let receipts = stmt
+            .query_map(params![pre], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?
+            .map(|row| {
+                let (pre, sn, dig, signer, signer_dig, sig) = row?;
+                Ok(EscrowedTReceipt {
+                    prefix: parse_prefix(&pre)?,
+                    sn: parse_sn(&sn)?,
+                    digest: parse_prefix(&dig)?,
+                    validator_seal: EventSeal {
+                        prefix: parse_prefix(&signer)?,
+                        event_digest: parse_prefix(&signer_dig)?,
+                    },
+                    signature: parse_prefix(&sig)?,
+                })
+            })
+            .collect();
+        receipts
+    }
+
+    fn commit(&self, batch: WriteBatch) -> Result<(), Self::Error> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for op in batch {
+            apply(&tx, op)?;
+        }
+        tx.commit()
+    }
+}
+
+#[test]
+fn basic() -> Result<(), Error> {
+    use super::test_db;
+    use tempfile::Builder;
+
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+
+    let db = SqliteEventDatabase::new(root.path().join("events.sqlite"))?;
+
+    test_db(db)
+}
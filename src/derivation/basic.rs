@@ -22,6 +22,17 @@ impl Basic {
     pub fn derive(&self, public_key: PublicKey) -> BasicPrefix {
         BasicPrefix::new(*self, public_key)
     }
+
+    /// Is Transferable
+    ///
+    /// False for the derivations of non-transferable prefixes,
+    /// whose keys can never be rotated
+    pub fn is_transferable(&self) -> bool {
+        !matches!(
+            self,
+            Self::ECDSAsecp256k1NT | Self::Ed25519NT | Self::Ed448NT
+        )
+    }
 }
 
 impl DerivationCode for Basic {
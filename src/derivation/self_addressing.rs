@@ -27,6 +27,14 @@ pub enum SelfAddressing {
 }
 
 impl SelfAddressing {
+    /// Is Implemented
+    ///
+    /// False for the derivations `digest` can't compute yet,
+    /// codes chosen by a peer must be checked with it first
+    pub fn is_implemented(&self) -> bool {
+        !matches!(self, Self::Blake2B256 | Self::Blake2S256)
+    }
+
     pub fn digest(&self, data: &[u8]) -> Vec<u8> {
         match self {
             Self::Blake3_256 => blake3_256_digest(data),
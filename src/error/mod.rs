@@ -1,4 +1,7 @@
-use crate::util::dfs_serializer;
+use crate::{
+    derivation::{basic::Basic, self_addressing::SelfAddressing, self_signing::SelfSigning},
+    util::dfs_serializer,
+};
 use base64::DecodeError;
 use core::num::ParseIntError;
 use serde_cbor;
@@ -45,9 +48,21 @@ pub enum Error {
     #[error("Error while aplying event: duplicate event")]
     EventDuplicateError,
 
+    #[error("Error while applying event: duplicitous event")]
+    DuplicitousEventError,
+
+    #[error("Error while applying event: likely duplicitous event")]
+    LikelyDuplicitousEventError,
+
+    #[error("Error while applying event: missing delegating event")]
+    MissingDelegatingEventError,
+
     #[error("Not enough signatures while verifing")]
     NotEnoughSigsError,
 
+    #[error("Not enough witness receipts for event")]
+    NotEnoughReceiptsError,
+
     #[error("validation error")]
     CryptoError(CryptoError),
 
@@ -65,4 +80,43 @@ pub enum Error {
 
     #[error("Storage error")]
     StorageError,
+
+    #[error("Event rejected by processor policy: {0}")]
+    PolicyViolation(#[from] PolicyViolation),
+}
+
+/// Policy Violation
+///
+/// The rule of the `ProcessorConfig` a message was rejected by
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PolicyViolation {
+    #[error("basic derivation {0:?} is not allowed")]
+    BasicCode(Basic),
+
+    #[error("self signing derivation {0:?} is not allowed")]
+    SelfSigningCode(SelfSigning),
+
+    #[error("self addressing derivation {0:?} is not allowed")]
+    SelfAddressingCode(SelfAddressing),
+
+    #[error("self addressing derivation {0:?} is not implemented")]
+    UnimplementedDigest(SelfAddressing),
+
+    #[error("event of {size} bytes is larger than {max}")]
+    EventTooLarge { size: usize, max: usize },
+
+    #[error("{count} keys are more than {max}")]
+    TooManyKeys { count: usize, max: usize },
+
+    #[error("{count} witnesses are more than {max}")]
+    TooManyWitnesses { count: usize, max: usize },
+
+    #[error("toad {toad} is lower than {min}")]
+    ToadTooLow { toad: u64, min: u64 },
+
+    #[error("non-transferable identifiers are not accepted")]
+    NonTransferable,
+
+    #[error("delegated identifiers are not accepted")]
+    Delegated,
 }
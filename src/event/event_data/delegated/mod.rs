@@ -1,6 +1,13 @@
 use super::super::sections::seal::LocationSeal;
-use super::{InceptionEvent, RotationEvent};
-use crate::state::EventSemantics;
+use super::{EventData, InceptionEvent, RotationEvent};
+use crate::{
+    derivation::self_addressing::SelfAddressing,
+    error::Error,
+    event::Event,
+    event_message::{serialization_info::SerializationFormats, EventMessage},
+    prefix::IdentifierPrefix,
+    state::{EventSemantics, IdentifierState},
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -13,6 +20,32 @@ pub struct DelegatedInceptionEvent {
     pub seal: LocationSeal,
 }
 
+impl DelegatedInceptionEvent {
+    /// Incept Self Addressing
+    ///
+    /// Takes the delegated inception data and creates an EventMessage
+    /// based on it, deriving the delegated Self Addressing Identifier
+    /// from the whole Dip event data with the given derivation method
+    pub fn incept_self_addressing(
+        self,
+        derivation: SelfAddressing,
+        format: SerializationFormats,
+    ) -> Result<EventMessage, Error> {
+        let prefix = IdentifierPrefix::SelfAddressing(derivation.derive(
+            &EventMessage::get_delegated_inception_data(&self, derivation, format)?,
+        ));
+
+        EventMessage::new(
+            Event {
+                prefix,
+                sn: 0,
+                event_data: EventData::Dip(self),
+            },
+            format,
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DelegatedRotationEvent {
     #[serde(flatten)]
@@ -23,5 +56,27 @@ pub struct DelegatedRotationEvent {
     pub seal: LocationSeal,
 }
 
-impl EventSemantics for DelegatedInceptionEvent {}
-impl EventSemantics for DelegatedRotationEvent {}
+impl EventSemantics for DelegatedInceptionEvent {
+    fn apply_to(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
+        Ok(IdentifierState {
+            delegator: Some(self.seal.prefix.clone()),
+            perm: self.perm.clone(),
+            ..self.inception_data.apply_to(state)?
+        })
+    }
+}
+
+impl EventSemantics for DelegatedRotationEvent {
+    fn apply_to(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
+        // only the original delegator can delegate a rotation
+        if state.delegator.as_ref() != Some(&self.seal.prefix) {
+            return Err(Error::SemanticError(
+                "Rotation not delegated by the delegator".into(),
+            ));
+        }
+        Ok(IdentifierState {
+            perm: self.perm.clone(),
+            ..self.rotation_data.apply_to(state)?
+        })
+    }
+}
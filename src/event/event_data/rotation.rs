@@ -26,9 +26,23 @@ pub struct RotationEvent {
 impl EventSemantics for RotationEvent {
     fn apply_to(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
         if state.current.verify_next(&self.key_config) {
+            // cut witnesses before adding the new ones
+            let mut witnesses: Vec<_> = state
+                .witnesses
+                .iter()
+                .filter(|w| !self.witness_config.prune.contains(w))
+                .cloned()
+                .collect();
+            for w in &self.witness_config.graft {
+                if !witnesses.contains(w) {
+                    witnesses.push(w.clone());
+                }
+            }
+
             Ok(IdentifierState {
                 current: self.key_config.clone(),
                 tally: self.witness_config.tally,
+                witnesses,
                 ..state
             })
         } else {
@@ -30,7 +30,7 @@ impl Event {
 impl EventSemantics for Event {
     fn apply_to(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
         match self.event_data {
-            EventData::Icp(_) => {
+            EventData::Icp(_) | EventData::Dip(_) => {
                 // ICP events require the state to be uninitialized
                 if state.prefix != IdentifierPrefix::default() {
                     return Err(Error::EventDuplicateError);
@@ -47,15 +47,31 @@ impl EventSemantics for Event {
                 }
             }
             _ => {
+                let ilk = match self.event_data {
+                    EventData::Rot(_) => "rot",
+                    EventData::Drt(_) => "drt",
+                    _ => "ixn",
+                };
                 // prefix must equal.
                 if self.prefix != state.prefix {
                     return Err(Error::SemanticError("Prefix does not match".to_string()));
-                // sn must be incremented
-                // TODO recovery will break this rule when we implement it
+                // sn must be incremented, recovery rotations with a lower sn
+                // are checked by the processor against an earlier state
                 } else if self.sn < state.sn + 1 {
                     return Err(Error::EventDuplicateError);
                 } else if self.sn > state.sn + 1 {
                     return Err(Error::EventOutOfOrderError);
+                // delegated identifiers rotate with drt events
+                } else if state.delegator.is_some() && ilk == "rot" {
+                    return Err(Error::SemanticError(
+                        "Delegated identifier must use delegated rotation".into(),
+                    ));
+                // and are restricted to the events their delegator permits
+                } else if !state.perm.is_empty() && !state.perm.iter().any(|p| p == ilk) {
+                    return Err(Error::SemanticError(format!(
+                        "{} events are not permitted by the delegator",
+                        ilk
+                    )));
                 }
             }
         };
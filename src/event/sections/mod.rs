@@ -68,6 +68,32 @@ impl KeyConfig {
         }
     }
 
+    /// Valid Signatures
+    ///
+    /// Filters the given sigs down to those which verify against the
+    /// Public Key at their index, keeping the first valid sig per index.
+    /// Used to accumulate signatures which arrive separately.
+    pub fn valid_signatures(
+        &self,
+        message: &[u8],
+        sigs: &[AttachedSignaturePrefix],
+    ) -> Vec<AttachedSignaturePrefix> {
+        sigs.iter()
+            .fold(vec![], |mut acc: Vec<AttachedSignaturePrefix>, sig| {
+                if !acc.iter().any(|s| s.index == sig.index)
+                    && self
+                        .public_keys
+                        .get(sig.index as usize)
+                        .map_or(false, |key| {
+                            key.verify(message, &sig.signature).unwrap_or(false)
+                        })
+                {
+                    acc.push(sig.clone());
+                }
+                acc
+            })
+    }
+
     /// Verify Next
     ///
     /// Verifies that the given next KeyConfig matches that which is committed
@@ -23,7 +23,7 @@ pub struct RootSeal {
     pub tree_root: SelfAddressingPrefix,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct EventSeal {
     #[serde(rename = "pre")]
     pub prefix: IdentifierPrefix,
@@ -2,7 +2,12 @@ use crate::{
     derivation::{attached_signature_code::get_sig_count, self_addressing::SelfAddressing},
     error::Error,
     event::{
-        event_data::{inception::InceptionEvent, EventData},
+        event_data::{
+            delegated::{DelegatedInceptionEvent, DelegatedRotationEvent},
+            inception::InceptionEvent,
+            rotation::RotationEvent,
+            EventData,
+        },
         Event,
     },
     prefix::{AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, Prefix, SelfSigningPrefix},
@@ -72,13 +77,33 @@ impl EventMessage {
         icp: &InceptionEvent,
         code: SelfAddressing,
         format: SerializationFormats,
+    ) -> Result<Vec<u8>, Error> {
+        Self::strip_prefix(EventData::Icp(icp.clone()), code, format)
+    }
+
+    /// Get Delegated Inception Data
+    ///
+    /// As Get Inception Data, but for a delegated inception
+    /// event, where the whole Dip event data is committed to (2.3.4)
+    pub fn get_delegated_inception_data(
+        dip: &DelegatedInceptionEvent,
+        code: SelfAddressing,
+        format: SerializationFormats,
+    ) -> Result<Vec<u8>, Error> {
+        Self::strip_prefix(EventData::Dip(dip.clone()), code, format)
+    }
+
+    fn strip_prefix(
+        event_data: EventData,
+        code: SelfAddressing,
+        format: SerializationFormats,
     ) -> Result<Vec<u8>, Error> {
         // use dummy prefix to get correct size info
         // TODO: dynamically size dummy derivative, non-32 byte prefixes will fail
         let icp_event_data = Event {
             prefix: IdentifierPrefix::SelfAddressing(code.derive(&[0u8; 32])),
             sn: 0,
-            event_data: EventData::Icp(icp.clone()),
+            event_data,
         };
         Ok(dfs_serializer::to_vec(&Self {
             serialization_info: icp_event_data
@@ -105,6 +130,26 @@ impl EventMessage {
     pub fn sign(&self, sigs: Vec<AttachedSignaturePrefix>) -> SignedEventMessage {
         SignedEventMessage::new(self, sigs)
     }
+
+    /// Digest Derivation
+    ///
+    /// The digest algorithm the event commits with: that of the
+    /// previous or receipted event digest it carries, or of its
+    /// prefix for a self-addressing inception. Blake3-256 if the
+    /// event carries no digest at all
+    pub fn digest_derivation(&self) -> SelfAddressing {
+        match &self.event.event_data {
+            EventData::Rot(rot) => rot.previous_event_hash.derivation,
+            EventData::Ixn(ixn) => ixn.previous_event_hash.derivation,
+            EventData::Drt(drt) => drt.rotation_data.previous_event_hash.derivation,
+            EventData::Rct(rct) => rct.receipted_event_digest.derivation,
+            EventData::Vrc(vrc) => vrc.receipted_event_digest.derivation,
+            EventData::Icp(_) | EventData::Dip(_) => match &self.event.prefix {
+                IdentifierPrefix::SelfAddressing(sap) => sap.derivation,
+                _ => SelfAddressing::Blake3_256,
+            },
+        }
+    }
 }
 
 impl SignedEventMessage {
@@ -130,11 +175,34 @@ impl SignedEventMessage {
     }
 }
 
+impl SignedNontransferableReceipt {
+    pub fn new(message: &EventMessage, couplets: Vec<(BasicPrefix, SelfSigningPrefix)>) -> Self {
+        Self {
+            body: message.clone(),
+            couplets,
+        }
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+        Ok([
+            self.body.serialize()?,
+            get_sig_count(self.couplets.len() as u16)
+                .as_bytes()
+                .to_vec(),
+            self.couplets
+                .iter()
+                .map(|(witness, sig)| [witness.to_str(), sig.to_str()].concat().into_bytes())
+                .fold(vec![], |acc, next| [acc, next].concat()),
+        ]
+        .concat())
+    }
+}
+
 impl EventSemantics for EventMessage {
     fn apply_to(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
         // Update state.last with serialized current event message.
         match self.event.event_data {
-            EventData::Icp(_) => {
+            EventData::Icp(_) | EventData::Dip(_) => {
                 if verify_identifier_binding(self)? {
                     self.event.apply_to(IdentifierState {
                         last: self.serialize()?,
@@ -146,13 +214,24 @@ impl EventSemantics for EventMessage {
                     ))
                 }
             }
-            EventData::Rot(ref rot) => {
+            EventData::Rot(RotationEvent {
+                ref previous_event_hash,
+                ..
+            })
+            | EventData::Drt(DelegatedRotationEvent {
+                rotation_data:
+                    RotationEvent {
+                        ref previous_event_hash,
+                        ..
+                    },
+                ..
+            }) => {
                 // Event may be out of order or duplicated, so before checking
                 // previous event hash binding and update state last, apply it
                 // to the state. It will return EventOutOfOrderError or
                 // EventDuplicateError in that cases.
                 self.event.apply_to(state.clone()).and_then(|next_state| {
-                    if rot.previous_event_hash.verify_binding(&state.last) {
+                    if previous_event_hash.verify_binding(&state.last) {
                         Ok(IdentifierState {
                             last: self.serialize()?,
                             ..next_state
@@ -199,6 +278,17 @@ pub fn verify_identifier_binding(icp_event: &EventMessage) -> Result<bool, Error
             )),
             IdentifierPrefix::SelfSigning(_ssp) => todo!(),
         },
+        // delegated identifiers are always self-addressing
+        EventData::Dip(dip) => match &icp_event.event.prefix {
+            IdentifierPrefix::SelfAddressing(sap) => Ok(sap.verify_binding(
+                &EventMessage::get_delegated_inception_data(
+                    &dip,
+                    sap.derivation,
+                    icp_event.serialization(),
+                )?,
+            )),
+            _ => Ok(false),
+        },
         _ => Err(Error::SemanticError("Not an ICP event".into())),
     }
 }
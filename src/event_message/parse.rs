@@ -319,6 +319,32 @@ fn test_stream3() {
     assert!(!result.is_ok());
 }
 
+#[test]
+fn test_malformed_couplet() {
+    let valid = r#"{"vs":"KERI10JSON00012a_","pre":"E4_CHZxqydVAvJEI7beqk3TZwUR92nQydi1nI8UqUTxk","sn":"0","ilk":"icp","sith":"1","keys":["DLfozZ0uGvLED22X3K8lX6ciwhl02jdjt1DQ_EHnJro0","C6KROFI5gWRXhAiIMiHLCDa-Oj09kmVMr2btCE96k_3g"],"nxt":"E99mhvP0pLkGtxymQkspRqcdoIFOqdigCf_F3rpg7rfk","toad":"0","wits":[],"cnfg":[]}-AABAAlxZyoxbADu-x9Ho6EC7valjC4bNn7muWvqC_u0EBd1P9xIeOSxmcYdhyvBg1-o-25ebv66Q3Td5bZ730wqLjBA"#;
+    // the witness prefix of the couplet isn't valid base64
+    let malformed = [
+        r#"{"vs":"KERI10JSON000099_","pre":"E4_CHZxqydVAvJEI7beqk3TZwUR92nQydi1nI8UqUTxk","sn":"0","ilk":"rct","dig":"E4_CHZxqydVAvJEI7beqk3TZwUR92nQydi1nI8UqUTxk"}-AAB"#,
+        "B",
+        &"!".repeat(43),
+        "0B",
+        &"A".repeat(86),
+    ]
+    .concat();
+
+    // a malformed message is a recoverable error, so a stream stops
+    // at it instead of failing as a whole
+    assert!(matches!(
+        signed_message(malformed.as_bytes()),
+        Err(nom::Err::Error(_))
+    ));
+    let rest = [malformed.as_str(), valid].concat();
+    let stream = [valid, &rest].concat();
+    let (unparsed, messages) = signed_event_stream(stream.as_bytes()).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(unparsed, rest.as_bytes());
+}
+
 #[test]
 fn test_sed_extraction() {
     let stream = r#"{"vs":"KERI10JSON000159_","pre":"ECui-E44CqN2U7uffCikRCp_YKLkPrA4jsTZ_A0XRLzc","sn":"0","ilk":"icp","sith":"2","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA","DVcuJOOJF1IE8svqEtrSuyQjGTd2HhfAkt9y2QkUtFJI","DT1iAhBWCkvChxNWsby2J0pJyxBIxbAtbLA0Ljx-Grh8"],"nxt":"Evhf3437ZRRnVhT0zOxo_rBX_GxpGoAnLuzrVlDK8ZdM","toad":"0","wits":[],"cnfg":[]}"#.as_bytes();
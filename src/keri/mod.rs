@@ -1,16 +1,14 @@
-use std::{collections::HashMap, str::from_utf8};
-
 use crate::{
+    database::EventDatabase,
     derivation::basic::Basic,
     derivation::self_addressing::SelfAddressing,
-    derivation::self_signing::SelfSigning,
     error::Error,
     event::event_data::inception::InceptionEvent,
     event::{
         event_data::interaction::InteractionEvent,
         sections::{
             seal::{DigestSeal, Seal},
-            WitnessConfig,
+            InceptionWitnessConfig, WitnessConfig,
         },
     },
     event::{
@@ -19,109 +17,239 @@ use crate::{
     },
     event::{
         event_data::EventData,
-        sections::{nxt_commitment, InceptionWitnessConfig, KeyConfig},
-        Event, EventMessage, SerializationFormats,
+        sections::{nxt_commitment, KeyConfig},
+        Event, SerializationFormats,
     },
-    event_message::parse::{signed_event_stream, Deserialized},
+    event_message::parse::{signed_event_stream, signed_message, Deserialized},
     event_message::SignedEventMessage,
-    log::EventLog,
-    prefix::AttachedSignaturePrefix,
-    prefix::IdentifierPrefix,
-    prefix::Prefix,
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
+    processor::{
+        config::ProcessorConfig, duplicity::SignedEvent, outcome::ProcessingOutcome, EventProcessor,
+    },
     signer::CryptoBox,
     state::IdentifierState,
-    util::dfs_serializer,
 };
+#[cfg(test)]
 mod test;
-pub struct Keri {
+
+/// Keri
+///
+/// A controller of one Identifier. Its own KEL and the KELs of
+/// the peers it talks to are kept in the database, so a controller
+/// can be reopened from it. The keys are kept by the key manager,
+/// which the caller has to keep between runs
+pub struct Keri<D: EventDatabase> {
+    prefix: IdentifierPrefix,
     key_manager: CryptoBox,
-    kel: EventLog,
+    next_threshold: u64,
+    processor: EventProcessor<D>,
     state: IdentifierState,
-    receipts: HashMap<u64, Vec<SignedEventMessage>>,
-    escrow_sigs: Vec<SignedEventMessage>,
-    other_instances: HashMap<String, IdentifierState>,
 }
-impl Keri {
+
+impl<D: EventDatabase> Keri<D> {
     // incept a state and keys
-    pub fn new() -> Result<Keri, Error> {
-        let key_manager = CryptoBox::new()?;
+    pub fn new(db: D, key_manager: CryptoBox) -> Result<Keri<D>, Error> {
+        let threshold = majority(key_manager.public_keys().len());
+        let next_threshold = majority(key_manager.next_public_keys().len());
+        Self::incept(db, key_manager, threshold, next_threshold)
+    }
+
+    /// Incept
+    ///
+    /// Incepts an identifier controlled by all current keys of
+    /// the key manager, `threshold` of which have to sign each
+    /// event. `next_threshold` is committed to for the next keys
+    pub fn incept(
+        db: D,
+        key_manager: CryptoBox,
+        threshold: u64,
+        next_threshold: u64,
+    ) -> Result<Keri<D>, Error> {
+        Self::incept_with_witnesses(db, key_manager, threshold, next_threshold, vec![], 0)
+    }
+
+    /// Incept with Witnesses
+    ///
+    /// Incepts an identifier as `incept` does, designating the
+    /// witnesses `toad` of which have to receipt each event
+    pub fn incept_with_witnesses(
+        db: D,
+        key_manager: CryptoBox,
+        threshold: u64,
+        next_threshold: u64,
+        witnesses: Vec<BasicPrefix>,
+        toad: u64,
+    ) -> Result<Keri<D>, Error> {
+        if (1..witnesses.len()).any(|i| witnesses[i..].contains(&witnesses[i - 1])) {
+            return Err(Error::SemanticError("Duplicate witnesses".into()));
+        }
+        check_toad(witnesses.len(), toad)?;
 
         let icp = InceptionEvent::new(
-            KeyConfig::new(
-                vec![Basic::Ed25519.derive(key_manager.public_key())],
-                nxt_commitment(
-                    1,
-                    &[Basic::Ed25519.derive(key_manager.next_pub_key.clone())],
-                    SelfAddressing::Blake3_256,
-                ),
-                Some(1),
-            ),
-            None,
+            key_config(&key_manager, threshold, next_threshold)?,
+            Some(InceptionWitnessConfig {
+                tally: toad,
+                initial_witnesses: witnesses,
+            }),
             None,
         )
         .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
 
-        let sigged = icp.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            key_manager.sign(&icp.serialize()?)?,
-            0,
-        )]);
-        let mut log = EventLog::new();
-
-        let s0 = IdentifierState::default().apply(&sigged)?;
-        s0.current
-            .verify(&sigged.event_message.serialize()?, &sigged.signatures)?;
+        let sigged = icp.sign(key_manager.sign_indexed(&icp.serialize()?)?);
 
-        log.commit(sigged)?;
+        let processor = EventProcessor::new(db, ProcessorConfig::default());
+        let state = finalise(&processor, &sigged)?;
 
         Ok(Keri {
-            kel: log,
-            receipts: HashMap::new(),
-            state: s0,
+            prefix: state.prefix.clone(),
             key_manager,
-            escrow_sigs: vec![],
-            other_instances: HashMap::new(),
+            next_threshold,
+            processor,
+            state,
         })
     }
 
+    /// Open
+    ///
+    /// Restores the controller of the KEL in the database
+    /// whose current keys are held by the key manager
+    pub fn open(db: D, key_manager: CryptoBox) -> Result<Keri<D>, Error> {
+        let processor = EventProcessor::new(db, ProcessorConfig::default());
+        let keys: Vec<BasicPrefix> = key_manager
+            .public_keys()
+            .into_iter()
+            .map(|k| Basic::Ed25519.derive(k))
+            .collect();
+        let next_keys: Vec<BasicPrefix> = key_manager
+            .next_public_keys()
+            .into_iter()
+            .map(|k| Basic::Ed25519.derive(k))
+            .collect();
+
+        for prefix in processor.get_prefixes()? {
+            if let Some(state) = processor.compute_state(&prefix)? {
+                if state.current.public_keys != keys {
+                    continue;
+                }
+                // the next threshold is only known through the commitment
+                let next_threshold = (1..=next_keys.len() as u64)
+                    .find(|t| {
+                        nxt_commitment(*t, &next_keys, SelfAddressing::Blake3_256)
+                            == state.current.threshold_key_digest
+                    })
+                    .ok_or_else(|| {
+                        Error::SemanticError("Next keys don't match the commitment".into())
+                    })?;
+
+                return Ok(Keri {
+                    prefix,
+                    key_manager,
+                    next_threshold,
+                    processor,
+                    state,
+                });
+            }
+        }
+
+        Err(Error::SemanticError(
+            "No KEL controlled by the given keys".into(),
+        ))
+    }
+
+    /// Rotate
+    ///
+    /// Rotates to the next keys, pre-rotating as many
+    /// new ones under the same threshold
     pub fn rotate(&mut self) -> Result<SignedEventMessage, Error> {
-        self.key_manager = self.key_manager.rotate()?;
+        let next_keys = self.key_manager.next_public_keys().len();
+        self.rotate_with(next_keys, self.next_threshold)
+    }
+
+    /// Rotate With
+    ///
+    /// Rotates to the next keys under the committed threshold,
+    /// pre-rotating `next_keys` new keys under `next_threshold`
+    pub fn rotate_with(
+        &mut self,
+        next_keys: usize,
+        next_threshold: u64,
+    ) -> Result<SignedEventMessage, Error> {
+        let witness_config = WitnessConfig {
+            tally: self.state.tally,
+            ..WitnessConfig::default()
+        };
+        self.make_rot(next_keys, next_threshold, witness_config)
+    }
+
+    /// Rotate Witnesses
+    ///
+    /// Rotates the keys as `rotate` does, adding and cutting
+    /// witnesses and setting a new toad for them
+    pub fn rotate_witnesses(
+        &mut self,
+        adds: Vec<BasicPrefix>,
+        cuts: Vec<BasicPrefix>,
+        toad: u64,
+    ) -> Result<SignedEventMessage, Error> {
+        let witnesses = &self.state.witnesses;
+        if cuts.iter().any(|w| !witnesses.contains(w)) {
+            return Err(Error::SemanticError("Cut witness is not a witness".into()));
+        }
+        if adds
+            .iter()
+            .enumerate()
+            .any(|(i, w)| witnesses.contains(w) || cuts.contains(w) || adds[i + 1..].contains(w))
+        {
+            return Err(Error::SemanticError(
+                "Added witness is already a witness".into(),
+            ));
+        }
+        let cut = witnesses.iter().filter(|w| cuts.contains(w)).count();
+        check_toad(witnesses.len() - cut + adds.len(), toad)?;
+
+        let next_keys = self.key_manager.next_public_keys().len();
+        self.make_rot(
+            next_keys,
+            self.next_threshold,
+            WitnessConfig {
+                tally: toad,
+                prune: cuts,
+                graft: adds,
+            },
+        )
+    }
+
+    fn make_rot(
+        &mut self,
+        next_keys: usize,
+        next_threshold: u64,
+        witness_config: WitnessConfig,
+    ) -> Result<SignedEventMessage, Error> {
+        let key_manager = self.key_manager.rotate_to(next_keys)?;
 
         let ev = {
             Event {
-                prefix: self.state.prefix.clone(),
+                prefix: self.prefix.clone(),
                 sn: self.state.sn + 1,
                 event_data: EventData::Rot(RotationEvent {
                     previous_event_hash: SelfAddressing::Blake3_256.derive(&self.state.last),
-                    key_config: KeyConfig::new(
-                        vec![Basic::Ed25519.derive(self.key_manager.public_key())],
-                        nxt_commitment(
-                            1,
-                            &[Basic::Ed25519.derive(self.key_manager.next_pub_key.clone())],
-                            SelfAddressing::Blake3_256,
-                        ),
-                        Some(1),
-                    ),
-                    witness_config: WitnessConfig::default(),
+                    key_config: key_config(&key_manager, self.next_threshold, next_threshold)?,
+                    witness_config,
                     data: vec![],
                 }),
             }
             .to_message(SerializationFormats::JSON)?
         };
 
-        let signature = self.key_manager.sign(&ev.serialize()?)?;
-        let rot = ev.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            signature,
-            0,
-        )]);
+        let rot = ev.sign(key_manager.sign_indexed(&ev.serialize()?)?);
 
-        self.state = self.state.clone().apply(&rot)?;
-        self.state
-            .current
-            .verify(&rot.event_message.serialize()?, &rot.signatures)?;
-
-        self.kel.commit(rot.clone())?;
+        // the new keys are only taken into use once the rotation is in the KEL
+        self.state = finalise(&self.processor, &rot)?;
+        self.key_manager = key_manager;
+        self.next_threshold = next_threshold;
 
         Ok(rot)
     }
@@ -132,7 +260,7 @@ impl Keri {
         };
 
         let ev = Event {
-            prefix: self.state.prefix.clone(),
+            prefix: self.prefix.clone(),
             sn: self.state.sn + 1,
             event_data: EventData::Ixn(InteractionEvent {
                 previous_event_hash: SelfAddressing::Blake3_256.derive(&self.state.last),
@@ -141,163 +269,241 @@ impl Keri {
         }
         .to_message(SerializationFormats::JSON)?;
 
-        let signature = self.key_manager.sign(&ev.serialize()?)?;
-        let ixn = ev.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            signature,
-            0,
-        )]);
+        let ixn = ev.sign(self.key_manager.sign_indexed(&ev.serialize()?)?);
 
-        self.state = self.state.clone().apply(&ixn)?;
-        self.state
-            .current
-            .verify(&ixn.event_message.serialize()?, &ixn.signatures)?;
+        self.state = finalise(&self.processor, &ixn)?;
 
-        self.kel.commit(ixn.clone())?;
         Ok(ixn)
     }
 
-    pub fn process_events(&mut self, msg: &[u8]) -> Result<String, Error> {
+    /// Process Events
+    ///
+    /// Processes a stream of messages from peers, returns receipts
+    /// of the peer events which were verified and finalised, preceded
+    /// by the own KEL for peers seen for the first time
+    pub fn process_events(&self, msg: &[u8]) -> Result<String, Error> {
         let events = signed_event_stream(msg)
             .map_err(|_| Error::DeserializationError)?
             .1;
-        let mut response: Vec<SignedEventMessage> = vec![];
+        let mut response: Vec<Vec<u8>> = vec![];
         for dev in events {
             match dev {
-                Deserialized::Event(ev) => match ev.event.event.event.event_data {
-                    EventData::Icp(_) => {
-                        let ev_prefix = ev.event.event.event.prefix.to_str();
-                        let state = IdentifierState::default().apply(&ev.event.event)?;
-                        state.current.verify(ev.event.raw, &ev.signatures)?;
-
-                        if !self.other_instances.contains_key(&ev_prefix) {
-                            if let Some(icp) = self.kel.get_last() {
-                                response.push(icp);
-                            }
-                        }
-                        self.other_instances.insert(ev_prefix.clone(), state);
-                        let rct = self.make_rct(ev.event.event)?;
-                        response.push(rct);
+                Deserialized::Event(ev) => {
+                    let prefix = ev.event.event.event.prefix.clone();
+                    // own events are not receipted
+                    if prefix == self.prefix {
+                        continue;
                     }
-                    _ => {
-                        let prefix_str = ev.event.event.event.prefix.to_str();
-
-                        let state = self
-                            .other_instances
-                            .remove(&prefix_str)
-                            .unwrap_or(IdentifierState::default());
-                        self.other_instances
-                            .insert(prefix_str.clone(), state.apply(&ev.event.event)?);
-
-                        let rct = self.make_rct(ev.event.event)?;
-                        response.push(rct);
-                    }
-                },
-                Deserialized::Vrc(r) => match r.event_message.event.event_data {
-                    EventData::Vrc(ref rct) => {
-                        let prefix_str = rct.validator_location_seal.prefix.to_str();
-                        let validator = self.other_instances.get(&prefix_str).unwrap().clone();
+                    let known = self.processor.compute_state(&prefix)?.is_some();
+                    let event = ev.event.event.event.clone();
+                    let raw = ev.event.raw;
 
-                        self.process_receipt(validator, r).unwrap();
-                    }
-                    // NOTE should never happen
-                    _ => Err(Error::SemanticError("Incorrect Receipt Structure".into()))?,
-                },
-                Deserialized::Rct(_) => todo!(),
-            }
-        }
-        let str_res = response
-            .iter()
-            .map(|ev| from_utf8(&ev.serialize().unwrap()).unwrap().to_string())
-            .collect::<Vec<_>>()
-            .concat();
-        Ok(str_res)
-    }
-
-    // take a receipt made by validator, verify it and add to receipts or escrow
-    fn process_receipt(
-        &mut self,
-        validator: IdentifierState,
-        sigs: SignedEventMessage,
-    ) -> Result<(), Error> {
-        match sigs.event_message.event.event_data.clone() {
-            EventData::Vrc(rct) => {
-                let event = self.kel.get(sigs.event_message.event.sn)?;
-
-                // This logic can in future be moved to the correct place in the Kever equivalent here
-                // receipt pref is the ID who made the event being receipted
-                if sigs.event_message.event.prefix == self.state.prefix
-                            // dig is the digest of the event being receipted
-                            && rct.receipted_event_digest
-                                == rct
-                                    .receipted_event_digest
-                                    .derivation
-                                    .derive(&event.event_message.serialize()?)
-                            // seal pref is the pref of the validator
-                            && rct.validator_location_seal.prefix == validator.prefix
-                {
-                    if rct.validator_location_seal.event_digest
-                        == rct
-                            .validator_location_seal
-                            .event_digest
-                            .derivation
-                            .derive(&validator.last)
-                    {
-                        // seal dig is the digest of the last establishment event for the validator, verify the rct
-                        validator
-                            .current
-                            .verify(&event.event_message.serialize()?, &sigs.signatures)?;
-                        self.receipts
-                            .entry(sigs.event_message.event.sn)
-                            .or_insert_with(|| vec![])
-                            .push(sigs);
-                    } else {
-                        // escrow the seal
-                        self.escrow_sigs.push(sigs)
+                    // only events whose signatures satisfy the current
+                    // threshold are finalised, the rest are escrowed or
+                    // rejected by the processor and never receipted
+                    if let ProcessingOutcome::Accepted { .. } = self.processor.process_event(ev)? {
+                        // introduce ourselves to a new peer
+                        if !known {
+                            response.extend(
+                                self.processor
+                                    .get_kel(&self.prefix)?
+                                    .iter()
+                                    .map(SignedEvent::serialize),
+                            );
+                        }
+                        response.push(self.make_rct(&event, raw)?.serialize()?);
                     }
-                    Ok(())
-                } else {
-                    Err(Error::SemanticError("incorrect receipt binding".into()))
+                }
+                // receipts are escrowed by the processor until
+                // the event, or the validator's keys, are known
+                Deserialized::Vrc(r) => {
+                    self.processor.process_validator_receipt(r)?;
+                }
+                Deserialized::Rct(r) => {
+                    self.processor.process_witness_receipt(r)?;
                 }
             }
-            _ => Err(Error::SemanticError("not a receipt".into())),
         }
+
+        String::from_utf8(response.concat()).map_err(|e| Error::SerializationError(e.to_string()))
     }
 
-    fn make_rct(&self, event: EventMessage) -> Result<SignedEventMessage, Error> {
-        let ser = event.serialize()?;
-        let signature = self.key_manager.sign(&ser)?;
+    /// Make Receipt
+    ///
+    /// Receipts the event as it was received and verified,
+    /// re-serializing it might not reproduce its signed bytes
+    fn make_rct(&self, event: &Event, raw: &[u8]) -> Result<SignedEventMessage, Error> {
+        let signatures = self.key_manager.sign_indexed(&raw.to_vec())?;
         Ok(Event {
-            prefix: event.event.prefix,
-            sn: event.event.sn,
+            prefix: event.prefix.clone(),
+            sn: event.sn,
             event_data: EventData::Vrc(ReceiptTransferable {
-                receipted_event_digest: SelfAddressing::Blake3_256.derive(&ser),
+                receipted_event_digest: SelfAddressing::Blake3_256.derive(raw),
                 validator_location_seal: EventSeal {
-                    prefix: self.state.prefix.clone(),
+                    prefix: self.prefix.clone(),
                     event_digest: SelfAddressing::Blake3_256.derive(&self.state.last),
                 },
             }),
         }
         .to_message(SerializationFormats::JSON)?
-        .sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            signature,
-            0,
-        )]))
+        .sign(signatures))
     }
 
-    pub fn get_last_event(&self) -> String {
-        match self.kel.get_last() {
-            Some(ev) => from_utf8(&ev.serialize().unwrap()).unwrap().to_string(),
-            None => String::new(),
+    pub fn get_last_event(&self) -> Result<String, Error> {
+        match self.processor.get_kel(&self.prefix)?.last() {
+            Some(ev) => String::from_utf8(ev.serialize())
+                .map_err(|e| Error::SerializationError(e.to_string())),
+            None => Ok(String::new()),
         }
     }
 
-    pub fn get_log_len(&self) -> usize {
-        self.kel.get_len()
+    pub fn get_log_len(&self) -> Result<usize, Error> {
+        Ok(self.processor.get_kel(&self.prefix)?.len())
+    }
+
+    /// Get Receipts
+    ///
+    /// Returns the validator receipts of the own event at sn
+    pub fn get_receipts(
+        &self,
+        sn: u64,
+    ) -> Result<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>, Error> {
+        match self.own_event_digest(sn)? {
+            Some(dig) => self.processor.get_validator_receipts(&self.prefix, &dig),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Get Witness Receipts
+    ///
+    /// Returns the witness receipt couplets of the own event at sn
+    pub fn get_witness_receipts(
+        &self,
+        sn: u64,
+    ) -> Result<Vec<(BasicPrefix, SelfSigningPrefix)>, Error> {
+        match self.own_event_digest(sn)? {
+            Some(dig) => self.processor.get_witness_receipts(&self.prefix, &dig),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Is Fully Witnessed
+    ///
+    /// Whether at least toad of the witnesses designated at the
+    /// own event at sn have receipted it
+    pub fn is_fully_witnessed(&self, sn: u64) -> Result<bool, Error> {
+        let state = match self.processor.compute_state_at_sn(&self.prefix, sn)? {
+            Some(state) if state.sn == sn => state,
+            _ => return Ok(false),
+        };
+        let witnessed = self
+            .get_witness_receipts(sn)?
+            .iter()
+            .filter(|(witness, _)| state.witnesses.contains(witness))
+            .count();
+        Ok(witnessed as u64 >= state.tally)
+    }
+
+    fn own_event_digest(&self, sn: u64) -> Result<Option<SelfAddressingPrefix>, Error> {
+        Ok(self
+            .processor
+            .get_kel(&self.prefix)?
+            .get(sn as usize)
+            .map(|ev| SelfAddressing::Blake3_256.derive(&ev.raw)))
     }
 
     pub fn get_state(&self) -> IdentifierState {
         self.state.clone()
     }
+
+    /// Get State for Prefix
+    ///
+    /// Returns the current state of a peer, or of this controller
+    pub fn get_state_for_prefix(
+        &self,
+        id: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierState>, Error> {
+        self.processor.compute_state(id)
+    }
+
+    pub fn get_prefix(&self) -> &IdentifierPrefix {
+        &self.prefix
+    }
+
+    /// Key Manager
+    ///
+    /// The keys currently in use, to be exported by the
+    /// caller for reopening the controller
+    pub fn key_manager(&self) -> &CryptoBox {
+        &self.key_manager
+    }
+}
+
+/// Key Config
+///
+/// Builds the key config of the current keys of the key manager,
+/// committing to its next keys
+fn key_config(
+    key_manager: &CryptoBox,
+    threshold: u64,
+    next_threshold: u64,
+) -> Result<KeyConfig, Error> {
+    let keys = key_manager.public_keys();
+    let next_keys = key_manager.next_public_keys();
+    if threshold == 0 || threshold as usize > keys.len() {
+        return Err(Error::SemanticError("Threshold out of range".into()));
+    }
+    if next_threshold == 0 || next_threshold as usize > next_keys.len() {
+        return Err(Error::SemanticError("Next threshold out of range".into()));
+    }
+
+    let next_keys: Vec<BasicPrefix> = next_keys
+        .into_iter()
+        .map(|k| Basic::Ed25519.derive(k))
+        .collect();
+    Ok(KeyConfig::new(
+        keys.into_iter().map(|k| Basic::Ed25519.derive(k)).collect(),
+        nxt_commitment(next_threshold, &next_keys, SelfAddressing::Blake3_256),
+        Some(threshold),
+    ))
+}
+
+/// Check Toad
+///
+/// A toad has to be reachable by the witnesses, and
+/// can only be zero without witnesses
+fn check_toad(witnesses: usize, toad: u64) -> Result<(), Error> {
+    if toad as usize > witnesses || (toad == 0 && witnesses > 0) {
+        return Err(Error::SemanticError("Toad out of range".into()));
+    }
+    Ok(())
+}
+
+/// Majority
+///
+/// The default threshold for a number of keys
+fn majority(keys: usize) -> u64 {
+    (keys / 2 + 1) as u64
+}
+
+/// Finalise
+///
+/// Processes an own event, which has to be accepted,
+/// returns the state after it
+fn finalise<D: EventDatabase>(
+    processor: &EventProcessor<D>,
+    event: &SignedEventMessage,
+) -> Result<IdentifierState, Error> {
+    let raw = event.serialize()?;
+    let message = signed_message(&raw)
+        .map_err(|_| Error::DeserializationError)?
+        .1;
+
+    match processor.process(message)? {
+        ProcessingOutcome::Accepted { mut states, .. } if !states.is_empty() => {
+            Ok(states.remove(0))
+        }
+        ProcessingOutcome::Rejected { reason, .. } => Err(reason),
+        _ => Err(Error::SemanticError("Own event not accepted".into())),
+    }
 }
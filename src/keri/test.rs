@@ -1,97 +1,550 @@
-use crate::{error::Error, keri::Keri, prefix::Prefix};
+use crate::{
+    database::memory::MemoryEventDatabase,
+    derivation::{basic::Basic, self_addressing::SelfAddressing, self_signing::SelfSigning},
+    error::Error,
+    event::SerializationFormats,
+    event::{event_data::receipt::ReceiptNonTransferable, event_data::EventData, Event},
+    event_message::{
+        parse::{signed_event_stream, signed_message, Deserialized},
+        SignedEventMessage, SignedNontransferableReceipt,
+    },
+    keri::Keri,
+    prefix::{BasicPrefix, IdentifierPrefix},
+    processor::duplicity::SignedEvent,
+    signer::CryptoBox,
+};
+use ursa::{
+    keys::PrivateKey,
+    signatures::{ed25519, SignatureScheme},
+};
 
 #[test]
 fn test_direct_mode() -> Result<(), Error> {
     // Init alice.
-    let mut alice = Keri::new()?;
+    let mut alice = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
     assert_eq!(alice.get_state().sn, 0);
-    assert_eq!(alice.kel.get_len(), 1);
-    assert!(alice.other_instances.is_empty());
-    assert!(alice.receipts.is_empty());
-    assert!(alice.escrow_sigs.is_empty());
+    assert_eq!(alice.get_log_len()?, 1);
+    assert!(alice.get_receipts(0)?.is_empty());
 
     // Init bob.
-    let mut bob = Keri::new()?;
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
     assert_eq!(bob.get_state().sn, 0);
-    assert_eq!(bob.get_log_len(), 1);
-    assert!(bob.other_instances.is_empty());
-    assert!(bob.receipts.is_empty());
-    assert!(bob.escrow_sigs.is_empty());
+    assert_eq!(bob.get_log_len()?, 1);
+    assert!(bob.get_receipts(0)?.is_empty());
 
     // Get alice's inception event.
-    let mut msg_to_bob = alice.get_last_event();
+    let mut msg_to_bob = alice.get_last_event()?;
 
     // Send it to bob.
-    let mut msg_to_alice = bob.process_events(&msg_to_bob.as_bytes())?;
-    {
-        // Check if bob's state of alice is the same as current alice state.
-        let alice_state_in_bob = bob
-            .other_instances
-            .get(&alice.get_state().prefix.to_str())
-            .unwrap();
-        assert_eq!(*alice_state_in_bob, alice.get_state());
-    }
+    let mut msg_to_alice = bob.process_events(msg_to_bob.as_bytes())?;
+    // Check if bob's state of alice is the same as current alice state.
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
 
     // Send message from bob to alice and get alice's receipts.
-    msg_to_bob = alice.process_events(&msg_to_alice.as_bytes())?;
-
-    {
-        // Check if alice's state of bob is the same as current bob state.
-        let bob_state_in_alice = alice
-            .other_instances
-            .get(&bob.get_state().prefix.to_str())
-            .unwrap();
-        assert_eq!(*bob_state_in_alice, bob.get_state());
-        assert_eq!(alice.receipts[&0].len(), 1);
-    }
+    msg_to_bob = alice.process_events(msg_to_alice.as_bytes())?;
+    // Check if alice's state of bob is the same as current bob state.
+    assert_eq!(
+        alice.get_state_for_prefix(bob.get_prefix())?,
+        Some(bob.get_state())
+    );
+    assert_eq!(alice.get_receipts(0)?.len(), 1);
 
     // Send it to bob.
-    bob.process_events(&msg_to_bob.as_bytes())?;
-    assert_eq!(bob.receipts[&0].len(), 1);
+    bob.process_events(msg_to_bob.as_bytes())?;
+    assert_eq!(bob.get_receipts(0)?.len(), 1);
 
     // Rotation event.
     alice.rotate()?;
-    assert_eq!(alice.get_log_len(), 2);
+    assert_eq!(alice.get_log_len()?, 2);
     assert_eq!(alice.get_state().sn, 1);
 
     // Send rotation event to bob.
-    msg_to_bob = alice.get_last_event();
-    msg_to_alice = bob.process_events(&msg_to_bob.as_bytes())?;
-    {
-        // Check if bob's state of alice is the same as current alice state.
-        let alice_state_in_bob = bob
-            .other_instances
-            .get(&alice.get_state().prefix.to_str())
-            .unwrap();
-        assert_eq!(*alice_state_in_bob, alice.get_state());
-    }
+    msg_to_bob = alice.get_last_event()?;
+    msg_to_alice = bob.process_events(msg_to_bob.as_bytes())?;
+    // Check if bob's state of alice is the same as current alice state.
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
 
     // Send bob's receipt to alice.
-    alice.process_events(&msg_to_alice.as_bytes())?;
-    assert_eq!(alice.receipts.len(), 2);
-    assert_eq!(alice.escrow_sigs.len(), 0);
+    alice.process_events(msg_to_alice.as_bytes())?;
+    assert_eq!(alice.get_receipts(1)?.len(), 1);
 
     // Interaction event.
     alice.make_ixn("")?;
-    assert_eq!(alice.get_log_len(), 3);
+    assert_eq!(alice.get_log_len()?, 3);
     assert_eq!(alice.get_state().sn, 2);
 
     // Send interaction event to bob.
-    msg_to_bob = alice.get_last_event();
-    msg_to_alice = bob.process_events(&msg_to_bob.as_bytes())?;
-
-    {
-        // Check if bob's state of alice is the same as current alice state.
-        let alice_state_in_bob = bob
-            .other_instances
-            .get(&alice.get_state().prefix.to_str())
-            .unwrap();
-        assert_eq!(*alice_state_in_bob, alice.get_state());
+    msg_to_bob = alice.get_last_event()?;
+    msg_to_alice = bob.process_events(msg_to_bob.as_bytes())?;
+    // Check if bob's state of alice is the same as current alice state.
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+
+    alice.process_events(msg_to_alice.as_bytes())?;
+    assert_eq!(alice.get_receipts(2)?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_threshold_controller() -> Result<(), Error> {
+    // Thresholds have to be reachable by the keys.
+    assert!(Keri::incept(MemoryEventDatabase::new(), CryptoBox::generate(3, 3)?, 4, 2).is_err());
+    assert!(Keri::incept(MemoryEventDatabase::new(), CryptoBox::generate(3, 3)?, 2, 0).is_err());
+
+    // Init a 2 of 3 alice.
+    let mut alice = Keri::incept(MemoryEventDatabase::new(), CryptoBox::generate(3, 3)?, 2, 2)?;
+    assert_eq!(alice.get_state().current.public_keys.len(), 3);
+    assert_eq!(alice.get_state().current.threshold, 2);
+
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+
+    // Bob verifies alice's multi signature inception and receipts it.
+    let msg_to_alice = bob.process_events(alice.get_last_event()?.as_bytes())?;
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+    let msg_to_bob = alice.process_events(msg_to_alice.as_bytes())?;
+    assert_eq!(alice.get_receipts(0)?.len(), 1);
+
+    // Alice's receipt of bob is signed with all her keys.
+    bob.process_events(msg_to_bob.as_bytes())?;
+    let receipts = bob.get_receipts(0)?;
+    assert_eq!(receipts.len(), 3);
+    assert!(receipts.iter().all(|(id, _)| id == alice.get_prefix()));
+
+    // Rotate to the committed 2 of 3 keys, pre-rotating 3 of 4.
+    let rot = alice.rotate_with(4, 3)?;
+    assert_eq!(rot.signatures.len(), 3);
+    assert_eq!(alice.get_state().current.threshold, 2);
+    bob.process_events(alice.get_last_event()?.as_bytes())?;
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+
+    // And on to the 3 of 4 keys.
+    let rot = alice.rotate()?;
+    assert_eq!(rot.signatures.len(), 4);
+    assert_eq!(alice.get_state().current.public_keys.len(), 4);
+    assert_eq!(alice.get_state().current.threshold, 3);
+    bob.process_events(alice.get_last_event()?.as_bytes())?;
+    alice.make_ixn("")?;
+    bob.process_events(alice.get_last_event()?.as_bytes())?;
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+
+    Ok(())
+}
+
+fn witness() -> Result<(BasicPrefix, PrivateKey), Error> {
+    let (pub_key, priv_key) = ed25519::Ed25519Sha512::new()
+        .keypair(None)
+        .map_err(Error::CryptoError)?;
+    Ok((Basic::Ed25519NT.derive(pub_key), priv_key))
+}
+
+fn witness_receipt(
+    prefix: &IdentifierPrefix,
+    sn: u64,
+    raw: &[u8],
+    witness: &(BasicPrefix, PrivateKey),
+) -> Result<String, Error> {
+    let rct = Event {
+        prefix: prefix.clone(),
+        sn,
+        event_data: EventData::Rct(ReceiptNonTransferable {
+            receipted_event_digest: SelfAddressing::Blake3_256.derive(raw),
+        }),
+    }
+    .to_message(SerializationFormats::JSON)?;
+    let signature = ed25519::Ed25519Sha512::new()
+        .sign(raw, &witness.1)
+        .map_err(Error::CryptoError)?;
+    let couplets = vec![(
+        witness.0.clone(),
+        SelfSigning::Ed25519Sha512.derive(signature),
+    )];
+
+    String::from_utf8(SignedNontransferableReceipt::new(&rct, couplets).serialize()?)
+        .map_err(|e| Error::SerializationError(e.to_string()))
+}
+
+#[test]
+fn test_witnessed_controller() -> Result<(), Error> {
+    let (w1, w2, w3) = (witness()?, witness()?, witness()?);
+
+    // Unwitnessed events need no receipts.
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+    assert!(bob.is_fully_witnessed(0)?);
+
+    // The toad has to be reachable by the witnesses.
+    let incept = |witnesses: Vec<BasicPrefix>, toad| {
+        Keri::incept_with_witnesses(
+            MemoryEventDatabase::new(),
+            CryptoBox::new()?,
+            1,
+            1,
+            witnesses,
+            toad,
+        )
+    };
+    assert!(incept(vec![w1.0.clone(), w2.0.clone()], 3).is_err());
+    assert!(incept(vec![w1.0.clone(), w2.0.clone()], 0).is_err());
+    assert!(incept(vec![w1.0.clone(), w1.0.clone()], 1).is_err());
+
+    // Init alice with two witnesses, both of which have to receipt.
+    let mut alice = incept(vec![w1.0.clone(), w2.0.clone()], 2)?;
+    assert_eq!(
+        alice.get_state().witnesses,
+        vec![w1.0.clone(), w2.0.clone()]
+    );
+    assert_eq!(alice.get_state().tally, 2);
+    assert!(!alice.is_fully_witnessed(0)?);
+
+    let icp = alice.get_state().last;
+    alice.process_events(witness_receipt(alice.get_prefix(), 0, &icp, &w1)?.as_bytes())?;
+    assert_eq!(alice.get_witness_receipts(0)?.len(), 1);
+    assert!(!alice.is_fully_witnessed(0)?);
+
+    alice.process_events(witness_receipt(alice.get_prefix(), 0, &icp, &w2)?.as_bytes())?;
+    assert_eq!(alice.get_witness_receipts(0)?.len(), 2);
+    assert!(alice.is_fully_witnessed(0)?);
+
+    // Witnesses can only be cut if they are witnesses, and added if they are not.
+    assert!(alice
+        .rotate_witnesses(vec![], vec![w3.0.clone()], 1)
+        .is_err());
+    assert!(alice
+        .rotate_witnesses(vec![w2.0.clone()], vec![], 1)
+        .is_err());
+    assert!(alice
+        .rotate_witnesses(vec![w3.0.clone()], vec![w1.0.clone()], 3)
+        .is_err());
+    assert_eq!(alice.get_state().sn, 0);
+
+    // Swap the first witness for the third one, either of which is enough.
+    alice.rotate_witnesses(vec![w3.0.clone()], vec![w1.0.clone()], 1)?;
+    assert_eq!(
+        alice.get_state().witnesses,
+        vec![w2.0.clone(), w3.0.clone()]
+    );
+    assert_eq!(alice.get_state().tally, 1);
+    assert!(!alice.is_fully_witnessed(1)?);
+
+    // A receipt of a witness which was cut doesn't count.
+    let rot = alice.get_state().last;
+    alice.process_events(witness_receipt(alice.get_prefix(), 1, &rot, &w1)?.as_bytes())?;
+    assert!(!alice.is_fully_witnessed(1)?);
+
+    alice.process_events(witness_receipt(alice.get_prefix(), 1, &rot, &w3)?.as_bytes())?;
+    assert!(alice.is_fully_witnessed(1)?);
+    assert!(alice.is_fully_witnessed(0)?);
+
+    // Rotating the keys keeps the witnesses and the toad.
+    alice.rotate()?;
+    assert_eq!(
+        alice.get_state().witnesses,
+        vec![w2.0.clone(), w3.0.clone()]
+    );
+    assert_eq!(alice.get_state().tally, 1);
+    assert!(!alice.is_fully_witnessed(2)?);
+
+    // Peers see the witnesses too.
+    let kel: Vec<u8> = alice
+        .processor
+        .get_kel(alice.get_prefix())?
+        .iter()
+        .map(SignedEvent::serialize)
+        .collect::<Vec<_>>()
+        .concat();
+    bob.process_events(&kel)?;
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_receipt_escrow() -> Result<(), Error> {
+    let w1 = witness()?;
+    let alice = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+    let carol = Keri::incept_with_witnesses(
+        MemoryEventDatabase::new(),
+        CryptoBox::new()?,
+        1,
+        1,
+        vec![w1.0.clone()],
+        1,
+    )?;
+    let icp = carol.get_state().last;
+    let icp_dig = SelfAddressing::Blake3_256.derive(&icp);
+
+    // A witness receipt of an event bob doesn't know yet is kept.
+    let rct = witness_receipt(carol.get_prefix(), 0, &icp, &w1)?;
+    assert_eq!(bob.process_events(rct.as_bytes())?, "");
+    assert!(bob
+        .processor
+        .get_witness_receipts(carol.get_prefix(), &icp_dig)?
+        .is_empty());
+
+    // And stored once the event arrives.
+    bob.process_events(carol.get_last_event()?.as_bytes())?;
+    assert_eq!(
+        bob.processor
+            .get_witness_receipts(carol.get_prefix(), &icp_dig)?,
+        vec![(
+            w1.0.clone(),
+            SelfSigning::Ed25519Sha512.derive(
+                ed25519::Ed25519Sha512::new()
+                    .sign(&icp, &w1.1)
+                    .map_err(Error::CryptoError)?
+            )
+        )]
+    );
+
+    // Alice's receipt of carol reaches bob before alice's KEL does.
+    let msg = alice.process_events(carol.get_last_event()?.as_bytes())?;
+    let vrc = signed_event_stream(msg.as_bytes())
+        .unwrap()
+        .1
+        .into_iter()
+        .find_map(|m| match m {
+            Deserialized::Vrc(vrc) => Some(vrc),
+            _ => None,
+        })
+        .unwrap();
+    bob.process_events(&vrc.serialize()?)?;
+    assert!(bob
+        .processor
+        .get_validator_receipts(carol.get_prefix(), &icp_dig)?
+        .is_empty());
+
+    // It is verified and stored once alice's inception arrives.
+    bob.process_events(alice.get_last_event()?.as_bytes())?;
+    let receipts = bob
+        .processor
+        .get_validator_receipts(carol.get_prefix(), &icp_dig)?;
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(&receipts[0].0, alice.get_prefix());
+
+    Ok(())
+}
+
+#[test]
+fn test_unimplemented_receipt_digest() -> Result<(), Error> {
+    use crate::{
+        event::{event_data::receipt::ReceiptTransferable, sections::seal::EventSeal},
+        prefix::{AttachedSignaturePrefix, SelfAddressingPrefix},
+    };
+
+    let w1 = witness()?;
+    let alice = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+    bob.process_events(alice.get_last_event()?.as_bytes())?;
+    let icp = alice.get_state().last;
+
+    // Receipts binding with an algorithm which isn't implemented
+    // are rejected, whether the receipted event is known or not.
+    for code in &[SelfAddressing::Blake2B256, SelfAddressing::Blake2S256] {
+        for sn in 0..2 {
+            let digest = SelfAddressingPrefix::new(*code, vec![0; 32]);
+            let rct = Event {
+                prefix: alice.get_prefix().clone(),
+                sn,
+                event_data: EventData::Rct(ReceiptNonTransferable {
+                    receipted_event_digest: digest.clone(),
+                }),
+            }
+            .to_message(SerializationFormats::JSON)?;
+            let signature = ed25519::Ed25519Sha512::new()
+                .sign(&icp, &w1.1)
+                .map_err(Error::CryptoError)?;
+            let rct = SignedNontransferableReceipt::new(
+                &rct,
+                vec![(w1.0.clone(), SelfSigning::Ed25519Sha512.derive(signature))],
+            );
+            assert_eq!(bob.process_events(&rct.serialize()?)?, "");
+
+            let vrc = Event {
+                prefix: alice.get_prefix().clone(),
+                sn,
+                event_data: EventData::Vrc(ReceiptTransferable {
+                    receipted_event_digest: digest,
+                    validator_location_seal: EventSeal {
+                        prefix: bob.get_prefix().clone(),
+                        event_digest: SelfAddressing::Blake3_256.derive(&bob.get_state().last),
+                    },
+                }),
+            }
+            .to_message(SerializationFormats::JSON)?
+            .sign(vec![AttachedSignaturePrefix::new(
+                SelfSigning::Ed25519Sha512,
+                vec![0; 64],
+                0,
+            )]);
+            assert_eq!(bob.process_events(&vrc.serialize()?)?, "");
+        }
     }
+    let icp_dig = SelfAddressing::Blake3_256.derive(&icp);
+    assert!(bob
+        .processor
+        .get_witness_receipts(alice.get_prefix(), &icp_dig)?
+        .is_empty());
+    assert!(bob
+        .processor
+        .get_validator_receipts(alice.get_prefix(), &icp_dig)?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_signatures() -> Result<(), Error> {
+    // Init a 2 of 3 alice and bob.
+    let mut alice = Keri::incept(MemoryEventDatabase::new(), CryptoBox::generate(3, 3)?, 2, 2)?;
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+
+    // Alice's inception with a single signature is not receipted.
+    let icp = signed_message(alice.get_last_event()?.as_bytes())
+        .map(|(_, m)| match m {
+            Deserialized::Event(ev) => SignedEventMessage::from(ev),
+            _ => unreachable!(),
+        })
+        .unwrap();
+    let partial = |ev: &SignedEventMessage, sigs: &[usize]| {
+        String::from_utf8(
+            SignedEventMessage::new(
+                &ev.event_message,
+                sigs.iter().map(|i| ev.signatures[*i].clone()).collect(),
+            )
+            .serialize()
+            .unwrap(),
+        )
+        .unwrap()
+    };
+    assert_eq!(bob.process_events(partial(&icp, &[0]).as_bytes())?, "");
+    assert_eq!(bob.get_state_for_prefix(alice.get_prefix())?, None);
+
+    // A second signature completes it, and then it is receipted.
+    let msg_to_alice = bob.process_events(partial(&icp, &[2]).as_bytes())?;
+    assert!(!msg_to_alice.is_empty());
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+    alice.process_events(msg_to_alice.as_bytes())?;
+    assert_eq!(alice.get_receipts(0)?.len(), 1);
+
+    // Signatures swapped between keys don't verify.
+    let ixn = alice.make_ixn("")?;
+    let mut swapped = SignedEventMessage::new(
+        &ixn.event_message,
+        vec![ixn.signatures[0].clone(), ixn.signatures[1].clone()],
+    );
+    swapped.signatures[0].index = 1;
+    swapped.signatures[1].index = 0;
+    assert_eq!(bob.process_events(&swapped.serialize()?)?, "");
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?.map(|s| s.sn),
+        Some(0)
+    );
+
+    // Nor do signatures of an other event.
+    let forged = SignedEventMessage::new(&ixn.event_message, icp.signatures.clone());
+    assert_eq!(bob.process_events(&forged.serialize()?)?, "");
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?.map(|s| s.sn),
+        Some(0)
+    );
+
+    // The threshold of signatures is receipted.
+    let msg_to_alice = bob.process_events(partial(&ixn, &[0, 1]).as_bytes())?;
+    assert!(!msg_to_alice.is_empty());
+    assert_eq!(
+        bob.get_state_for_prefix(alice.get_prefix())?,
+        Some(alice.get_state())
+    );
+    alice.process_events(msg_to_alice.as_bytes())?;
+    assert_eq!(alice.get_receipts(1)?.len(), 1);
+
+    Ok(())
+}
+
+#[cfg(feature = "lmdb")]
+#[test]
+fn test_reopen() -> Result<(), Error> {
+    use crate::database::lmdb::LmdbEventDatabase;
+    use std::fs;
+    use tempfile::Builder;
+
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+    fs::create_dir_all(root.path()).unwrap();
+
+    reopen(|| LmdbEventDatabase::new(root.path()).unwrap())
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_reopen_sqlite() -> Result<(), Error> {
+    use crate::database::sqlite::SqliteEventDatabase;
+    use tempfile::Builder;
+
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+    let path = root.path().join("events.sqlite");
+
+    reopen(|| SqliteEventDatabase::new(&path).unwrap())
+}
+
+#[cfg(any(feature = "lmdb", feature = "sqlite"))]
+fn reopen<D: crate::database::EventDatabase>(open_db: impl Fn() -> D) -> Result<(), Error> {
+    let bob = Keri::new(MemoryEventDatabase::new(), CryptoBox::new()?)?;
+
+    let (prefix, state, (current, next)) = {
+        let mut alice = Keri::incept(open_db(), CryptoBox::generate(3, 3)?, 2, 2)?;
+        alice.rotate()?;
+        alice.make_ixn("")?;
+        // Bob's KEL is stored alongside alice's.
+        alice.process_events(bob.get_last_event()?.as_bytes())?;
+
+        (
+            alice.get_prefix().clone(),
+            alice.get_state(),
+            alice.key_manager().export(),
+        )
+    };
+
+    // The reopened controller finds its own KEL by its saved keys.
+    let mut alice = Keri::open(open_db(), CryptoBox::import(current, next)?)?;
+    assert_eq!(alice.get_prefix(), &prefix);
+    assert_eq!(alice.get_state(), state);
+    assert_eq!(alice.get_log_len()?, 3);
+    assert_eq!(
+        alice.get_state_for_prefix(bob.get_prefix())?,
+        Some(bob.get_state())
+    );
+
+    // And carries on with its KEL under the committed next threshold.
+    alice.rotate()?;
+    assert_eq!(alice.get_state().sn, 3);
+    assert_eq!(alice.get_state().current.threshold, 2);
 
-    alice.process_events(&msg_to_alice.as_bytes())?;
-    assert_eq!(alice.receipts.len(), 3);
-    assert_eq!(alice.escrow_sigs.len(), 0);
+    // Keys which control no KEL in the database can't open it.
+    assert!(Keri::open(open_db(), CryptoBox::new()?).is_err());
 
     Ok(())
 }
@@ -90,13 +90,16 @@ pub fn basic_prefix(s: &[u8]) -> nom::IResult<&[u8], BasicPrefix> {
     })(s)?;
 
     let code: Basic = String::from_utf8(code_str.to_vec())
-        .map_err(|_| nom::Err::Failure((s, ErrorKind::IsNot)))?
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?
         .parse()
-        .map_err(|_| nom::Err::Failure((s, ErrorKind::IsNot)))?;
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?;
 
     let (extra, b) = take(code.derivative_b64_len())(rest)?;
 
-    Ok((extra, code.derive(PublicKey(b.to_vec()))))
+    let pk = base64::decode_config(b, base64::URL_SAFE)
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?;
+
+    Ok((extra, code.derive(PublicKey(pk))))
 }
 
 pub fn self_signing_prefix(s: &[u8]) -> nom::IResult<&[u8], SelfSigningPrefix> {
@@ -110,13 +113,16 @@ pub fn self_signing_prefix(s: &[u8]) -> nom::IResult<&[u8], SelfSigningPrefix> {
     })(s)?;
 
     let code: SelfSigning = String::from_utf8(code_str.to_vec())
-        .map_err(|_| nom::Err::Failure((s, ErrorKind::IsNot)))?
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?
         .parse()
-        .map_err(|_| nom::Err::Failure((s, ErrorKind::IsNot)))?;
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?;
 
     let (extra, b) = take(code.derivative_b64_len())(rest)?;
 
-    Ok((extra, code.derive(b.to_vec())))
+    let sig = base64::decode_config(b, base64::URL_SAFE)
+        .map_err(|_| nom::Err::Error((s, ErrorKind::IsNot)))?;
+
+    Ok((extra, code.derive(sig)))
 }
 
 #[test]
@@ -130,4 +136,17 @@ fn test() {
         attached_signature("BCAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".as_bytes()),
         Ok(("AA".as_bytes(), AttachedSignaturePrefix::new(SelfSigning::ECDSAsecp256k1Sha256, vec![0u8; 64], 2)))
     );
+
+    assert_eq!(
+        basic_prefix("BAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA0B".as_bytes()),
+        Ok((
+            "0B".as_bytes(),
+            Basic::Ed25519NT.derive(PublicKey(vec![0u8; 32]))
+        ))
+    );
+
+    assert_eq!(
+        self_signing_prefix("0BAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".as_bytes()),
+        Ok(("".as_bytes(), SelfSigning::Ed25519Sha512.derive(vec![0u8; 64])))
+    );
 }
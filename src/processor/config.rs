@@ -0,0 +1,259 @@
+use crate::{
+    database::INDEXED_DIGESTS,
+    derivation::{basic::Basic, self_addressing::SelfAddressing, self_signing::SelfSigning},
+    error::PolicyViolation,
+    event::{
+        event_data::{rotation::RotationEvent, EventData},
+        sections::{seal::Seal, KeyConfig},
+    },
+    event_message::{EventMessage, SignedEventMessage, SignedNontransferableReceipt},
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
+    state::IdentifierState,
+};
+
+/// Processor Config
+///
+/// The validation policy of an `EventProcessor`. Limits set to
+/// `None` are not enforced, so the default accepts every valid event
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessorConfig {
+    /// Derivations accepted for keys, witnesses and Basic prefixes
+    pub allowed_basic_codes: Option<Vec<Basic>>,
+    /// Derivations accepted for event and receipt signatures
+    pub allowed_self_signing_codes: Option<Vec<SelfSigning>>,
+    /// Derivations accepted for prefixes, digests and commitments
+    pub allowed_self_addressing_codes: Option<Vec<SelfAddressing>>,
+    /// Largest accepted serialized event, in bytes
+    pub max_event_size: Option<usize>,
+    /// Most current keys an establishment event may list
+    pub max_keys: Option<usize>,
+    /// Most witnesses an Identifier may have
+    pub max_witnesses: Option<usize>,
+    /// Smallest witness threshold an Identifier may have
+    pub min_toad: u64,
+    pub accept_non_transferable: bool,
+    pub accept_delegated: bool,
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        Self {
+            allowed_basic_codes: None,
+            allowed_self_signing_codes: None,
+            allowed_self_addressing_codes: None,
+            max_event_size: None,
+            max_keys: None,
+            max_witnesses: None,
+            min_toad: 0,
+            accept_non_transferable: true,
+            accept_delegated: true,
+        }
+    }
+}
+
+impl ProcessorConfig {
+    /// Check Event
+    ///
+    /// Checks a raw event and its signatures against the
+    /// rules which don't depend on the Identifier's state
+    pub fn check_event(
+        &self,
+        raw: &[u8],
+        event: &EventMessage,
+        sigs: &[AttachedSignaturePrefix],
+    ) -> Result<(), PolicyViolation> {
+        if let Some(max) = self.max_event_size {
+            if raw.len() > max {
+                return Err(PolicyViolation::EventTooLarge {
+                    size: raw.len(),
+                    max,
+                });
+            }
+        }
+        self.check_message(event)?;
+        sigs.iter()
+            .try_for_each(|sig| self.check_signature(&sig.signature))
+    }
+
+    /// Check State
+    ///
+    /// Checks the state an event would result in against
+    /// the witness rules, `min_toad` only applies to witnessed
+    /// Identifiers
+    pub fn check_state(&self, state: &IdentifierState) -> Result<(), PolicyViolation> {
+        if let Some(max) = self.max_witnesses {
+            if state.witnesses.len() > max {
+                return Err(PolicyViolation::TooManyWitnesses {
+                    count: state.witnesses.len(),
+                    max,
+                });
+            }
+        }
+        // an Identifier without witnesses has nothing to set a toad for
+        if !state.witnesses.is_empty() && state.tally < self.min_toad {
+            return Err(PolicyViolation::ToadTooLow {
+                toad: state.tally,
+                min: self.min_toad,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check Validator Receipt
+    pub fn check_validator_receipt(&self, vrc: &SignedEventMessage) -> Result<(), PolicyViolation> {
+        self.check_message(&vrc.event_message)?;
+        vrc.signatures
+            .iter()
+            .try_for_each(|sig| self.check_signature(&sig.signature))
+    }
+
+    /// Check Witness Receipt
+    pub fn check_witness_receipt(
+        &self,
+        rct: &SignedNontransferableReceipt,
+    ) -> Result<(), PolicyViolation> {
+        self.check_message(&rct.body)?;
+        rct.couplets.iter().try_for_each(|(witness, sig)| {
+            self.check_basic(witness)?;
+            self.check_signature(sig)
+        })
+    }
+
+    /// Indexed Digests
+    ///
+    /// The digest algorithms logged events are indexed by, besides
+    /// the one they commit with. Receipts by a digest with any other
+    /// algorithm are rejected, so indexing it would be wasted
+    pub fn indexed_digests(&self) -> Vec<SelfAddressing> {
+        match &self.allowed_self_addressing_codes {
+            Some(allowed) => INDEXED_DIGESTS
+                .iter()
+                .filter(|code| allowed.contains(code))
+                .cloned()
+                .collect(),
+            None => INDEXED_DIGESTS.to_vec(),
+        }
+    }
+
+    fn check_message(&self, message: &EventMessage) -> Result<(), PolicyViolation> {
+        self.check_identifier(&message.event.prefix)?;
+        match &message.event.event_data {
+            EventData::Icp(icp) => {
+                self.check_key_config(&icp.key_config)?;
+                self.check_witnesses(&icp.witness_config.initial_witnesses)
+            }
+            EventData::Dip(dip) => {
+                self.check_delegated()?;
+                self.check_identifier(&dip.seal.prefix)?;
+                self.check_digest(&dip.seal.prior_digest)?;
+                self.check_key_config(&dip.inception_data.key_config)?;
+                self.check_witnesses(&dip.inception_data.witness_config.initial_witnesses)
+            }
+            EventData::Rot(rot) => self.check_rotation(rot),
+            EventData::Drt(drt) => {
+                self.check_delegated()?;
+                self.check_digest(&drt.seal.prior_digest)?;
+                self.check_rotation(&drt.rotation_data)
+            }
+            EventData::Ixn(ixn) => {
+                self.check_digest(&ixn.previous_event_hash)?;
+                self.check_seals(&ixn.data)
+            }
+            EventData::Rct(rct) => self.check_digest(&rct.receipted_event_digest),
+            EventData::Vrc(vrc) => {
+                self.check_digest(&vrc.receipted_event_digest)?;
+                self.check_identifier(&vrc.validator_location_seal.prefix)?;
+                self.check_digest(&vrc.validator_location_seal.event_digest)
+            }
+        }
+    }
+
+    fn check_rotation(&self, rot: &RotationEvent) -> Result<(), PolicyViolation> {
+        self.check_digest(&rot.previous_event_hash)?;
+        self.check_key_config(&rot.key_config)?;
+        self.check_witnesses(&rot.witness_config.graft)?;
+        self.check_seals(&rot.data)
+    }
+
+    fn check_seals(&self, seals: &[Seal]) -> Result<(), PolicyViolation> {
+        seals.iter().try_for_each(|seal| match seal {
+            Seal::Location(ls) => self.check_digest(&ls.prior_digest),
+            Seal::Event(es) => self.check_digest(&es.event_digest),
+            Seal::Digest(ds) => self.check_digest(&ds.dig),
+            Seal::Root(rs) => self.check_digest(&rs.tree_root),
+        })
+    }
+
+    fn check_key_config(&self, key_config: &KeyConfig) -> Result<(), PolicyViolation> {
+        if let Some(max) = self.max_keys {
+            if key_config.public_keys.len() > max {
+                return Err(PolicyViolation::TooManyKeys {
+                    count: key_config.public_keys.len(),
+                    max,
+                });
+            }
+        }
+        self.check_witnesses(&key_config.public_keys)?;
+        self.check_digest(&key_config.threshold_key_digest)
+    }
+
+    fn check_witnesses(&self, prefixes: &[BasicPrefix]) -> Result<(), PolicyViolation> {
+        prefixes.iter().try_for_each(|p| self.check_basic(p))
+    }
+
+    fn check_identifier(&self, prefix: &IdentifierPrefix) -> Result<(), PolicyViolation> {
+        match prefix {
+            IdentifierPrefix::Basic(bp) => {
+                if !self.accept_non_transferable && !bp.derivation.is_transferable() {
+                    return Err(PolicyViolation::NonTransferable);
+                }
+                self.check_basic(bp)
+            }
+            IdentifierPrefix::SelfAddressing(sap) => self.check_digest(sap),
+            IdentifierPrefix::SelfSigning(ssp) => self.check_signature(ssp),
+        }
+    }
+
+    fn check_delegated(&self) -> Result<(), PolicyViolation> {
+        if self.accept_delegated {
+            Ok(())
+        } else {
+            Err(PolicyViolation::Delegated)
+        }
+    }
+
+    fn check_basic(&self, prefix: &BasicPrefix) -> Result<(), PolicyViolation> {
+        match &self.allowed_basic_codes {
+            Some(codes) if !codes.contains(&prefix.derivation) => {
+                Err(PolicyViolation::BasicCode(prefix.derivation))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_signature(&self, sig: &SelfSigningPrefix) -> Result<(), PolicyViolation> {
+        match &self.allowed_self_signing_codes {
+            Some(codes) if !codes.contains(&sig.derivation) => {
+                Err(PolicyViolation::SelfSigningCode(sig.derivation))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_digest(&self, digest: &SelfAddressingPrefix) -> Result<(), PolicyViolation> {
+        // digests are bound to events by computing them, which
+        // isn't possible for every derivation yet
+        if !digest.derivation.is_implemented() {
+            return Err(PolicyViolation::UnimplementedDigest(digest.derivation));
+        }
+        match &self.allowed_self_addressing_codes {
+            Some(codes) if !codes.contains(&digest.derivation) => {
+                Err(PolicyViolation::SelfAddressingCode(digest.derivation))
+            }
+            _ => Ok(()),
+        }
+    }
+}
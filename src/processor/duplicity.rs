@@ -0,0 +1,125 @@
+use crate::{
+    derivation::attached_signature_code::get_sig_count,
+    error::Error,
+    event_message::parse::{message, signed_event_stream, Deserialized},
+    prefix::{AttachedSignaturePrefix, IdentifierPrefix, Prefix},
+    state::IdentifierState,
+};
+
+/// Signed Event
+///
+/// The raw bytes of an event exactly as they were signed,
+/// with the signatures attached to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedEvent {
+    pub raw: Vec<u8>,
+    pub signatures: Vec<AttachedSignaturePrefix>,
+}
+
+impl SignedEvent {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            self.raw.clone(),
+            get_sig_count(self.signatures.len() as u16).into_bytes(),
+            self.signatures
+                .iter()
+                .map(|sig| sig.to_str().into_bytes())
+                .collect::<Vec<_>>()
+                .concat(),
+        ]
+        .concat()
+    }
+}
+
+/// Duplicity Evidence
+///
+/// Two different events signed for the same Identifier at the
+/// same sn. It serializes to a plain stream of the two signed
+/// events, so it can be handed to other validators, who can
+/// check it against their own state of the Identifier
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicityEvidence {
+    pub prefix: IdentifierPrefix,
+    pub sn: u64,
+    pub events: [SignedEvent; 2],
+}
+
+impl DuplicityEvidence {
+    pub fn serialize(&self) -> Vec<u8> {
+        [self.events[0].serialize(), self.events[1].serialize()].concat()
+    }
+
+    /// Parse
+    ///
+    /// Reads evidence from a stream of exactly two signed events
+    /// for the same Identifier and sn
+    pub fn parse(stream: &[u8]) -> Result<Self, Error> {
+        let (rest, messages) =
+            signed_event_stream(stream).map_err(|_| Error::DeserializationError)?;
+        if !rest.is_empty() {
+            return Err(Error::DeserializationError);
+        }
+
+        let events = messages
+            .into_iter()
+            .map(|m| match m {
+                Deserialized::Event(e) => Ok((
+                    e.event.event.event.prefix,
+                    e.event.event.event.sn,
+                    SignedEvent {
+                        raw: e.event.raw.to_vec(),
+                        signatures: e.signatures,
+                    },
+                )),
+                _ => Err(Error::SemanticError(
+                    "Evidence must only hold events".into(),
+                )),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        match events.as_slice() {
+            [(prefix, sn, first), (second_prefix, second_sn, second)]
+                if prefix == second_prefix && sn == second_sn =>
+            {
+                Ok(Self {
+                    prefix: prefix.clone(),
+                    sn: *sn,
+                    events: [first.clone(), second.clone()],
+                })
+            }
+            _ => Err(Error::SemanticError(
+                "Evidence must hold two events at the same sn".into(),
+            )),
+        }
+    }
+
+    /// Verify
+    ///
+    /// Checks that the two events differ and that both are properly
+    /// signed successors of the given state, which must be the state
+    /// of the Identifier just before sn
+    pub fn verify(&self, prior: &IdentifierState) -> Result<bool, Error> {
+        if self.events[0].raw == self.events[1].raw {
+            return Ok(false);
+        }
+
+        for event in &self.events {
+            let parsed = message(&event.raw)
+                .map_err(|_| Error::DeserializationError)?
+                .1;
+            if parsed.event.event.prefix != self.prefix || parsed.event.event.sn != self.sn {
+                return Ok(false);
+            }
+            let valid = prior
+                .clone()
+                .apply(&parsed.event)
+                .and_then(|state| state.current.verify(&event.raw, &event.signatures))
+                .unwrap_or(false);
+            if !valid {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
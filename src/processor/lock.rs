@@ -0,0 +1,54 @@
+use crate::prefix::{IdentifierPrefix, Prefix};
+use std::{
+    collections::HashSet,
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+/// Prefix Locks
+///
+/// Serialises the processing of each Identifier while letting
+/// different Identifiers be processed in parallel. Only the
+/// prefixes being processed are kept, so it does not grow
+/// with the number of known Identifiers
+#[derive(Default)]
+pub(crate) struct PrefixLocks {
+    busy: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+impl PrefixLocks {
+    /// Lock
+    ///
+    /// Blocks until no other thread holds the lock
+    /// for the prefix, then holds it until the guard
+    /// is dropped
+    pub fn lock(&self, prefix: &IdentifierPrefix) -> PrefixGuard<'_> {
+        let prefix = prefix.to_str();
+        let mut busy = self.busy();
+        while busy.contains(&prefix) {
+            busy = self.released.wait(busy).unwrap_or_else(|e| e.into_inner());
+        }
+        busy.insert(prefix.clone());
+        PrefixGuard {
+            locks: self,
+            prefix,
+        }
+    }
+
+    fn busy(&self) -> MutexGuard<'_, HashSet<String>> {
+        // the set is updated in single calls, so it is never left inconsistent
+        self.busy.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) struct PrefixGuard<'a> {
+    locks: &'a PrefixLocks,
+    prefix: String,
+}
+
+impl Drop for PrefixGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.busy().remove(&self.prefix);
+        self.locks.released.notify_all();
+    }
+}
@@ -1,26 +1,129 @@
 use crate::{
-    database::EventDatabase,
+    database::{
+        EscrowedNtReceipt, EscrowedTReceipt, EventDatabase, SequenceTable, WriteBatch, WriteOp,
+    },
     derivation::self_addressing::SelfAddressing,
     error::Error,
-    event::{event_data::EventData, sections::KeyConfig},
+    event::{
+        event_data::EventData,
+        sections::{seal::Seal, KeyConfig},
+    },
     event_message::{
-        parse::{message, Deserialized, DeserializedSignedEvent},
+        parse::{message, signed_message, Deserialized, DeserializedSignedEvent},
         EventMessage, SignedEventMessage, SignedNontransferableReceipt,
     },
-    prefix::{IdentifierPrefix, SelfAddressingPrefix},
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
     state::{EventSemantics, IdentifierState},
 };
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
 
+pub mod config;
+pub mod duplicity;
+mod lock;
+pub mod observer;
+pub mod outcome;
 #[cfg(test)]
 mod tests;
 
+use config::ProcessorConfig;
+use duplicity::{DuplicityEvidence, SignedEvent};
+use lock::PrefixLocks;
+use observer::ProcessorObserver;
+use outcome::{EscrowReason, EventId, ProcessingOutcome, StreamOutcome};
+
+/// Stream Receipt
+///
+/// The signatures carried by a receipt of a processed stream,
+/// kept to tell if the receipt got stored by the end of it
+enum StreamReceipt {
+    Witness(Vec<(BasicPrefix, SelfSigningPrefix)>),
+    Validator(IdentifierPrefix, Vec<AttachedSignaturePrefix>),
+}
+
+impl StreamReceipt {
+    fn of(message: &Deserialized) -> Option<Self> {
+        match message {
+            Deserialized::Event(_) => None,
+            Deserialized::Rct(rct) => Some(StreamReceipt::Witness(rct.couplets.clone())),
+            Deserialized::Vrc(vrc) => match &vrc.event_message.event.event_data {
+                EventData::Vrc(r) => Some(StreamReceipt::Validator(
+                    r.validator_location_seal.prefix.clone(),
+                    vrc.signatures.clone(),
+                )),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Discarded Receipt
+///
+/// An escrowed receipt which was dropped when the event
+/// it receipts was finalised, with the reason why
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscardedReceipt {
+    Nontransferable(EscrowedNtReceipt, DiscardReason),
+    Transferable(EscrowedTReceipt, DiscardReason),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiscardReason {
+    /// The receipt commits to a different event at the same sn
+    DigestMismatch { expected: SelfAddressingPrefix },
+    /// The receipt signature does not verify against the event
+    InvalidSignature,
+    /// The validator seal does not point at an establishment event
+    InvalidValidatorSeal,
+}
+
+/// Event Processor
+///
+/// Validates messages and commits their results to the database.
+/// It is `Send` and `Sync` when the database is, so it can be shared
+/// between threads: messages concerning different Identifiers are
+/// processed in parallel, and those concerning the same Identifier
+/// one at a time. Only one processor should write to a database
 pub struct EventProcessor<D: EventDatabase> {
     db: D,
+    config: ProcessorConfig,
+    witness_threshold: bool,
+    observers: Vec<Arc<dyn ProcessorObserver>>,
+    locks: PrefixLocks,
 }
 
 impl<D: EventDatabase> EventProcessor<D> {
-    pub fn new(db: D) -> Self {
-        Self { db }
+    pub fn new(db: D, config: ProcessorConfig) -> Self {
+        Self {
+            db,
+            config,
+            witness_threshold: false,
+            observers: vec![],
+            locks: PrefixLocks::default(),
+        }
+    }
+
+    /// Register Observer
+    ///
+    /// Adds an observer to be told about every change
+    /// committed by this processor
+    pub fn register_observer(&mut self, observer: Arc<dyn ProcessorObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Enforce Witness Threshold
+    ///
+    /// Holds the events of witnessed identifiers in the partially
+    /// witnessed escrow until at least toad of their designated
+    /// witnesses have receipted them
+    pub fn enforce_witness_threshold(self) -> Self {
+        Self {
+            witness_threshold: true,
+            ..self
+        }
     }
 
     /// Compute State for Prefix
@@ -28,11 +131,160 @@ impl<D: EventDatabase> EventProcessor<D> {
     /// Returns the current State associated with
     /// the given Prefix
     pub fn compute_state(&self, id: &IdentifierPrefix) -> Result<Option<IdentifierState>, Error> {
+        self.current_state(id)?
+            .map(|state| self.with_delegated_keys(state))
+            .transpose()
+    }
+
+    /// With Delegated Keys
+    ///
+    /// Fills in the Identifiers the State's Prefix had delegated to
+    /// as of the State's sn, known by the location seal of each
+    /// delegated inception
+    fn with_delegated_keys(&self, state: IdentifierState) -> Result<IdentifierState, Error> {
+        let mut delegated_keys = vec![];
+        for delegate in self
+            .db
+            .get_children_of_prefix(&state.prefix)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_default()
+        {
+            let raw = match self
+                .db
+                .last_event_at_sn(&delegate, 0)
+                .map_err(|_| Error::StorageError)?
+            {
+                Some(raw) => raw,
+                None => continue,
+            };
+            let parsed = message(&raw).map_err(|_| Error::DeserializationError)?.1;
+            if let EventData::Dip(dip) = parsed.event.event.event_data {
+                if dip.seal.sn <= state.sn {
+                    delegated_keys.push(delegate);
+                }
+            }
+        }
+
+        Ok(IdentifierState {
+            delegated_keys,
+            ..state
+        })
+    }
+
+    /// Current State
+    ///
+    /// Returns the cached current State of the given Prefix,
+    /// replaying its KEL if nothing usable is cached
+    fn current_state(&self, id: &IdentifierPrefix) -> Result<Option<IdentifierState>, Error> {
+        let cached = self
+            .db
+            .get_cached_state(id)
+            .map_err(|_| Error::StorageError)?;
+        if let Some((state, dig)) = cached {
+            // events finalised without updating the cache, like through
+            // `EventDatabase::finalise_event`, leave it behind the KEL
+            let behind = match state.sn.checked_add(1) {
+                Some(next) => self
+                    .db
+                    .last_event_at_sn(id, next)
+                    .map_err(|_| Error::StorageError)?
+                    .is_some(),
+                None => false,
+            };
+            let last = self
+                .db
+                .last_event_at_sn(id, state.sn)
+                .map_err(|_| Error::StorageError)?;
+            // and one built from an event which was superseded since,
+            // by recovery or a duplicitous replacement, is off the KEL
+            if let (false, Some(last)) = (behind, last) {
+                if dig.verify_binding(&last) {
+                    return Ok(Some(IdentifierState { last, ..state }));
+                }
+            }
+        }
+
+        self.replay_state_at_sn(id, u64::MAX)
+    }
+
+    /// Check State Cache
+    ///
+    /// Compares the cached current State of the given Prefix
+    /// with the State computed by replaying its KEL, true if
+    /// they match, up to the same event, or there is neither
+    pub fn check_state_cache(&self, id: &IdentifierPrefix) -> Result<bool, Error> {
+        let cached = self
+            .db
+            .get_cached_state(id)
+            .map_err(|_| Error::StorageError)?;
+        let replayed = self
+            .replay_state_at_sn(id, u64::MAX)?
+            .map(|state| -> Result<_, Error> {
+                Ok((
+                    IdentifierState {
+                        last: vec![],
+                        ..state.clone()
+                    },
+                    event_digest(&state.last)?,
+                ))
+            })
+            .transpose()?;
+
+        Ok(cached == replayed)
+    }
+
+    /// Rebuild State Cache
+    ///
+    /// Replays the KEL of the given Prefix and replaces
+    /// its cached current State with the result
+    pub fn rebuild_state_cache(
+        &self,
+        id: &IdentifierPrefix,
+    ) -> Result<Option<IdentifierState>, Error> {
+        let _lock = self.locks.lock(id);
+        let state = self.replay_state_at_sn(id, u64::MAX)?;
+        if let Some(state) = &state {
+            let cached = IdentifierState {
+                last: vec![],
+                ..state.clone()
+            };
+            let dig = event_digest(&state.last)?;
+            self.db
+                .commit(WriteOp::UpdateState { state: cached, dig }.into())
+                .map_err(|_| Error::StorageError)?;
+        }
+
+        Ok(state)
+    }
+
+    /// Compute State for Prefix at SN
+    ///
+    /// Returns the State associated with the given
+    /// Prefix after applying its events up to sn
+    pub fn compute_state_at_sn(
+        &self,
+        id: &IdentifierPrefix,
+        last_sn: u64,
+    ) -> Result<Option<IdentifierState>, Error> {
+        self.replay_state_at_sn(id, last_sn)?
+            .map(|state| self.with_delegated_keys(state))
+            .transpose()
+    }
+
+    /// Replay State at SN
+    ///
+    /// Applies the KEL events of the given Prefix up to sn,
+    /// without looking up its delegates
+    fn replay_state_at_sn(
+        &self,
+        id: &IdentifierPrefix,
+        last_sn: u64,
+    ) -> Result<Option<IdentifierState>, Error> {
         // start with empty state
         let mut state = IdentifierState::default();
 
         // starting from inception
-        for sn in 0.. {
+        for sn in 0..=last_sn {
             // read the latest raw event
             let raw = match self
                 .db
@@ -65,16 +317,91 @@ impl<D: EventDatabase> EventProcessor<D> {
         Ok(Some(state))
     }
 
+    /// Compute State for Prefix at Event
+    ///
+    /// Returns the State associated with the given Prefix
+    /// right after the KEL event represented by Event Digest,
+    /// or None if the KEL holds no such event
+    pub fn compute_state_at_event(
+        &self,
+        id: &IdentifierPrefix,
+        event_digest: &SelfAddressingPrefix,
+    ) -> Result<Option<IdentifierState>, Error> {
+        if !event_digest.derivation.is_implemented() {
+            return Ok(None);
+        }
+        for sn in 0.. {
+            match self
+                .db
+                .last_event_at_sn(id, sn)
+                .map_err(|_| Error::StorageError)?
+            {
+                Some(raw) if event_digest.verify_binding(&raw) => {
+                    return self.compute_state_at_sn(id, sn)
+                }
+                Some(_) => (),
+                None => break,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Compute State for Prefix at Time
+    ///
+    /// Returns the State associated with the given Prefix
+    /// after applying the KEL events first seen no later
+    /// than the given time
+    pub fn compute_state_at_time(
+        &self,
+        id: &IdentifierPrefix,
+        time: DateTime<Utc>,
+    ) -> Result<Option<IdentifierState>, Error> {
+        let mut last_sn = None;
+        for sn in 0.. {
+            let raw = match self
+                .db
+                .last_event_at_sn(id, sn)
+                .map_err(|_| Error::StorageError)?
+            {
+                Some(raw) => raw,
+                None => break,
+            };
+            let first_seen = match self
+                .db
+                .get_event_timestamp(id, &event_digest(&raw)?)
+                .map_err(|_| Error::StorageError)?
+            {
+                Some(dts) => {
+                    DateTime::parse_from_rfc3339(&dts).map_err(|_| Error::DeserializationError)?
+                }
+                None => break,
+            };
+            if first_seen > time {
+                break;
+            }
+            last_sn = Some(sn);
+        }
+
+        match last_sn {
+            Some(sn) => self.compute_state_at_sn(id, sn),
+            None => Ok(None),
+        }
+    }
+
     /// Get keys from Establishment Event
     ///
     /// Returns the current Key Config associated with
     /// the given Prefix at the establishment event
     /// represented by Event Digest
-    fn get_keys_at_event(
+    pub fn get_keys_at_event(
         &self,
         id: &IdentifierPrefix,
         event_digest: &SelfAddressingPrefix,
     ) -> Result<Option<KeyConfig>, Error> {
+        if !event_digest.derivation.is_implemented() {
+            return Ok(None);
+        }
         // starting from inception
         for sn in 0.. {
             // read the latest raw event
@@ -110,8 +437,9 @@ impl<D: EventDatabase> EventProcessor<D> {
 
     /// Process
     ///
-    /// Process a deserialized KERI message
-    pub fn process(&self, data: Deserialized) -> Result<Option<IdentifierState>, Error> {
+    /// Process a deserialized KERI message, returns what
+    /// became of it
+    pub fn process(&self, data: Deserialized) -> Result<ProcessingOutcome, Error> {
         match data {
             Deserialized::Event(e) => self.process_event(e),
             Deserialized::Vrc(r) => self.process_validator_receipt(r),
@@ -119,182 +447,1463 @@ impl<D: EventDatabase> EventProcessor<D> {
         }
     }
 
+    /// Process Stream
+    ///
+    /// Parses and processes the events, vrcs and rcts of the stream
+    /// one message at a time, then retries the escrows of the
+    /// Identifiers they concern. Returns one outcome per processed
+    /// message, escrowed events finalised and escrowed receipts
+    /// stored by the end of the stream are reported as accepted.
+    /// Processing stops at the first message which can't be parsed
+    /// or processed, it is returned untouched with the rest of the
+    /// stream. Errors processing a message or retrying escrows are
+    /// returned alongside the outcomes gathered until then
+    pub fn process_stream<'a>(&self, stream: &'a [u8]) -> Result<StreamOutcome<'a>, Error> {
+        let mut outcomes = vec![];
+        let mut receipts = vec![];
+        let mut unparsed = stream;
+        let mut error = None;
+        while !unparsed.is_empty() {
+            let (rest, message) = match signed_message(unparsed) {
+                Ok(parsed) => parsed,
+                Err(_) => break,
+            };
+            let receipt = StreamReceipt::of(&message);
+            match self.process(message) {
+                Ok(outcome) => {
+                    outcomes.push(outcome);
+                    receipts.push(receipt);
+                    unparsed = rest;
+                }
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        // the outcomes gathered so far are reported even if settling them fails
+        if let Err(e) = self.settle_stream(&mut outcomes, &receipts) {
+            error.get_or_insert(e);
+        }
+
+        Ok(StreamOutcome {
+            outcomes,
+            unparsed,
+            error,
+        })
+    }
+
+    /// Settle Stream
+    ///
+    /// Retries the escrows of the Identifiers a stream concerned,
+    /// then reports its escrowed messages which got finalised or
+    /// stored as accepted
+    fn settle_stream(
+        &self,
+        outcomes: &mut [ProcessingOutcome],
+        receipts: &[Option<StreamReceipt>],
+    ) -> Result<(), Error> {
+        let mut prefixes: Vec<IdentifierPrefix> = vec![];
+        for outcome in outcomes.iter() {
+            if !prefixes.contains(&outcome.event().prefix) {
+                prefixes.push(outcome.event().prefix.clone());
+            }
+        }
+        for prefix in &prefixes {
+            self.retry_escrows(prefix)?;
+        }
+
+        for (outcome, receipt) in outcomes.iter_mut().zip(receipts) {
+            let (event, reason) = match outcome {
+                ProcessingOutcome::Escrowed { event, reason } => (event, reason),
+                _ => continue,
+            };
+            let states = match (reason, receipt) {
+                (EscrowReason::MissingEvent, Some(receipt))
+                | (EscrowReason::UnknownValidator, Some(receipt)) => {
+                    if !self.is_receipt_stored(event, receipt)? {
+                        continue;
+                    }
+                    self.compute_state(&event.prefix)?
+                }
+                (EscrowReason::PartiallySigned, None)
+                | (EscrowReason::PartiallyWitnessed, None)
+                | (EscrowReason::OutOfOrder, None) => {
+                    if !self.is_finalised(event)? {
+                        continue;
+                    }
+                    self.compute_state_at_sn(&event.prefix, event.sn)?
+                }
+                _ => continue,
+            };
+            *outcome = ProcessingOutcome::Accepted {
+                states: states.into_iter().collect(),
+                event: event.clone(),
+                discarded_receipts: vec![],
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Is Receipt Stored
+    ///
+    /// Checks if any signature of the receipt is among the stored
+    /// receipts of the receipted event
+    fn is_receipt_stored(&self, event: &EventId, receipt: &StreamReceipt) -> Result<bool, Error> {
+        let dig = match self
+            .db
+            .resolve_digest(&event.prefix, &event.digest)
+            .map_err(|_| Error::StorageError)?
+        {
+            Some(dig) => dig,
+            None => return Ok(false),
+        };
+        Ok(match receipt {
+            StreamReceipt::Witness(couplets) => matches!(
+                self.db
+                    .get_nt_receipts_for_event(&event.prefix, &dig)
+                    .map_err(|_| Error::StorageError)?,
+                Some(stored) if couplets.iter().any(|c| stored.contains(c))
+            ),
+            StreamReceipt::Validator(validator, signatures) => matches!(
+                self.db
+                    .get_t_receipts_for_event(&event.prefix, &dig)
+                    .map_err(|_| Error::StorageError)?,
+                Some(stored) if stored
+                    .iter()
+                    .any(|(v, sig)| v == validator && signatures.contains(sig))
+            ),
+        })
+    }
+
+    /// Is Finalised
+    ///
+    /// Checks if the KEL holds the given event
+    fn is_finalised(&self, event: &EventId) -> Result<bool, Error> {
+        Ok(matches!(
+            self.db
+                .last_event_at_sn(&event.prefix, event.sn)
+                .map_err(|_| Error::StorageError)?,
+            Some(raw) if event.digest.verify_binding(&raw)
+        ))
+    }
+
     /// Process Event
     ///
     /// Validates a Key Event against the latest state
-    /// of the Identifier and applies it to update the state
-    /// returns the updated state
-    /// TODO improve checking and handling of errors!
+    /// of the Identifier and applies it to update the state,
+    /// then replays any out of order events it unblocks and
+    /// promotes the escrowed receipts of every finalised event
     pub fn process_event<'a>(
         &self,
         event: DeserializedSignedEvent<'a>,
-    ) -> Result<Option<IdentifierState>, Error> {
-        // extract some useful info from the event for readability
-        let dig = SelfAddressing::Blake3_256.derive(event.event.raw);
-        let pref = &event.event.event.event.prefix.clone();
-        let sn = event.event.event.event.sn;
-        let raw = &event.event.raw;
-        let sigs = event.signatures;
+    ) -> Result<ProcessingOutcome, Error> {
+        let message = &event.event.event;
+        let derivation = message.digest_derivation();
 
-        // Log event.
-        self.db
-            .log_event(&pref, &dig, raw, &sigs)
+        // events outside the policy are neither logged nor escrowed, the
+        // check comes first as it rules out digests which can't be computed
+        if let Err(violation) = self
+            .config
+            .check_event(event.event.raw, message, &event.signatures)
+        {
+            // the commit algorithm may be the reason, the event is
+            // then reported by its Blake3-256 digest
+            let derivation = if derivation.is_implemented() {
+                derivation
+            } else {
+                SelfAddressing::Blake3_256
+            };
+            let id = EventId {
+                prefix: message.event.prefix.clone(),
+                sn: message.event.sn,
+                digest: derivation.derive(event.event.raw),
+            };
+            return self.failure_outcome(id, violation.into());
+        }
+        let id = EventId {
+            prefix: message.event.prefix.clone(),
+            sn: message.event.sn,
+            digest: derivation.derive(event.event.raw),
+        };
+        let outcome = {
+            let _lock = self.locks.lock(&id.prefix);
+            match self.apply_and_commit(event.event.raw, event.event.event, event.signatures, None)
+            {
+                Ok(state) => self.process_escrows(id, state)?,
+                Err(e) => return self.failure_outcome(id, e),
+            }
+        };
+
+        // receipts made by the Identifier may have been waiting for its keys,
+        // they are promoted under the locks of the Identifiers they receipt
+        match outcome {
+            ProcessingOutcome::Accepted {
+                event,
+                states,
+                mut discarded_receipts,
+            } => {
+                discarded_receipts.append(&mut self.process_validator_escrow(&event.prefix)?);
+                Ok(ProcessingOutcome::Accepted {
+                    event,
+                    states,
+                    discarded_receipts,
+                })
+            }
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// Failure Outcome
+    ///
+    /// Sorts an event which was not finalised into escrowed,
+    /// duplicate, duplicitous or rejected. Errors which say
+    /// nothing about the event itself are passed on
+    fn failure_outcome(&self, event: EventId, error: Error) -> Result<ProcessingOutcome, Error> {
+        let escrowed = |event, reason| ProcessingOutcome::Escrowed { event, reason };
+
+        Ok(match error {
+            Error::StorageError | Error::DeserializationError => return Err(error),
+            Error::NotEnoughSigsError => escrowed(event, EscrowReason::PartiallySigned),
+            Error::NotEnoughReceiptsError => escrowed(event, EscrowReason::PartiallyWitnessed),
+            Error::EventOutOfOrderError => escrowed(event, EscrowReason::OutOfOrder),
+            Error::EventDuplicateError => ProcessingOutcome::Duplicate { event },
+            Error::DuplicitousEventError => ProcessingOutcome::Duplicitous {
+                evidence: self.duplicity_evidence(&event.prefix, event.sn, &event.digest)?,
+                event,
+            },
+            reason => ProcessingOutcome::Rejected { event, reason },
+        })
+    }
+
+    /// Process Escrows
+    ///
+    /// Replays the out of order events unblocked by a newly
+    /// finalised state and promotes the escrowed receipts of
+    /// every event finalised on the way
+    fn process_escrows(
+        &self,
+        event: EventId,
+        state: IdentifierState,
+    ) -> Result<ProcessingOutcome, Error> {
+        let mut states = self.process_out_of_order_escrow(&state)?;
+        states.insert(0, state);
+
+        let mut discarded_receipts = vec![];
+        for state in &states {
+            discarded_receipts.append(&mut self.process_receipt_escrow(state)?);
+        }
+
+        Ok(ProcessingOutcome::Accepted {
+            event,
+            states,
+            discarded_receipts,
+        })
+    }
+
+    /// Retry Escrows
+    ///
+    /// Replays every escrowed event of an Identifier in sn order,
+    /// returns the outcome of each one which was finalised. Failed
+    /// replays are taken out of the escrow or moved to a better one
+    pub fn retry_escrows(&self, id: &IdentifierPrefix) -> Result<Vec<ProcessingOutcome>, Error> {
+        let _lock = self.locks.lock(id);
+        let mut escrowed = vec![];
+        for escrow in [
+            SequenceTable::PartiallySigned,
+            SequenceTable::PartiallyWitnessed,
+            SequenceTable::OutOfOrder,
+        ] {
+            for e in self
+                .db
+                .get_escrowed_events(escrow, Some(id))
+                .map_err(|_| Error::StorageError)?
+            {
+                escrowed.push((escrow, e));
+            }
+        }
+        escrowed.sort_by_key(|(_, e)| e.sn);
+
+        let mut outcomes = vec![];
+        for (escrow, e) in escrowed {
+            let parsed = message(&e.raw).map_err(|_| Error::DeserializationError)?.1;
+            let event = EventId {
+                prefix: e.prefix,
+                sn: e.sn,
+                digest: e.digest,
+            };
+            // an earlier replay may have finalised it already
+            if self.is_finalised(&event)? {
+                continue;
+            }
+            if let Ok(state) =
+                self.apply_and_commit(&e.raw, parsed.event, e.signatures, Some(escrow))
+            {
+                outcomes.push(self.process_escrows(event, state)?);
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Process Out of Order Escrow
+    ///
+    /// Replays escrowed out of order events for the next sn
+    /// after the given state until no more apply, returns the
+    /// state after each one which did
+    fn process_out_of_order_escrow(
+        &self,
+        state: &IdentifierState,
+    ) -> Result<Vec<IdentifierState>, Error> {
+        let mut states: Vec<IdentifierState> = vec![];
+
+        loop {
+            let last = states.last().unwrap_or(state);
+            let escrowed = self
+                .db
+                .get_escrowed_events_at_sn(SequenceTable::OutOfOrder, &last.prefix, last.sn + 1)
+                .map_err(|_| Error::StorageError)?;
+
+            let mut next = None;
+            for e in escrowed {
+                let parsed = message(&e.raw).map_err(|_| Error::DeserializationError)?.1;
+                // a failed replay is taken out of the escrow, or moved to a better one
+                if let Ok(s) = self.apply_and_commit(
+                    &e.raw,
+                    parsed.event,
+                    e.signatures,
+                    Some(SequenceTable::OutOfOrder),
+                ) {
+                    next = Some(s);
+                    break;
+                }
+            }
+
+            match next {
+                Some(s) => states.push(s),
+                None => return Ok(states),
+            }
+        }
+    }
+
+    /// Process Receipt Escrow
+    ///
+    /// Verifies and stores the escrowed receipts for the event
+    /// the given state was finalised with. Receipts which commit
+    /// to another digest or fail to verify are discarded and
+    /// returned, receipts from unknown validators stay in escrow
+    fn process_receipt_escrow(
+        &self,
+        state: &IdentifierState,
+    ) -> Result<Vec<DiscardedReceipt>, Error> {
+        let raw = match self
+            .db
+            .last_event_at_sn(&state.prefix, state.sn)
+            .map_err(|_| Error::StorageError)?
+        {
+            Some(r) => r,
+            None => return Ok(vec![]),
+        };
+        let dig = event_digest(&raw)?;
+        let mismatch = |r: &SelfAddressingPrefix| {
+            if !r.derivation.is_implemented() || !r.verify_binding(&raw) {
+                Some(DiscardReason::DigestMismatch {
+                    expected: dig.clone(),
+                })
+            } else {
+                None
+            }
+        };
+
+        let mut batch = WriteBatch::new();
+        let mut discarded = vec![];
+
+        let nt_receipts = self
+            .db
+            .get_escrowed_nt_receipts(Some(&state.prefix))
             .map_err(|_| Error::StorageError)?;
+        for r in nt_receipts.into_iter().filter(|r| r.sn == state.sn) {
+            let reason = mismatch(&r.digest).or_else(|| {
+                if r.signer.verify(&raw, &r.signature).unwrap_or(false) {
+                    None
+                } else {
+                    Some(DiscardReason::InvalidSignature)
+                }
+            });
 
-        self.apply_to_state(event.event.event)
-            .and_then(|new_state| {
-                // match on verification result
-                new_state
-                    .current
-                    .verify(raw, &sigs)
-                    .and_then(|_result| {
-                        // TODO should check if there are enough receipts and probably escrow
-                        self.db
-                            .finalise_event(pref, sn, &dig)
-                            .map_err(|_| Error::StorageError)?;
-                        Ok(Some(new_state))
-                    })
-                    .map_err(|e| match e {
-                        Error::NotEnoughSigsError => {
-                            match self.db.escrow_partially_signed_event(pref, sn, &dig) {
-                                Ok(_) => e,
-                                Err(_) => Error::StorageError,
-                            }
-                        }
-                        _ => e,
-                    })
+            batch.push(WriteOp::RemoveNtReceipt(r.clone()));
+            match reason {
+                Some(reason) => discarded.push(DiscardedReceipt::Nontransferable(r, reason)),
+                None => {
+                    batch.push(WriteOp::NtReceipt {
+                        pref: r.prefix,
+                        dig: dig.clone(),
+                        signer: r.signer,
+                        sig: r.signature,
+                    });
+                }
+            }
+        }
+
+        let t_receipts = self
+            .db
+            .get_escrowed_t_receipts(Some(&state.prefix))
+            .map_err(|_| Error::StorageError)?;
+        for r in t_receipts.into_iter().filter(|r| r.sn == state.sn) {
+            if let Some(d) = self.promote_t_receipt(r, &raw, &dig, &mut batch) {
+                discarded.push(d);
+            }
+        }
+
+        if !batch.is_empty() {
+            let event = EventId {
+                prefix: state.prefix.clone(),
+                sn: state.sn,
+                digest: dig,
+            };
+            self.commit(&event, batch)?;
+        }
+
+        Ok(discarded)
+    }
+
+    /// Promote Transferable Receipt
+    ///
+    /// Checks an escrowed validator receipt against the receipted
+    /// event, adding ops to the batch which store it or discard it.
+    /// Receipts whose validator has no matching establishment event
+    /// yet are left in the escrow
+    fn promote_t_receipt(
+        &self,
+        r: EscrowedTReceipt,
+        raw: &[u8],
+        dig: &SelfAddressingPrefix,
+        batch: &mut WriteBatch,
+    ) -> Option<DiscardedReceipt> {
+        let reason = if !r.digest.derivation.is_implemented() || !r.digest.verify_binding(raw) {
+            Some(DiscardReason::DigestMismatch {
+                expected: dig.clone(),
             })
-            .map_err(|e| {
-                match e {
-                    // see why application failed and reject or escrow accordingly
-                    Error::EventOutOfOrderError => {
-                        self.db.escrow_out_of_order_event(pref, sn, &dig)
+        } else {
+            match self.get_keys_at_event(&r.validator_seal.prefix, &r.validator_seal.event_digest) {
+                // wait for the validator's establishment event
+                Ok(None) => return None,
+                Ok(Some(keys)) => {
+                    if keys
+                        .valid_signatures(raw, std::slice::from_ref(&r.signature))
+                        .is_empty()
+                    {
+                        Some(DiscardReason::InvalidSignature)
+                    } else {
+                        None
                     }
-                    Error::EventDuplicateError => self.db.duplicitous_event(pref, sn, &dig),
-                    _ => Ok(()),
+                }
+                Err(_) => Some(DiscardReason::InvalidValidatorSeal),
+            }
+        };
+
+        batch.push(WriteOp::RemoveTReceipt(r.clone()));
+        match reason {
+            Some(reason) => Some(DiscardedReceipt::Transferable(r, reason)),
+            None => {
+                batch.push(WriteOp::TReceipt {
+                    pref: r.prefix,
+                    dig: dig.clone(),
+                    signer: r.validator_seal.prefix,
+                    sig: r.signature,
+                });
+                None
+            }
+        }
+    }
+
+    /// Process Validator Escrow
+    ///
+    /// Promotes the escrowed receipts made by the validator whose
+    /// receipted events are known, used once the validator has a
+    /// new establishment event. Returns the discarded ones
+    fn process_validator_escrow(
+        &self,
+        validator: &IdentifierPrefix,
+    ) -> Result<Vec<DiscardedReceipt>, Error> {
+        let mut receipted: Vec<IdentifierPrefix> = vec![];
+        for r in self
+            .db
+            .get_escrowed_t_receipts(None)
+            .map_err(|_| Error::StorageError)?
+        {
+            if &r.validator_seal.prefix == validator && !receipted.contains(&r.prefix) {
+                receipted.push(r.prefix);
+            }
+        }
+
+        let mut discarded = vec![];
+        for pref in receipted {
+            let _lock = self.locks.lock(&pref);
+            let escrowed = self
+                .db
+                .get_escrowed_t_receipts(Some(&pref))
+                .map_err(|_| Error::StorageError)?;
+            for r in escrowed
+                .into_iter()
+                .filter(|r| &r.validator_seal.prefix == validator)
+            {
+                let raw = match self
+                    .db
+                    .last_event_at_sn(&pref, r.sn)
+                    .map_err(|_| Error::StorageError)?
+                {
+                    Some(raw) => raw,
+                    // still waiting for the receipted event
+                    None => continue,
+                };
+                let event = EventId {
+                    prefix: pref.clone(),
+                    sn: r.sn,
+                    digest: event_digest(&raw)?,
                 };
-                e
+                let mut batch = WriteBatch::new();
+                if let Some(d) = self.promote_t_receipt(r, &raw, &event.digest, &mut batch) {
+                    discarded.push(d);
+                }
+                if !batch.is_empty() {
+                    self.commit_resolving(&event, batch, EscrowReason::UnknownValidator)?;
+                }
+            }
+        }
+
+        Ok(discarded)
+    }
+
+    /// Apply and Commit
+    ///
+    /// Validates an event and commits the result: the event is
+    /// finalised, escrowed or marked according to the outcome.
+    /// Escrow is the escrow the event is being replayed from, if any
+    fn apply_and_commit(
+        &self,
+        raw: &[u8],
+        event: EventMessage,
+        sigs: Vec<AttachedSignaturePrefix>,
+        escrow: Option<SequenceTable>,
+    ) -> Result<IdentifierState, Error> {
+        // extract some useful info from the event for readability
+        let dig = event.digest_derivation().derive(raw);
+        let pref = &event.event.prefix.clone();
+        let sn = event.event.sn;
+        // a dip names its delegator in the location seal
+        let delegator = match &event.event.event_data {
+            EventData::Dip(dip) => Some(dip.seal.prefix.clone()),
+            _ => None,
+        };
+
+        // every side effect of processing the event is committed together
+        let mut batch = WriteBatch::new();
+        if let Some(escrow) = escrow {
+            batch.push(WriteOp::remove(escrow, pref, sn, &dig));
+        }
+
+        let mut sigs = sigs;
+        let mut superseded = vec![];
+        let result = self
+            .apply_to_state(&event)
+            .or_else(|e| match e {
+                // a rotation with a lower sn may be a recovery
+                Error::EventDuplicateError => {
+                    let (state, replaced) = self.apply_recovery(&event)?;
+                    superseded = replaced;
+                    Ok(state)
+                }
+                e => Err(e),
             })
+            .and_then(|new_state| {
+                self.config.check_state(&new_state)?;
+                // combine with the signatures gathered for this event so far
+                sigs = self.accumulate_signatures(pref, &dig, raw, &sigs, &new_state.current)?;
+                // match on verification result
+                new_state.current.verify(raw, &sigs)?;
+                self.validate_delegation(&event, raw)?;
+                if self.witness_threshold && new_state.tally > 0 {
+                    let receipted =
+                        self.collect_witness_receipts(pref, sn, &dig, raw, &mut batch)?;
+                    let witnessed = new_state
+                        .witnesses
+                        .iter()
+                        .filter(|w| receipted.contains(w))
+                        .count();
+                    if (witnessed as u64) < new_state.tally {
+                        return Err(Error::NotEnoughReceiptsError);
+                    }
+                }
+                Ok(new_state)
+            });
+
+        let result = match result {
+            Err(Error::EventDuplicateError) => {
+                Err(self.classify_duplicate(pref, sn, &dig, raw, &event, &sigs)?)
+            }
+            r => r,
+        };
+
+        // replayed events were logged when they were escrowed,
+        // and a resubmitted finalised event is already logged
+        if escrow.is_none()
+            && !matches!(
+                result,
+                Err(Error::EventDuplicateError) | Err(Error::PolicyViolation(_))
+            )
+        {
+            batch.push(WriteOp::log_event(
+                pref,
+                &dig,
+                raw,
+                &sigs,
+                &self.config.indexed_digests(),
+            ));
+        }
+
+        match &result {
+            Ok(state) => {
+                // the last event is in the KEL already and delegates
+                // are tracked apart from it, so neither is cached
+                batch.push(WriteOp::UpdateState {
+                    state: IdentifierState {
+                        last: vec![],
+                        delegated_keys: vec![],
+                        ..state.clone()
+                    },
+                    dig: dig.clone(),
+                });
+                // superseded events leave the KEL but are kept for audit
+                for (sn, dig) in &superseded {
+                    batch.push(WriteOp::remove(SequenceTable::KeyEventLog, pref, *sn, dig));
+                    batch.push(WriteOp::insert(SequenceTable::Superseded, pref, *sn, dig));
+                }
+                batch.push(WriteOp::insert(SequenceTable::KeyEventLog, pref, sn, &dig));
+                // the event may have been waiting in an escrow
+                batch.push(WriteOp::remove(
+                    SequenceTable::PartiallySigned,
+                    pref,
+                    sn,
+                    &dig,
+                ));
+                batch.push(WriteOp::remove(
+                    SequenceTable::PartiallyWitnessed,
+                    pref,
+                    sn,
+                    &dig,
+                ));
+                batch.push(WriteOp::remove(SequenceTable::OutOfOrder, pref, sn, &dig));
+                if let Some(delegator) = delegator {
+                    batch.push(WriteOp::LogDelegation {
+                        delegator,
+                        delegate: pref.clone(),
+                    });
+                }
+            }
+            // see why application failed and reject or escrow accordingly
+            Err(Error::NotEnoughSigsError) => {
+                batch.push(WriteOp::insert(
+                    SequenceTable::PartiallySigned,
+                    pref,
+                    sn,
+                    &dig,
+                ));
+            }
+            Err(Error::NotEnoughReceiptsError) => {
+                batch.push(WriteOp::insert(
+                    SequenceTable::PartiallyWitnessed,
+                    pref,
+                    sn,
+                    &dig,
+                ));
+            }
+            Err(Error::EventOutOfOrderError) => {
+                batch.push(WriteOp::insert(SequenceTable::OutOfOrder, pref, sn, &dig));
+            }
+            Err(Error::DuplicitousEventError) => {
+                batch.push(WriteOp::insert(SequenceTable::Duplicitous, pref, sn, &dig));
+            }
+            Err(Error::LikelyDuplicitousEventError) => {
+                batch.push(WriteOp::insert(
+                    SequenceTable::LikelyDuplicitous,
+                    pref,
+                    sn,
+                    &dig,
+                ));
+            }
+            Err(_) => (),
+        };
+
+        let event = EventId {
+            prefix: pref.clone(),
+            sn,
+            digest: dig,
+        };
+        let held = self.escrows_holding(&event)?;
+        self.commit(&event, batch)?;
+
+        let escrowed = match &result {
+            Err(Error::NotEnoughSigsError) => Some(EscrowReason::PartiallySigned),
+            Err(Error::NotEnoughReceiptsError) => Some(EscrowReason::PartiallyWitnessed),
+            Err(Error::EventOutOfOrderError) => Some(EscrowReason::OutOfOrder),
+            _ => None,
+        };
+        // an event moved to another escrow is past the reason it was held for
+        if result.is_ok() || escrowed.is_some() {
+            for reason in held.iter().filter(|r| Some(**r) != escrowed) {
+                self.notify(|o| o.escrow_resolved(&event, *reason));
+            }
+        }
+        match (&result, escrowed) {
+            (Ok(state), _) => self.notify(|o| o.event_finalised(&event, state)),
+            (_, Some(reason)) if !held.contains(&reason) => {
+                self.notify(|o| o.event_escrowed(&event, reason))
+            }
+            (Err(Error::DuplicitousEventError), _) if !self.observers.is_empty() => {
+                let evidence = self.duplicity_evidence(pref, sn, &event.digest)?;
+                self.notify(|o| o.duplicity_detected(&evidence));
+            }
+            _ => (),
+        };
+
+        result
+    }
+
+    /// Commit
+    ///
+    /// Commits a batch of changes concerning the event, then tells
+    /// the observers about the receipts of it which were stored
+    fn commit(&self, event: &EventId, batch: WriteBatch) -> Result<(), Error> {
+        self.commit_resolving(event, batch, EscrowReason::MissingEvent)
+    }
+
+    /// Commit Resolving
+    ///
+    /// Commits as `commit` does, with the reason the promoted
+    /// receipts in the batch were escrowed for
+    fn commit_resolving(
+        &self,
+        event: &EventId,
+        batch: WriteBatch,
+        reason: EscrowReason,
+    ) -> Result<(), Error> {
+        let signers: Vec<IdentifierPrefix> = batch
+            .iter()
+            .filter_map(|op| match op {
+                WriteOp::NtReceipt { signer, .. } => Some(IdentifierPrefix::Basic(signer.clone())),
+                WriteOp::TReceipt { signer, .. } => Some(signer.clone()),
+                _ => None,
+            })
+            .collect();
+        // stored receipts which are also taken out of escrow were promoted
+        let promoted = batch
+            .iter()
+            .any(|op| matches!(op, WriteOp::RemoveNtReceipt(_) | WriteOp::RemoveTReceipt(_)));
+
+        self.db.commit(batch).map_err(|_| Error::StorageError)?;
+
+        if promoted && !signers.is_empty() {
+            self.notify(|o| o.escrow_resolved(event, reason));
+        }
+        for signer in &signers {
+            self.notify(|o| o.receipt_added(event, signer));
+        }
+        Ok(())
+    }
+
+    fn notify(&self, f: impl Fn(&dyn ProcessorObserver)) {
+        self.observers.iter().for_each(|o| f(o.as_ref()));
+    }
+
+    /// Escrows Holding
+    ///
+    /// Lists the reasons the event is escrowed for, only
+    /// looked up when there are observers to tell
+    fn escrows_holding(&self, event: &EventId) -> Result<Vec<EscrowReason>, Error> {
+        if self.observers.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut held = vec![];
+        for (escrow, reason) in [
+            (
+                SequenceTable::PartiallySigned,
+                EscrowReason::PartiallySigned,
+            ),
+            (
+                SequenceTable::PartiallyWitnessed,
+                EscrowReason::PartiallyWitnessed,
+            ),
+            (SequenceTable::OutOfOrder, EscrowReason::OutOfOrder),
+        ] {
+            if self
+                .db
+                .get_escrowed_events_at_sn(escrow, &event.prefix, event.sn)
+                .map_err(|_| Error::StorageError)?
+                .iter()
+                .any(|e| e.digest == event.digest)
+            {
+                held.push(reason);
+            }
+        }
+        Ok(held)
+    }
+
+    /// Validate Delegation
+    ///
+    /// Checks that the location seal of a delegated event points
+    /// at an event in the delegator's KEL which anchors it
+    fn validate_delegation(&self, event: &EventMessage, raw: &[u8]) -> Result<(), Error> {
+        let seal = match &event.event.event_data {
+            EventData::Dip(dip) => &dip.seal,
+            EventData::Drt(drt) => &drt.seal,
+            _ => return Ok(()),
+        };
+
+        let delegating = self
+            .db
+            .last_event_at_sn(&seal.prefix, seal.sn)
+            .map_err(|_| Error::StorageError)?
+            .ok_or(Error::MissingDelegatingEventError)?;
+        let parsed = message(&delegating)
+            .map_err(|_| Error::DeserializationError)?
+            .1;
+        let (ilk, prior_digest, data) = match parsed.event.event.event_data {
+            EventData::Ixn(ixn) => ("ixn", ixn.previous_event_hash, ixn.data),
+            EventData::Rot(rot) => ("rot", rot.previous_event_hash, rot.data),
+            EventData::Drt(drt) => (
+                "drt",
+                drt.rotation_data.previous_event_hash,
+                drt.rotation_data.data,
+            ),
+            _ => {
+                return Err(Error::SemanticError(
+                    "Delegating event can't anchor seals".into(),
+                ))
+            }
+        };
+
+        if seal.ilk != ilk || seal.prior_digest != prior_digest {
+            return Err(Error::SemanticError(
+                "Location seal does not match delegating event".into(),
+            ));
+        }
+        let anchored = data.iter().any(|s| match s {
+            Seal::Event(es) => {
+                es.prefix == event.event.prefix && es.event_digest.verify_binding(raw)
+            }
+            _ => false,
+        });
+        if anchored {
+            Ok(())
+        } else {
+            Err(Error::SemanticError(
+                "Delegated event is not anchored by the delegator".into(),
+            ))
+        }
+    }
+
+    /// Apply Recovery
+    ///
+    /// Applies a rotation with a lower sn than the current state,
+    /// which is only allowed when every event from that sn on is an
+    /// interaction event. Returns the recovered state and the sn and
+    /// digest of each event it supersedes
+    fn apply_recovery(
+        &self,
+        event: &EventMessage,
+    ) -> Result<(IdentifierState, Vec<(u64, SelfAddressingPrefix)>), Error> {
+        let pref = &event.event.prefix;
+        let sn = event.event.sn;
+        match event.event.event_data {
+            EventData::Rot(_) | EventData::Drt(_) if sn > 0 => (),
+            _ => return Err(Error::EventDuplicateError),
+        };
+
+        let mut superseded = vec![];
+        for i in sn.. {
+            let raw = match self
+                .db
+                .last_event_at_sn(pref, i)
+                .map_err(|_| Error::StorageError)?
+            {
+                Some(r) => r,
+                None => break,
+            };
+            let parsed = message(&raw).map_err(|_| Error::DeserializationError)?.1;
+            match parsed.event.event.event_data {
+                EventData::Ixn(_) => {
+                    superseded.push((i, parsed.event.digest_derivation().derive(&raw)))
+                }
+                // establishment events can't be superseded
+                _ => return Err(Error::EventDuplicateError),
+            }
+        }
+
+        let prior = self
+            .replay_state_at_sn(pref, sn - 1)?
+            .ok_or(Error::EventDuplicateError)?;
+        Ok((prior.apply(event)?, superseded))
+    }
+
+    /// Classify Duplicate
+    ///
+    /// Tells a resubmission of the finalised event at sn apart from a
+    /// different event for the same sn. The latter is duplicitous when
+    /// it is properly signed by the keys it was bound to, otherwise
+    /// it is only likely duplicitous
+    fn classify_duplicate(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+        raw: &[u8],
+        event: &EventMessage,
+        sigs: &[AttachedSignaturePrefix],
+    ) -> Result<Error, Error> {
+        if let Some(finalised) = self
+            .db
+            .last_event_at_sn(pref, sn)
+            .map_err(|_| Error::StorageError)?
+        {
+            if dig.verify_binding(&finalised) {
+                return Ok(Error::EventDuplicateError);
+            }
+        }
+
+        let prior = if sn == 0 {
+            Some(IdentifierState::default())
+        } else {
+            self.replay_state_at_sn(pref, sn - 1)?
+        };
+        let verified = prior
+            .and_then(|state| state.apply(event).ok())
+            .map_or(false, |state| {
+                state.current.verify(raw, sigs).unwrap_or(false)
+            });
+
+        Ok(if verified {
+            Error::DuplicitousEventError
+        } else {
+            Error::LikelyDuplicitousEventError
+        })
+    }
+
+    /// Get Duplicity Evidence
+    ///
+    /// Returns evidence for every event recorded as duplicitous
+    /// for the given Prefix, each paired with the finalised
+    /// event at the same sn
+    pub fn get_duplicity_evidence(
+        &self,
+        id: &IdentifierPrefix,
+    ) -> Result<Vec<DuplicityEvidence>, Error> {
+        self.db
+            .get_sequence_entries(SequenceTable::Duplicitous, Some(id))
+            .map_err(|_| Error::StorageError)?
+            .into_iter()
+            .map(|(_, sn, dig)| self.duplicity_evidence(id, sn, &dig))
+            .collect()
+    }
+
+    fn duplicity_evidence(
+        &self,
+        id: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<DuplicityEvidence, Error> {
+        let finalised = self
+            .db
+            .last_event_at_sn(id, sn)
+            .map_err(|_| Error::StorageError)?
+            .ok_or(Error::StorageError)?;
+
+        Ok(DuplicityEvidence {
+            prefix: id.clone(),
+            sn,
+            events: [
+                self.get_signed_event(id, &event_digest(&finalised)?)?,
+                self.get_signed_event(id, dig)?,
+            ],
+        })
+    }
+
+    /// Verify Duplicity Evidence
+    ///
+    /// Checks evidence received from another validator
+    /// against the local KEL of the Identifier it concerns
+    pub fn verify_duplicity_evidence(&self, evidence: &DuplicityEvidence) -> Result<bool, Error> {
+        // evidence outside the policy proves nothing, and may carry
+        // digests which can't be computed
+        for event in &evidence.events {
+            let parsed = message(&event.raw)
+                .map_err(|_| Error::DeserializationError)?
+                .1;
+            if self
+                .config
+                .check_event(&event.raw, &parsed.event, &event.signatures)
+                .is_err()
+            {
+                return Ok(false);
+            }
+        }
+        let prior = if evidence.sn == 0 {
+            Some(IdentifierState::default())
+        } else {
+            self.replay_state_at_sn(&evidence.prefix, evidence.sn - 1)?
+        };
+
+        match prior {
+            Some(state) if evidence.sn == 0 || state.sn == evidence.sn - 1 => {
+                evidence.verify(&state)
+            }
+            // the KEL does not reach the sn of the evidence
+            _ => Ok(false),
+        }
+    }
+
+    /// Get Signed Event
+    ///
+    /// Returns a logged event with its signatures, given
+    /// its digest with any of the indexed algorithms
+    pub fn get_signed_event(
+        &self,
+        id: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<SignedEvent, Error> {
+        let dig = &self
+            .db
+            .resolve_digest(id, dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_else(|| dig.clone());
+
+        Ok(SignedEvent {
+            raw: self
+                .db
+                .get_event(id, dig)
+                .map_err(|_| Error::StorageError)?
+                .ok_or(Error::StorageError)?,
+            signatures: self
+                .db
+                .get_signatures_for_event(id, dig)
+                .map_err(|_| Error::StorageError)?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Get KEL
+    ///
+    /// Returns the finalised events of the given Prefix
+    /// with their signatures, in sn order
+    pub fn get_kel(&self, id: &IdentifierPrefix) -> Result<Vec<SignedEvent>, Error> {
+        self.db
+            .get_sequence_entries(SequenceTable::KeyEventLog, Some(id))
+            .map_err(|_| Error::StorageError)?
+            .into_iter()
+            .map(|(_, _, dig)| self.get_signed_event(id, &dig))
+            .collect()
+    }
+
+    /// Get Prefixes
+    ///
+    /// Lists every Identifier with a KEL
+    pub fn get_prefixes(&self) -> Result<Vec<IdentifierPrefix>, Error> {
+        let mut prefixes: Vec<IdentifierPrefix> = vec![];
+        for (prefix, _, _) in self
+            .db
+            .get_sequence_entries(SequenceTable::KeyEventLog, None)
+            .map_err(|_| Error::StorageError)?
+        {
+            if prefixes.last() != Some(&prefix) {
+                prefixes.push(prefix);
+            }
+        }
+        Ok(prefixes)
+    }
+
+    /// Get Witness Receipts
+    ///
+    /// Returns the stored witness receipt couplets of an
+    /// event, given its digest with any indexed algorithm
+    pub fn get_witness_receipts(
+        &self,
+        id: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Vec<(BasicPrefix, SelfSigningPrefix)>, Error> {
+        let dig = self
+            .db
+            .resolve_digest(id, dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_else(|| dig.clone());
+        Ok(self
+            .db
+            .get_nt_receipts_for_event(id, &dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_default())
+    }
+
+    /// Get Validator Receipts
+    ///
+    /// Returns the stored validator receipt signatures of an
+    /// event with their validators, given its digest with
+    /// any indexed algorithm
+    pub fn get_validator_receipts(
+        &self,
+        id: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+    ) -> Result<Vec<(IdentifierPrefix, AttachedSignaturePrefix)>, Error> {
+        let dig = self
+            .db
+            .resolve_digest(id, dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_else(|| dig.clone());
+        Ok(self
+            .db
+            .get_t_receipts_for_event(id, &dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_default())
+    }
+
+    /// Collect Witness Receipts
+    ///
+    /// Returns the witnesses which have receipted the event,
+    /// adding ops to the batch which store the valid escrowed
+    /// receipts for it
+    fn collect_witness_receipts(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+        dig: &SelfAddressingPrefix,
+        raw: &[u8],
+        batch: &mut WriteBatch,
+    ) -> Result<Vec<BasicPrefix>, Error> {
+        let mut witnesses: Vec<BasicPrefix> = self
+            .db
+            .get_nt_receipts_for_event(pref, dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(witness, _)| witness)
+            .collect();
+
+        let escrowed = self
+            .db
+            .get_escrowed_nt_receipts(Some(pref))
+            .map_err(|_| Error::StorageError)?;
+        for r in escrowed.into_iter().filter(|r| {
+            r.sn == sn && r.digest.derivation.is_implemented() && r.digest.verify_binding(raw)
+        }) {
+            // invalid receipts are left for the receipt escrow to discard
+            if r.signer.verify(raw, &r.signature).unwrap_or(false) {
+                witnesses.push(r.signer.clone());
+                batch.push(WriteOp::RemoveNtReceipt(r.clone()));
+                batch.push(WriteOp::NtReceipt {
+                    pref: r.prefix,
+                    dig: dig.clone(),
+                    signer: r.signer,
+                    sig: r.signature,
+                });
+            }
+        }
+
+        Ok(witnesses)
+    }
+
+    /// Process Partially Witnessed Escrow
+    ///
+    /// Replays the partially witnessed events at sn, used when a
+    /// new witness receipt arrives for one of them. Returns the
+    /// outcome for the event if there is one in the escrow
+    fn process_partially_witnessed_escrow(
+        &self,
+        pref: &IdentifierPrefix,
+        sn: u64,
+    ) -> Result<Option<ProcessingOutcome>, Error> {
+        let escrowed = self
+            .db
+            .get_escrowed_events_at_sn(SequenceTable::PartiallyWitnessed, pref, sn)
+            .map_err(|_| Error::StorageError)?;
+
+        let mut outcome = None;
+        for e in escrowed {
+            let parsed = message(&e.raw).map_err(|_| Error::DeserializationError)?.1;
+            let event = EventId {
+                prefix: pref.clone(),
+                sn,
+                digest: e.digest,
+            };
+            match self.apply_and_commit(
+                &e.raw,
+                parsed.event,
+                e.signatures,
+                Some(SequenceTable::PartiallyWitnessed),
+            ) {
+                Ok(state) => return self.process_escrows(event, state).map(Some),
+                Err(Error::NotEnoughReceiptsError) => {
+                    outcome = Some(ProcessingOutcome::Escrowed {
+                        event,
+                        reason: EscrowReason::PartiallyWitnessed,
+                    })
+                }
+                Err(_) => (),
+            }
+        }
+
+        Ok(outcome)
     }
 
     /// Process Validator Receipt
     ///
     /// Checks the receipt against the receipted event
-    /// and the state of the validator, returns the state
-    /// of the identifier being receipted
-    /// TODO improve checking and handling of errors!
+    /// and the state of the validator, and stores it or
+    /// escrows it until the receipted event is known
     pub fn process_validator_receipt(
         &self,
         vrc: SignedEventMessage,
-    ) -> Result<Option<IdentifierState>, Error> {
-        match &vrc.event_message.event.event_data {
-            EventData::Vrc(r) => {
-                match self
-                    .db
-                    .last_event_at_sn(&vrc.event_message.event.prefix, vrc.event_message.event.sn)
-                    .map_err(|_| Error::StorageError)?
-                {
-                    // No event found, escrow the receipt
-                    None => {
-                        for sig in vrc.signatures {
-                            self.db
-                                .escrow_t_receipt(
-                                    &vrc.event_message.event.prefix,
-                                    &r.receipted_event_digest,
-                                    &r.validator_location_seal.prefix,
-                                    &sig,
-                                )
-                                .map_err(|_| Error::StorageError)?
-                        }
-                    }
-                    // Event found, verify receipt and store
-                    Some(event) => {
-                        let keys = self
-                            .get_keys_at_event(
-                                &r.validator_location_seal.prefix,
-                                &r.validator_location_seal.event_digest,
-                            )?
-                            .ok_or(Error::SemanticError("No establishment Event found".into()))?;
-                        if keys.verify(&event, &vrc.signatures)? {
-                            for sig in vrc.signatures {
-                                self.db
-                                    .add_t_receipt_for_event(
-                                        &vrc.event_message.event.prefix,
-                                        &SelfAddressing::Blake3_256.derive(&event),
-                                        &r.validator_location_seal.prefix,
-                                        &sig,
-                                    )
-                                    .map_err(|_| Error::StorageError);
-                            }
-                        } else {
-                            Err(Error::SemanticError("Incorrect receipt signatures".into()))?;
-                        }
-                    }
-                };
-                self.compute_state(&vrc.event_message.event.prefix)
+    ) -> Result<ProcessingOutcome, Error> {
+        let r = match &vrc.event_message.event.event_data {
+            EventData::Vrc(r) => r,
+            _ => return Err(Error::SemanticError("incorrect receipt structure".into())),
+        };
+        let event = EventId {
+            prefix: vrc.event_message.event.prefix.clone(),
+            sn: vrc.event_message.event.sn,
+            digest: r.receipted_event_digest.clone(),
+        };
+        let rejected = |event, reason: &str| {
+            Ok(ProcessingOutcome::Rejected {
+                event,
+                reason: Error::SemanticError(reason.into()),
+            })
+        };
+        if let Err(violation) = self.config.check_validator_receipt(&vrc) {
+            return Ok(ProcessingOutcome::Rejected {
+                event,
+                reason: violation.into(),
+            });
+        }
+        let _lock = self.locks.lock(&event.prefix);
+
+        let receipted = match self
+            .db
+            .last_event_at_sn(&event.prefix, event.sn)
+            .map_err(|_| Error::StorageError)?
+        {
+            Some(raw) => raw,
+            // No event found, escrow the receipt
+            None => {
+                let mut batch = WriteBatch::new();
+                for sig in vrc.signatures {
+                    batch.push(WriteOp::EscrowTReceipt(EscrowedTReceipt {
+                        prefix: event.prefix.clone(),
+                        sn: event.sn,
+                        digest: event.digest.clone(),
+                        validator_seal: r.validator_location_seal.clone(),
+                        signature: sig,
+                    }));
+                }
+                self.commit(&event, batch)?;
+                self.notify(|o| o.event_escrowed(&event, EscrowReason::MissingEvent));
+                return Ok(ProcessingOutcome::Escrowed {
+                    event,
+                    reason: EscrowReason::MissingEvent,
+                });
+            }
+        };
+        if !event.digest.verify_binding(&receipted) {
+            return rejected(event, "Receipt digest does not match event");
+        }
+
+        // Event found, verify receipt and store
+        let keys = match self.get_keys_at_event(
+            &r.validator_location_seal.prefix,
+            &r.validator_location_seal.event_digest,
+        ) {
+            Ok(Some(keys)) => keys,
+            // No establishment event of the validator found, escrow the receipt
+            Ok(None) => {
+                let mut batch = WriteBatch::new();
+                for sig in vrc.signatures {
+                    batch.push(WriteOp::EscrowTReceipt(EscrowedTReceipt {
+                        prefix: event.prefix.clone(),
+                        sn: event.sn,
+                        digest: event.digest.clone(),
+                        validator_seal: r.validator_location_seal.clone(),
+                        signature: sig,
+                    }));
+                }
+                self.commit(&event, batch)?;
+                self.notify(|o| o.event_escrowed(&event, EscrowReason::UnknownValidator));
+                return Ok(ProcessingOutcome::Escrowed {
+                    event,
+                    reason: EscrowReason::UnknownValidator,
+                });
             }
-            _ => Err(Error::SemanticError("incorrect receipt structure".into())),
+            Err(Error::StorageError) => return Err(Error::StorageError),
+            Err(reason) => return Ok(ProcessingOutcome::Rejected { event, reason }),
+        };
+        match keys.verify(&receipted, &vrc.signatures) {
+            Ok(true) => (),
+            Ok(false) => return rejected(event, "Incorrect receipt signatures"),
+            Err(reason) => return Ok(ProcessingOutcome::Rejected { event, reason }),
+        };
+
+        let dig = event_digest(&receipted)?;
+        let mut batch = WriteBatch::new();
+        for sig in vrc.signatures {
+            batch.push(WriteOp::TReceipt {
+                pref: event.prefix.clone(),
+                dig: dig.clone(),
+                signer: r.validator_location_seal.prefix.clone(),
+                sig,
+            });
         }
+        self.commit(
+            &EventId {
+                digest: dig,
+                ..event.clone()
+            },
+            batch,
+        )?;
+
+        Ok(ProcessingOutcome::Accepted {
+            states: self.compute_state(&event.prefix)?.into_iter().collect(),
+            event,
+            discarded_receipts: vec![],
+        })
     }
 
     /// Process Witness Receipt
     ///
-    /// Checks the receipt against the receipted event
-    /// returns the state of the Identifier being receipted,
-    /// which may have been updated by un-escrowing events
-    /// TODO improve checking and handling of errors!
+    /// Checks the receipt against the receipted event and stores
+    /// the valid couplets, or escrows them until the receipted
+    /// event is known. A receipt may complete a partially
+    /// witnessed event, which is then finalised
     pub fn process_witness_receipt(
         &self,
         rct: SignedNontransferableReceipt,
-    ) -> Result<Option<IdentifierState>, Error> {
+    ) -> Result<ProcessingOutcome, Error> {
         // check structure is correct
-        match &rct.body.event.event_data {
-            // get event which is being receipted
-            EventData::Rct(r) => {
-                match self
-                    .db
-                    .last_event_at_sn(&rct.body.event.prefix, rct.body.event.sn)
-                    // return if lookup fails
-                    .map_err(|_| Error::StorageError)?
-                {
-                    Some(event) => {
-                        // verify receipts and store or discard
-                        let cas_dig = SelfAddressing::Blake3_256.derive(&event);
-                        for (witness, receipt) in &rct.couplets {
-                            if witness.verify(&event, &receipt)? {
-                                self.db
-                                    .add_nt_receipt_for_event(
-                                        &rct.body.event.prefix,
-                                        &cas_dig,
-                                        &witness,
-                                        &receipt,
-                                    )
-                                    .map_err(|_| Error::StorageError);
-                            };
-                        }
+        let r = match &rct.body.event.event_data {
+            EventData::Rct(r) => r,
+            _ => return Err(Error::SemanticError("incorrect receipt structure".into())),
+        };
+        let event = EventId {
+            prefix: rct.body.event.prefix.clone(),
+            sn: rct.body.event.sn,
+            digest: r.receipted_event_digest.clone(),
+        };
+        if let Err(violation) = self.config.check_witness_receipt(&rct) {
+            return Ok(ProcessingOutcome::Rejected {
+                event,
+                reason: violation.into(),
+            });
+        }
+        let _lock = self.locks.lock(&event.prefix);
+
+        // get event which is being receipted
+        match self
+            .db
+            .last_event_at_sn(&event.prefix, event.sn)
+            .map_err(|_| Error::StorageError)?
+        {
+            Some(receipted) => {
+                if !event.digest.verify_binding(&receipted) {
+                    return Ok(ProcessingOutcome::Rejected {
+                        event,
+                        reason: Error::SemanticError("Receipt digest does not match event".into()),
+                    });
+                }
+
+                // verify receipts and store or discard
+                let cas_dig = event_digest(&receipted)?;
+                let mut batch = WriteBatch::new();
+                for (witness, sig) in rct.couplets {
+                    if witness.verify(&receipted, &sig).unwrap_or(false) {
+                        batch.push(WriteOp::NtReceipt {
+                            pref: event.prefix.clone(),
+                            dig: cas_dig.clone(),
+                            signer: witness,
+                            sig,
+                        });
                     }
-                    None => {
-                        for (witness, receipt) in rct.couplets {
-                            self.db
-                                .escrow_nt_receipt(
-                                    &rct.body.event.prefix,
-                                    // TODO THIS MAY NOT ALWAYS MATCH, see issue #74 in dif/keri
-                                    &r.receipted_event_digest,
-                                    &witness,
-                                    &receipt,
-                                )
-                                .map_err(|_| Error::StorageError);
-                        }
+                }
+                if batch.is_empty() {
+                    return Ok(ProcessingOutcome::Rejected {
+                        event,
+                        reason: Error::SemanticError("Incorrect receipt signatures".into()),
+                    });
+                }
+                self.commit(
+                    &EventId {
+                        digest: cas_dig,
+                        ..event.clone()
+                    },
+                    batch,
+                )?;
+
+                Ok(ProcessingOutcome::Accepted {
+                    states: self.compute_state(&event.prefix)?.into_iter().collect(),
+                    event,
+                    discarded_receipts: vec![],
+                })
+            }
+            None => {
+                let mut batch = WriteBatch::new();
+                for (witness, sig) in rct.couplets {
+                    batch.push(WriteOp::EscrowNtReceipt(EscrowedNtReceipt {
+                        prefix: event.prefix.clone(),
+                        sn: event.sn,
+                        // TODO THIS MAY NOT ALWAYS MATCH, see issue #74 in dif/keri
+                        digest: event.digest.clone(),
+                        signer: witness,
+                        signature: sig,
+                    }));
+                }
+                self.commit(&event, batch)?;
+                self.notify(|o| o.event_escrowed(&event, EscrowReason::MissingEvent));
+
+                // the receipt may complete a partially witnessed event
+                if self.witness_threshold {
+                    if let Some(outcome) =
+                        self.process_partially_witnessed_escrow(&event.prefix, event.sn)?
+                    {
+                        return Ok(outcome);
                     }
-                };
-                self.compute_state(&rct.body.event.prefix)
+                }
+                Ok(ProcessingOutcome::Escrowed {
+                    event,
+                    reason: EscrowReason::MissingEvent,
+                })
             }
-            _ => Err(Error::SemanticError("incorrect receipt structure".into())),
         }
     }
 
-    fn apply_to_state(&self, event: EventMessage) -> Result<IdentifierState, Error> {
-        // get state for id (TODO cache?)
+    /// Accumulate Signatures
+    ///
+    /// Merges the signatures already logged for an event with newly
+    /// received ones, keeping one valid signature per key index
+    fn accumulate_signatures(
+        &self,
+        pref: &IdentifierPrefix,
+        dig: &SelfAddressingPrefix,
+        raw: &[u8],
+        sigs: &[AttachedSignaturePrefix],
+        keys: &KeyConfig,
+    ) -> Result<Vec<AttachedSignaturePrefix>, Error> {
+        let logged = self
+            .db
+            .get_signatures_for_event(pref, dig)
+            .map_err(|_| Error::StorageError)?
+            .unwrap_or_default();
+
+        Ok(keys.valid_signatures(raw, &[logged, sigs.to_vec()].concat()))
+    }
+
+    fn apply_to_state(&self, event: &EventMessage) -> Result<IdentifierState, Error> {
+        // get cached state for id
         self.compute_state(&event.event.prefix)
             // get empty state if there is no state yet
             .and_then(|opt| Ok(opt.map_or_else(|| IdentifierState::default(), |s| s)))
@@ -302,3 +1911,12 @@ impl<D: EventDatabase> EventProcessor<D> {
             .and_then(|state| event.apply_to(state))
     }
 }
+
+/// Event Digest
+///
+/// Digests a raw event with the algorithm the event itself
+/// commits with, which is the digest it is stored under
+fn event_digest(raw: &[u8]) -> Result<SelfAddressingPrefix, Error> {
+    let parsed = message(raw).map_err(|_| Error::DeserializationError)?.1;
+    Ok(parsed.event.digest_derivation().derive(raw))
+}
@@ -0,0 +1,29 @@
+use super::{
+    duplicity::DuplicityEvidence,
+    outcome::{EscrowReason, EventId},
+};
+use crate::{prefix::IdentifierPrefix, state::IdentifierState};
+
+/// Processor Observer
+///
+/// Is told about changes to the KELs kept by an `EventProcessor`
+/// once they are committed. Every method does nothing by default,
+/// so an observer only implements the ones it needs
+pub trait ProcessorObserver: Send + Sync {
+    /// The event was added to the KEL, resulting in the state
+    fn event_finalised(&self, _event: &EventId, _state: &IdentifierState) {}
+
+    /// The event, or a receipt of it, is held until the reason
+    /// is resolved
+    fn event_escrowed(&self, _event: &EventId, _reason: EscrowReason) {}
+
+    /// The event, or receipts of it, left the escrow they were
+    /// held in because what they were waiting for arrived
+    fn escrow_resolved(&self, _event: &EventId, _reason: EscrowReason) {}
+
+    /// A verified receipt of the event made by the signer was stored
+    fn receipt_added(&self, _event: &EventId, _signer: &IdentifierPrefix) {}
+
+    /// A duplicitous event was recorded
+    fn duplicity_detected(&self, _evidence: &DuplicityEvidence) {}
+}
@@ -0,0 +1,112 @@
+use super::{duplicity::DuplicityEvidence, DiscardedReceipt};
+use crate::{
+    error::Error,
+    prefix::{IdentifierPrefix, SelfAddressingPrefix},
+    state::IdentifierState,
+};
+
+/// Event Id
+///
+/// Identifies the event a processed message concerns,
+/// for a receipt that is the receipted event
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventId {
+    pub prefix: IdentifierPrefix,
+    pub sn: u64,
+    pub digest: SelfAddressingPrefix,
+}
+
+/// Escrow Reason
+///
+/// Why a message is being held until something else arrives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscrowReason {
+    /// The event needs more controller signatures
+    PartiallySigned,
+    /// The event needs receipts from more of its witnesses
+    PartiallyWitnessed,
+    /// The event arrived before the events preceding it
+    OutOfOrder,
+    /// The receipt arrived before the event it receipts
+    MissingEvent,
+    /// The receipt arrived before the establishment event
+    /// of its validator
+    UnknownValidator,
+}
+
+/// Processing Outcome
+///
+/// What became of a processed message. Only failures to process
+/// it at all, like storage errors, are returned as an `Error`
+#[derive(Debug)]
+pub enum ProcessingOutcome {
+    /// The event was finalised, or the receipt stored. States holds
+    /// the state after it, followed by the states after each escrowed
+    /// event it unblocked, in order
+    Accepted {
+        event: EventId,
+        states: Vec<IdentifierState>,
+        discarded_receipts: Vec<DiscardedReceipt>,
+    },
+    Escrowed {
+        event: EventId,
+        reason: EscrowReason,
+    },
+    /// The event is already in the KEL
+    Duplicate {
+        event: EventId,
+    },
+    /// The event is validly signed but conflicts with the KEL
+    Duplicitous {
+        event: EventId,
+        evidence: DuplicityEvidence,
+    },
+    Rejected {
+        event: EventId,
+        reason: Error,
+    },
+}
+
+impl ProcessingOutcome {
+    pub fn event(&self) -> &EventId {
+        match self {
+            Self::Accepted { event, .. }
+            | Self::Escrowed { event, .. }
+            | Self::Duplicate { event }
+            | Self::Duplicitous { event, .. }
+            | Self::Rejected { event, .. } => event,
+        }
+    }
+
+    /// State
+    ///
+    /// Returns the latest state of the Identifier
+    /// if the message was accepted
+    pub fn state(&self) -> Option<&IdentifierState> {
+        match self {
+            Self::Accepted { states, .. } => states.last(),
+            _ => None,
+        }
+    }
+}
+
+/// Stream Outcome
+///
+/// The outcome of each message of a processed stream, in
+/// order, the trailing bytes which were not processed and the
+/// error which stopped processing, if any
+#[derive(Debug)]
+pub struct StreamOutcome<'a> {
+    pub outcomes: Vec<ProcessingOutcome>,
+    pub unparsed: &'a [u8],
+    pub error: Option<Error>,
+}
+
+impl<'a> StreamOutcome<'a> {
+    /// Is Complete
+    ///
+    /// True if the whole stream was consumed
+    pub fn is_complete(&self) -> bool {
+        self.unparsed.is_empty()
+    }
+}
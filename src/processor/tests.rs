@@ -1,22 +1,199 @@
-use super::EventProcessor;
-use crate::event_message::{parse, parse::Deserialized};
-use crate::{database::lmdb::LmdbEventDatabase, database::EventDatabase, error::Error};
-use std::fs;
+use super::{
+    config::ProcessorConfig,
+    event_digest,
+    outcome::{EscrowReason, ProcessingOutcome},
+    EventProcessor,
+};
+use crate::event_message::{parse, parse::Deserialized, SignedNontransferableReceipt};
+use crate::{
+    database::memory::MemoryEventDatabase,
+    database::{EventDatabase, SequenceTable},
+    derivation::{basic::Basic, self_addressing::SelfAddressing, self_signing::SelfSigning},
+    error::{Error, PolicyViolation},
+    event::{
+        event_data::{receipt::ReceiptNonTransferable, EventData},
+        sections::{nxt_commitment, KeyConfig},
+        Event, EventMessage, SerializationFormats,
+    },
+    prefix::{
+        AttachedSignaturePrefix, BasicPrefix, IdentifierPrefix, SelfAddressingPrefix,
+        SelfSigningPrefix,
+    },
+};
+use std::fmt::Debug;
+#[cfg(any(feature = "lmdb", feature = "sqlite"))]
+use tempfile::{Builder, TempDir};
+use ursa::{
+    keys::{PrivateKey, PublicKey},
+    signatures::{ed25519::Ed25519Sha512, SignatureScheme},
+};
 
-#[test]
-fn test_process() -> Result<(), Error> {
-    use tempfile::Builder;
+#[cfg(feature = "lmdb")]
+fn lmdb_processor() -> (
+    TempDir,
+    EventProcessor<crate::database::lmdb::LmdbEventDatabase>,
+) {
+    use crate::database::lmdb::LmdbEventDatabase;
+    use std::fs;
 
     // Create test db and event processor.
     let root = Builder::new().prefix("test-db").tempdir().unwrap();
     fs::create_dir_all(root.path()).unwrap();
 
     let db = LmdbEventDatabase::new(root.path()).unwrap();
-    let event_processor = EventProcessor::new(db);
+    (root, EventProcessor::new(db, ProcessorConfig::default()))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_processor() -> (
+    TempDir,
+    EventProcessor<crate::database::sqlite::SqliteEventDatabase>,
+) {
+    use crate::database::sqlite::SqliteEventDatabase;
+
+    // Create test db and event processor.
+    let root = Builder::new().prefix("test-db").tempdir().unwrap();
+
+    let db = SqliteEventDatabase::new(root.path().join("events.sqlite")).unwrap();
+    (root, EventProcessor::new(db, ProcessorConfig::default()))
+}
+
+// Events and sigs are from keripy `test_multisig_digprefix` test.
+
+/// Three key inception with all three signatures attached
+const MULTISIG_ICP: &str = r#"{"vs":"KERI10JSON000159_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"0","ilk":"icp","sith":"2","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA","DVcuJOOJF1IE8svqEtrSuyQjGTd2HhfAkt9y2QkUtFJI","DT1iAhBWCkvChxNWsby2J0pJyxBIxbAtbLA0Ljx-Grh8"],"nxt":"E9izzBkXX76sqt0N-tfLzJeRqj0W56p4pDQ_ZqNCDpyw","toad":"0","wits":[],"cnfg":[]}-AADAAh_trqX993WCZfZ2Mm8Rj2AnlpJzStsv2x4M3gKOIpA740SCYGTDIU4L2Zokd8Krfakt98vy2vAYTjrJ7_UMnBQABNgYwwmeinupnrK8nIbVkz4iL7OgjAalNSNimZciYLCBRoKD5jbyXbHDxgycjl2vaw3roAzuaSi4686OY4P0kDgACbz0tl-U_EBbSfdKmtJHkSNfbDjB7pw_k9C9MuTv1eP3XM4OFApLJyhshWDtWmUzb4uorpXWvqRKfkMIRCKyBDQ"#;
+/// Rotation at sn 1
+const MULTISIG_ROT: &str = r#"{"vs":"KERI10JSON000198_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"1","ilk":"rot","dig":"EYmBZ0_Nn4sjid4UcQckAq_IXE6yzyh0Yy-lwKeRUVxg","sith":"2","keys":["DKPE5eeJRzkRTMOoRGVd2m18o8fLqM2j9kaxLhV3x8AQ","D1kcBE7h0ImWW6_Sp7MQxGYSshZZz6XM7OiUE5DXm0dU","D4JDgo3WNSUpt-NG14Ni31_GCmrU0r38yo7kgDuyGkQM"],"nxt":"EQpRYqbID2rW8X5lB6mOzDckJEIFae6NbJISXgJSN9qg","toad":"0","cuts":[],"adds":[],"data":[]}-AADAAtjBE4-kz5byJJDJuqKKKyjujw0CBMJfdx4XPmky_7cl8jNyeoTpcSbcifr7LUbuM_iQIBXFNIBqL9KMw8RQgAQABB8zTUrCwrBzO4M58oJ_CRu6fdVXK-jy5tYSwoqWcxjtRYnF-OIZ03zVjdhiky24-P_dRCGBQE-VmOQcSRW6NAgACrt7M9UM2Thvib1OhFcQtGjNnDNkG502_YWUnhOYOiS-_poEQRHi2PrF5FSNSv8cnAKgTH9UNt8h98kqOqXYJCQ"#;
+/// Interaction at sn 2
+const MULTISIG_IXN: &str = r#"{"vs":"KERI10JSON0000a3_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"2","ilk":"ixn","dig":"EkH8Pm-Fv6QDawC4rDulf6X9anQ_AETbNdUh4HCjB0Co","data":[]}-AADAAYbN7F_JmSY9dZ5QzaccH8uaO6iCARwgebv4aw-MmM69Cn6iDWncWoK_Deu-Ik3hMTPpyhkUPsh444-psVFrhCAAB_YnGFnNbwJPiO1__3ecxOxFLBgvoAmSJ3j6ojA_a6tTbp19x0hg38OFvDlytbkbAXBCQPGrLDKoTclhFZ5guAQACpVhXP2WGe_Gd2aVpStB1NdRo9ipFFto4jyMeMWorUdCMMMwwTuIBa_gw62f4OyDTfWv4kSZo47l2li2RT6ydAw"#;
+/// Controller inception from keripy `test_direct_mode` test
+const DIRECT_MODE_ICP: &str = r#"{"vs":"KERI10JSON0000fb_","pre":"EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI","sn":"0","ilk":"icp","sith":"1","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"],"nxt":"EPYuj8mq_PYYsoBKkzX1kxSPGYBWaIya3slgCOyOtlqU","toad":"0","wits":[],"cnfg":[]}-AABAApYcYd1cppVg7Inh2YCslWKhUwh59TrPpIoqWxN2A38NCbTljvmBPBjSGIFDBNOvVjHpdZlty3Hgk6ilF8pVpAQ"#;
+
+fn keypair() -> Result<(PublicKey, PrivateKey), Error> {
+    Ed25519Sha512::new()
+        .keypair(None)
+        .map_err(Error::CryptoError)
+}
+
+/// Single Ed25519 key committing to a single next key with Blake3-256
+fn key_config(key: PublicKey, next_key: PublicKey) -> KeyConfig {
+    KeyConfig::new(
+        vec![Basic::Ed25519.derive(key)],
+        nxt_commitment(
+            1,
+            &[Basic::Ed25519.derive(next_key)],
+            SelfAddressing::Blake3_256,
+        ),
+        Some(1),
+    )
+}
+
+fn signature(raw: &[u8], key: &PrivateKey) -> Result<SelfSigningPrefix, Error> {
+    Ok(SelfSigning::Ed25519Sha512.derive(
+        Ed25519Sha512::new()
+            .sign(raw, key)
+            .map_err(Error::CryptoError)?,
+    ))
+}
 
-    let icp_raw = r#"{"vs":"KERI10JSON000159_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"0","ilk":"icp","sith":"2","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA","DVcuJOOJF1IE8svqEtrSuyQjGTd2HhfAkt9y2QkUtFJI","DT1iAhBWCkvChxNWsby2J0pJyxBIxbAtbLA0Ljx-Grh8"],"nxt":"E9izzBkXX76sqt0N-tfLzJeRqj0W56p4pDQ_ZqNCDpyw","toad":"0","wits":[],"cnfg":[]}-AADAAh_trqX993WCZfZ2Mm8Rj2AnlpJzStsv2x4M3gKOIpA740SCYGTDIU4L2Zokd8Krfakt98vy2vAYTjrJ7_UMnBQABNgYwwmeinupnrK8nIbVkz4iL7OgjAalNSNimZciYLCBRoKD5jbyXbHDxgycjl2vaw3roAzuaSi4686OY4P0kDgACbz0tl-U_EBbSfdKmtJHkSNfbDjB7pw_k9C9MuTv1eP3XM4OFApLJyhshWDtWmUzb4uorpXWvqRKfkMIRCKyBDQ"#;
+/// Serializes the message signed by the key at index 0
+fn sign(message: &EventMessage, key: &PrivateKey) -> Result<Vec<u8>, Error> {
+    let sig = Ed25519Sha512::new()
+        .sign(&message.serialize()?, key)
+        .map_err(Error::CryptoError)?;
+    message
+        .sign(vec![AttachedSignaturePrefix::new(
+            SelfSigning::Ed25519Sha512,
+            sig,
+            0,
+        )])
+        .serialize()
+}
+
+fn process<D: EventDatabase>(
+    event_processor: &EventProcessor<D>,
+    signed: &[u8],
+) -> Result<ProcessingOutcome, Error> {
+    event_processor.process(parse::signed_message(signed).unwrap().1)
+}
+
+fn witness_receipt(
+    prefix: &IdentifierPrefix,
+    sn: u64,
+    dig: SelfAddressingPrefix,
+    couplets: Vec<(BasicPrefix, SelfSigningPrefix)>,
+) -> Result<SignedNontransferableReceipt, Error> {
+    let body = Event {
+        prefix: prefix.clone(),
+        sn,
+        event_data: EventData::Rct(ReceiptNonTransferable {
+            receipted_event_digest: dig,
+        }),
+    }
+    .to_message(SerializationFormats::JSON)?;
+    Ok(SignedNontransferableReceipt::new(&body, couplets))
+}
+
+/// Backend Tests
+///
+/// Declares a module per scenario which runs it against
+/// every database backend enabled by the features
+macro_rules! backend_tests {
+    ($($name:ident: $scenario:ident,)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[test]
+                fn memory() -> Result<(), Error> {
+                    $scenario(EventProcessor::new(
+                        MemoryEventDatabase::new(),
+                        ProcessorConfig::default(),
+                    ))
+                }
+
+                #[cfg(feature = "lmdb")]
+                #[test]
+                fn lmdb() -> Result<(), Error> {
+                    let (_root, event_processor) = lmdb_processor();
+                    $scenario(event_processor)
+                }
+
+                #[cfg(feature = "sqlite")]
+                #[test]
+                fn sqlite() -> Result<(), Error> {
+                    let (_root, event_processor) = sqlite_processor();
+                    $scenario(event_processor)
+                }
+            }
+        )*
+    };
+}
+
+backend_tests! {
+    test_process: process_kel,
+    test_process_receipt: process_receipt,
+    test_process_delegation: process_delegation,
+    test_process_out_of_order: process_out_of_order,
+    test_process_escrowed_receipts: process_escrowed_receipts,
+    test_process_witnessed_event: process_witnessed_event,
+    test_process_duplicity: process_duplicity,
+    test_process_recovery: process_recovery,
+    test_process_stream: process_stream,
+    test_process_stream_receipts: process_stream_receipts,
+    test_historical_state: historical_state,
+    test_state_cache: state_cache,
+    test_process_digest_agility: process_digest_agility,
+    test_process_observer: process_observer,
+    test_concurrent_processing: concurrent_processing,
+}
+
+fn process_kel<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    let icp_raw = MULTISIG_ICP;
     // Create deserialized inception event from string.
-    // Events and sigs are from keripy `test_multisig_digprefix` test.
     let deserialized_icp = parse::signed_message(icp_raw.as_bytes()).unwrap().1;
 
     let (id, raw_parsed) = match &deserialized_icp {
@@ -25,14 +202,18 @@ fn test_process() -> Result<(), Error> {
     };
 
     // Process icp event.
-    let id_state = event_processor.process(deserialized_icp)?.unwrap();
+    let id_state = event_processor
+        .process(deserialized_icp)?
+        .state()
+        .cloned()
+        .unwrap();
 
     assert_eq!(id_state.sn, 0);
     // Check if processed event is in kel.
     let icp_from_db = event_processor.db.last_event_at_sn(&id, 0).unwrap();
     assert_eq!(icp_from_db, Some(raw_parsed));
 
-    let rot_raw = r#"{"vs":"KERI10JSON000198_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"1","ilk":"rot","dig":"EYmBZ0_Nn4sjid4UcQckAq_IXE6yzyh0Yy-lwKeRUVxg","sith":"2","keys":["DKPE5eeJRzkRTMOoRGVd2m18o8fLqM2j9kaxLhV3x8AQ","D1kcBE7h0ImWW6_Sp7MQxGYSshZZz6XM7OiUE5DXm0dU","D4JDgo3WNSUpt-NG14Ni31_GCmrU0r38yo7kgDuyGkQM"],"nxt":"EQpRYqbID2rW8X5lB6mOzDckJEIFae6NbJISXgJSN9qg","toad":"0","cuts":[],"adds":[],"data":[]}-AADAAtjBE4-kz5byJJDJuqKKKyjujw0CBMJfdx4XPmky_7cl8jNyeoTpcSbcifr7LUbuM_iQIBXFNIBqL9KMw8RQgAQABB8zTUrCwrBzO4M58oJ_CRu6fdVXK-jy5tYSwoqWcxjtRYnF-OIZ03zVjdhiky24-P_dRCGBQE-VmOQcSRW6NAgACrt7M9UM2Thvib1OhFcQtGjNnDNkG502_YWUnhOYOiS-_poEQRHi2PrF5FSNSv8cnAKgTH9UNt8h98kqOqXYJCQ"#;
+    let rot_raw = MULTISIG_ROT;
     // Create deserialized rotation event.
     let deserialized_rot = parse::signed_message(rot_raw.as_bytes()).unwrap().1;
 
@@ -42,7 +223,11 @@ fn test_process() -> Result<(), Error> {
     };
 
     // Process rotation event.
-    let id_state = event_processor.process(deserialized_rot.clone())?.unwrap();
+    let id_state = event_processor
+        .process(deserialized_rot.clone())?
+        .state()
+        .cloned()
+        .unwrap();
     assert_eq!(id_state.sn, 1);
 
     // Check if processed event is in db.
@@ -51,10 +236,9 @@ fn test_process() -> Result<(), Error> {
 
     // Process the same rotation event one more time.
     let id_state = event_processor.process(deserialized_rot);
-    assert!(id_state.is_err());
-    assert!(matches!(id_state, Err(Error::EventDuplicateError)));
+    assert!(matches!(id_state, Ok(ProcessingOutcome::Duplicate { .. })));
 
-    let ixn_raw = r#"{"vs":"KERI10JSON0000a3_","pre":"EUEtw_3JqBhrLtwwlP9QLnDXZGjJ3CIxq7QGP_dEQiwc","sn":"2","ilk":"ixn","dig":"EkH8Pm-Fv6QDawC4rDulf6X9anQ_AETbNdUh4HCjB0Co","data":[]}-AADAAYbN7F_JmSY9dZ5QzaccH8uaO6iCARwgebv4aw-MmM69Cn6iDWncWoK_Deu-Ik3hMTPpyhkUPsh444-psVFrhCAAB_YnGFnNbwJPiO1__3ecxOxFLBgvoAmSJ3j6ojA_a6tTbp19x0hg38OFvDlytbkbAXBCQPGrLDKoTclhFZ5guAQACpVhXP2WGe_Gd2aVpStB1NdRo9ipFFto4jyMeMWorUdCMMMwwTuIBa_gw62f4OyDTfWv4kSZo47l2li2RT6ydAw"#;
+    let ixn_raw = MULTISIG_IXN;
     // Create deserialized interaction event.
     let deserialized_ixn = parse::signed_message(ixn_raw.as_bytes()).unwrap().1;
 
@@ -64,7 +248,11 @@ fn test_process() -> Result<(), Error> {
     };
 
     // Process interaction event.
-    let id_state = event_processor.process(deserialized_ixn)?.unwrap();
+    let id_state = event_processor
+        .process(deserialized_ixn)?
+        .state()
+        .cloned()
+        .unwrap();
     assert_eq!(id_state.sn, 2);
 
     // Check if processed event is in db.
@@ -86,7 +274,13 @@ fn test_process() -> Result<(), Error> {
 
     // Process partially signed interaction event.
     let id_state = event_processor.process(partially_signed_deserialized_ixn);
-    assert!(matches!(id_state, Err(Error::NotEnoughSigsError)));
+    assert!(matches!(
+        id_state,
+        Ok(ProcessingOutcome::Escrowed {
+            reason: EscrowReason::PartiallySigned,
+            ..
+        })
+    ));
 
     // Check if processed ixn event is in kel. It shouldn't because of not enough signatures.
     let ixn_from_db = event_processor.db.last_event_at_sn(&id, 3);
@@ -100,30 +294,55 @@ fn test_process() -> Result<(), Error> {
         .1;
 
     let id_state = event_processor.process(out_of_order_ixn);
-    assert!(id_state.is_err());
-    assert!(matches!(id_state, Err(Error::EventOutOfOrderError)));
+    assert!(matches!(
+        id_state,
+        Ok(ProcessingOutcome::Escrowed {
+            reason: EscrowReason::OutOfOrder,
+            ..
+        })
+    ));
 
     // Check if processed event is in kel. It shouldn't.
     let ixn_from_db = event_processor.db.last_event_at_sn(&id, 4);
     assert!(matches!(ixn_from_db, Ok(None)));
 
-    Ok(())
-}
-
-#[test]
-fn test_process_receipt() -> Result<(), Error> {
-    use tempfile::Builder;
+    // Submit the partially signed event again with another of its signatures.
+    let other_signed_ixn = match parse::signed_message(ixn_raw.as_bytes()).unwrap().1 {
+        Deserialized::Event(mut e) => {
+            let sigs = e.signatures[0].clone();
+            e.signatures = vec![sigs];
+            Deserialized::Event(e)
+        }
+        _ => Err(Error::SemanticError("bad deser".into()))?,
+    };
 
-    // Create test db and event processor.
-    let root = Builder::new().prefix("test-db").tempdir().unwrap();
-    fs::create_dir_all(root.path()).unwrap();
+    // Together the two submissions meet the threshold.
+    let id_state = event_processor
+        .process(other_signed_ixn)?
+        .state()
+        .cloned()
+        .unwrap();
+    assert_eq!(id_state.sn, 3);
+    assert!(event_processor
+        .db
+        .last_event_at_sn(&id, 3)
+        .unwrap()
+        .is_some());
+    assert!(event_processor
+        .db
+        .get_escrowed_events(SequenceTable::PartiallySigned, Some(&id))
+        .unwrap()
+        .is_empty());
 
-    let db = LmdbEventDatabase::new(root.path()).unwrap();
-    let event_processor = EventProcessor::new(db);
+    Ok(())
+}
 
-    // Events and sigs are from keripy `test_direct_mode` test.
+fn process_receipt<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
     // Construct and process controller's inception event.
-    let icp_raw = r#"{"vs":"KERI10JSON0000fb_","pre":"EvEnZMhz52iTrJU8qKwtDxzmypyosgG70m6LIjkiCdoI","sn":"0","ilk":"icp","sith":"1","keys":["DSuhyBcPZEZLK-fcw5tzHn2N46wRCG_ZOoeKtWTOunRA"],"nxt":"EPYuj8mq_PYYsoBKkzX1kxSPGYBWaIya3slgCOyOtlqU","toad":"0","wits":[],"cnfg":[]}-AABAApYcYd1cppVg7Inh2YCslWKhUwh59TrPpIoqWxN2A38NCbTljvmBPBjSGIFDBNOvVjHpdZlty3Hgk6ilF8pVpAQ"#;
+    let icp_raw = DIRECT_MODE_ICP;
     let icp = parse::signed_message(icp_raw.as_bytes()).unwrap().1;
 
     let controller_id_state = event_processor.process(icp)?;
@@ -133,20 +352,1629 @@ fn test_process_receipt() -> Result<(), Error> {
     let rcp = parse::signed_message(vrc_raw.as_bytes()).unwrap().1;
 
     let id_state = event_processor.process(rcp.clone());
-    // Validator not yet in db. Event should be escrowed.
-    assert!(id_state.is_err());
+    // Validator not yet in db, so the receipt is escrowed until it is.
+    assert!(matches!(
+        id_state,
+        Ok(ProcessingOutcome::Escrowed {
+            reason: EscrowReason::UnknownValidator,
+            ..
+        })
+    ));
+    let id = controller_id_state.state().unwrap().prefix.clone();
+    let icp_dig = SelfAddressing::Blake3_256.derive(&controller_id_state.state().unwrap().last);
+    assert!(event_processor
+        .get_validator_receipts(&id, &icp_dig)?
+        .is_empty());
 
     // Contruct and process validator's inception event.
     let val_icp_raw = r#"{"vs":"KERI10JSON0000fb_","pre":"E0uTVILY2KXdcxX40MSM9Fr8EpGwfjMNap6ulAAzVt0M","sn":"0","ilk":"icp","sith":"1","keys":["D8KY1sKmgyjAiUDdUBPNPyrSz_ad_Qf9yzhDNZlEKiMc"],"nxt":"EOWDAJvex5dZzDxeHBANyaIoUG3F4-ic81G6GwtnC4f4","toad":"0","wits":[],"cnfg":[]}-AABAAR5dawnJxU_Gbb8EK2xUMLb2AU7wLlZDHlDzHvovP-YIowqFq719VMQc9hrEwW9JKs90leAm2rUp3_DOi7-olBg"#;
     let val_icp = parse::signed_message(val_icp_raw.as_bytes()).unwrap().1;
 
+    // The validator's inception promotes the escrowed receipt.
     event_processor.process(val_icp)?;
+    assert_eq!(
+        event_processor.get_validator_receipts(&id, &icp_dig)?.len(),
+        1
+    );
+    assert!(event_processor
+        .db
+        .get_escrowed_t_receipts(None)
+        .unwrap()
+        .is_empty());
 
     // Process receipt once again.
-    let id_state = event_processor.process(rcp);
-    assert!(id_state.is_ok());
+    let id_state = event_processor.process(rcp)?;
+    assert!(matches!(id_state, ProcessingOutcome::Accepted { .. }));
     // Controller's state shouldn't change after processing receipt.
-    assert_eq!(controller_id_state, id_state?);
+    assert_eq!(controller_id_state.state(), id_state.state());
+
+    Ok(())
+}
+
+fn process_delegation<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::{
+        event::{
+            event_data::{
+                delegated::{DelegatedInceptionEvent, DelegatedRotationEvent},
+                inception::InceptionEvent,
+                interaction::InteractionEvent,
+                rotation::RotationEvent,
+            },
+            sections::{
+                seal::{EventSeal, LocationSeal, Seal},
+                WitnessConfig,
+            },
+        },
+        prefix::SelfAddressingPrefix,
+    };
+
+    let (delegator_pub_key, delegator_priv_key) = keypair()?;
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, next_priv_key) = keypair()?;
+    let (next_next_pub_key, _) = keypair()?;
+
+    let digest = |message: &EventMessage| -> Result<SelfAddressingPrefix, Error> {
+        Ok(SelfAddressing::Blake3_256.derive(&message.serialize()?))
+    };
+    let delegator_icp = InceptionEvent::new(
+        key_config(delegator_pub_key.clone(), delegator_pub_key),
+        None,
+        None,
+    )
+    .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let delegator = delegator_icp.event.prefix.clone();
+    process(
+        &event_processor,
+        &sign(&delegator_icp, &delegator_priv_key)?,
+    )?;
+
+    // delegator interaction event anchoring a delegated event
+    let anchor = |sn, previous, delegated: &EventMessage| -> Result<EventMessage, Error> {
+        EventMessage::new(
+            Event {
+                prefix: delegator.clone(),
+                sn,
+                event_data: EventData::Ixn(InteractionEvent {
+                    previous_event_hash: previous,
+                    data: vec![Seal::Event(EventSeal {
+                        prefix: delegated.event.prefix.clone(),
+                        event_digest: digest(delegated)?,
+                    })],
+                }),
+            },
+            SerializationFormats::JSON,
+        )
+    };
+
+    // Construct delegated inception event sealed to the delegator's first interaction.
+    let dip = DelegatedInceptionEvent {
+        inception_data: InceptionEvent::new(key_config(pub_key, next_pub_key.clone()), None, None),
+        perm: vec!["drt".into()],
+        seal: LocationSeal {
+            prefix: delegator.clone(),
+            sn: 1,
+            ilk: "ixn".into(),
+            prior_digest: digest(&delegator_icp)?,
+        },
+    }
+    .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let delegate = dip.event.prefix.clone();
+    let signed_dip = sign(&dip, &priv_key)?;
+
+    // The delegating event is not known yet.
+    assert!(matches!(
+        process(&event_processor, &signed_dip),
+        Ok(ProcessingOutcome::Rejected {
+            reason: Error::MissingDelegatingEventError,
+            ..
+        })
+    ));
+
+    let delegating_ixn = anchor(1, digest(&delegator_icp)?, &dip)?;
+    process(
+        &event_processor,
+        &sign(&delegating_ixn, &delegator_priv_key)?,
+    )?;
+
+    let id_state = process(&event_processor, &signed_dip)?
+        .state()
+        .cloned()
+        .unwrap();
+    assert_eq!(id_state.prefix, delegate);
+    assert_eq!(id_state.sn, 0);
+    assert_eq!(id_state.delegator, Some(delegator.clone()));
+    assert_eq!(id_state.perm, vec!["drt".to_string()]);
+
+    // The delegation is indexed in both directions.
+    assert_eq!(
+        event_processor.db.get_parent_of_prefix(&delegate).unwrap(),
+        Some(delegator.clone())
+    );
+    assert_eq!(
+        event_processor
+            .db
+            .get_children_of_prefix(&delegator)
+            .unwrap(),
+        Some(vec![delegate.clone()])
+    );
+    assert_eq!(
+        event_processor
+            .compute_state(&delegator)?
+            .map(|s| s.delegated_keys),
+        Some(vec![delegate.clone()])
+    );
+    assert_eq!(
+        event_processor
+            .compute_state_at_sn(&delegator, 1)?
+            .map(|s| s.delegated_keys),
+        Some(vec![delegate.clone()])
+    );
+    assert_eq!(
+        event_processor
+            .compute_state_at_event(&delegator, &digest(&delegating_ixn)?)?
+            .map(|s| s.delegated_keys),
+        Some(vec![delegate.clone()])
+    );
+    assert_eq!(
+        event_processor
+            .compute_state_at_time(&delegator, chrono::Utc::now())?
+            .map(|s| s.delegated_keys),
+        Some(vec![delegate.clone()])
+    );
+    // States from before the delegating event don't list the delegate.
+    assert_eq!(
+        event_processor
+            .compute_state_at_sn(&delegator, 0)?
+            .map(|s| s.delegated_keys),
+        Some(vec![])
+    );
+    assert_eq!(
+        event_processor
+            .compute_state_at_event(&delegator, &digest(&delegator_icp)?)?
+            .map(|s| s.delegated_keys),
+        Some(vec![])
+    );
+
+    // Interaction events are not permitted to the delegate.
+    let ixn = EventMessage::new(
+        Event {
+            prefix: delegate.clone(),
+            sn: 1,
+            event_data: EventData::Ixn(InteractionEvent {
+                previous_event_hash: digest(&dip)?,
+                data: vec![],
+            }),
+        },
+        SerializationFormats::JSON,
+    )?;
+    assert!(matches!(
+        process(&event_processor, &sign(&ixn, &priv_key)?),
+        Ok(ProcessingOutcome::Rejected {
+            reason: Error::SemanticError(_),
+            ..
+        })
+    ));
+    assert_eq!(
+        event_processor.compute_state(&delegate)?.map(|s| s.sn),
+        Some(0)
+    );
+
+    // Delegated rotation anchored in the delegator's next interaction.
+    let drt = |perm: Vec<String>| {
+        EventMessage::new(
+            Event {
+                prefix: delegate.clone(),
+                sn: 1,
+                event_data: EventData::Drt(DelegatedRotationEvent {
+                    rotation_data: RotationEvent {
+                        previous_event_hash: digest(&dip)?,
+                        key_config: key_config(next_pub_key.clone(), next_next_pub_key.clone()),
+                        witness_config: WitnessConfig::default(),
+                        data: vec![],
+                    },
+                    perm,
+                    seal: LocationSeal {
+                        prefix: delegator.clone(),
+                        sn: 2,
+                        ilk: "ixn".into(),
+                        prior_digest: digest(&delegating_ixn)?,
+                    },
+                }),
+            },
+            SerializationFormats::JSON,
+        )
+    };
+    let anchored_drt = drt(vec![])?;
+    let unanchored_drt = drt(vec!["ixn".into()])?;
+    let rotating_ixn = anchor(2, digest(&delegating_ixn)?, &anchored_drt)?;
+    process(&event_processor, &sign(&rotating_ixn, &delegator_priv_key)?)?;
+
+    // Only the anchored rotation is accepted.
+    assert!(matches!(
+        process(&event_processor, &sign(&unanchored_drt, &next_priv_key)?),
+        Ok(ProcessingOutcome::Rejected {
+            reason: Error::SemanticError(_),
+            ..
+        })
+    ));
+    let id_state = process(&event_processor, &sign(&anchored_drt, &next_priv_key)?)?
+        .state()
+        .cloned()
+        .unwrap();
+    assert_eq!(id_state.sn, 1);
+    assert_eq!(
+        id_state.current.public_keys,
+        vec![Basic::Ed25519.derive(next_pub_key)]
+    );
+    assert!(id_state.perm.is_empty());
+
+    Ok(())
+}
+
+fn process_out_of_order<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    let icp_raw = MULTISIG_ICP;
+    let rot_raw = MULTISIG_ROT;
+    let ixn_raw = MULTISIG_IXN;
+
+    let icp = parse::signed_message(icp_raw.as_bytes()).unwrap().1;
+    let id = match &icp {
+        Deserialized::Event(e) => e.event.event.event.prefix.clone(),
+        _ => Err(Error::SemanticError("bad deser".into()))?,
+    };
+    event_processor.process(icp)?;
+
+    // Interaction event arrives before the rotation it follows.
+    let ixn = parse::signed_message(ixn_raw.as_bytes()).unwrap().1;
+    let id_state = event_processor.process(ixn);
+    assert!(matches!(
+        id_state,
+        Ok(ProcessingOutcome::Escrowed {
+            reason: EscrowReason::OutOfOrder,
+            ..
+        })
+    ));
+    assert_eq!(
+        event_processor
+            .db
+            .get_escrowed_events(SequenceTable::OutOfOrder, Some(&id))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Processing the rotation replays the escrowed interaction.
+    let rot = match parse::signed_message(rot_raw.as_bytes()).unwrap().1 {
+        Deserialized::Event(e) => e,
+        _ => Err(Error::SemanticError("bad deser".into()))?,
+    };
+    let states = match event_processor.process_event(rot)? {
+        ProcessingOutcome::Accepted { states, .. } => states,
+        _ => return Err(Error::SemanticError("event not accepted".into())),
+    };
+    assert_eq!(states.iter().map(|s| s.sn).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(event_processor.compute_state(&id)?, states.last().cloned());
+
+    // Nothing is left in escrow.
+    assert!(event_processor
+        .db
+        .get_escrowed_events(SequenceTable::OutOfOrder, None)
+        .unwrap()
+        .is_empty());
+
+    Ok(())
+}
+
+fn process_escrowed_receipts<D: EventDatabase>(
+    event_processor: EventProcessor<D>,
+) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use super::{DiscardReason, DiscardedReceipt};
+
+    let icp_raw = DIRECT_MODE_ICP;
+    let icp = match parse::signed_message(icp_raw.as_bytes()).unwrap().1 {
+        Deserialized::Event(e) => e,
+        _ => Err(Error::SemanticError("bad deser".into()))?,
+    };
+    let id = icp.event.event.event.prefix.clone();
+    let icp_dig = SelfAddressing::Blake3_256.derive(icp.event.raw);
+
+    // Witness receipts the inception event before it is known.
+    let (pub_key, priv_key) = keypair()?;
+    let witness = Basic::Ed25519NT.derive(pub_key);
+    let sig = signature(icp.event.raw, &priv_key)?;
+    let receipt = |dig| -> Result<SignedNontransferableReceipt, Error> {
+        witness_receipt(&id, 0, dig, vec![(witness.clone(), sig.clone())])
+    };
+    let wrong_dig = SelfAddressing::Blake3_256.derive(b"some other event");
+    assert!(matches!(
+        event_processor.process_witness_receipt(receipt(icp_dig.clone())?)?,
+        ProcessingOutcome::Escrowed {
+            reason: EscrowReason::MissingEvent,
+            ..
+        }
+    ));
+    event_processor.process_witness_receipt(receipt(wrong_dig.clone())?)?;
+    assert_eq!(
+        event_processor
+            .db
+            .get_escrowed_nt_receipts(Some(&id))
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // Finalising the event promotes the matching receipt and discards the other.
+    let discarded_receipts = match event_processor.process_event(icp)? {
+        ProcessingOutcome::Accepted {
+            states,
+            discarded_receipts,
+            ..
+        } => {
+            assert_eq!(states.len(), 1);
+            discarded_receipts
+        }
+        _ => return Err(Error::SemanticError("event not accepted".into())),
+    };
+    assert_eq!(discarded_receipts.len(), 1);
+    match &discarded_receipts[0] {
+        DiscardedReceipt::Nontransferable(r, reason) => {
+            assert_eq!(r.digest, wrong_dig);
+            assert_eq!(
+                reason,
+                &DiscardReason::DigestMismatch {
+                    expected: icp_dig.clone()
+                }
+            );
+        }
+        _ => panic!("expected a discarded witness receipt"),
+    }
+    assert_eq!(
+        event_processor
+            .db
+            .get_nt_receipts_for_event(&id, &icp_dig)
+            .unwrap(),
+        Some(vec![(witness, sig)])
+    );
+    assert!(event_processor
+        .db
+        .get_escrowed_nt_receipts(None)
+        .unwrap()
+        .is_empty());
+
+    Ok(())
+}
+
+fn process_witnessed_event<D: EventDatabase>(
+    event_processor: EventProcessor<D>,
+) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::event::{event_data::inception::InceptionEvent, sections::InceptionWitnessConfig};
+
+    let event_processor = event_processor.enforce_witness_threshold();
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, _) = keypair()?;
+    let witness_keys = [keypair()?, keypair()?];
+    let witnesses: Vec<_> = witness_keys
+        .iter()
+        .map(|(pk, _)| Basic::Ed25519NT.derive(pk.clone()))
+        .collect();
+
+    // Construct inception event which needs receipts from both witnesses.
+    let icp = InceptionEvent::new(
+        key_config(pub_key, next_pub_key),
+        Some(InceptionWitnessConfig {
+            tally: 2,
+            initial_witnesses: witnesses.clone(),
+        }),
+        None,
+    )
+    .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let id = icp.event.prefix.clone();
+    let icp_raw = icp.serialize()?;
+    let icp_dig = SelfAddressing::Blake3_256.derive(&icp_raw);
+
+    let signed_icp = sign(&icp, &priv_key)?;
+
+    let receipt = |i: usize| -> Result<SignedNontransferableReceipt, Error> {
+        let sig = signature(&icp_raw, &witness_keys[i].1)?;
+        witness_receipt(&id, 0, icp_dig.clone(), vec![(witnesses[i].clone(), sig)])
+    };
+
+    // The first witness receipt arrives before the event.
+    event_processor.process_witness_receipt(receipt(0)?)?;
+
+    // One receipt is not enough, the event waits for the other witness.
+    let deserialized_icp = parse::signed_message(&signed_icp).unwrap().1;
+    let id_state = event_processor.process(deserialized_icp);
+    assert!(matches!(
+        id_state,
+        Ok(ProcessingOutcome::Escrowed {
+            reason: EscrowReason::PartiallyWitnessed,
+            ..
+        })
+    ));
+    assert_eq!(event_processor.compute_state(&id)?, None);
+    assert_eq!(
+        event_processor
+            .db
+            .get_escrowed_events(SequenceTable::PartiallyWitnessed, Some(&id))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // The second receipt completes the threshold and finalises the event.
+    let id_state = event_processor.process_witness_receipt(receipt(1)?)?;
+    assert_eq!(id_state.state().map(|s| s.sn), Some(0));
+    assert!(event_processor
+        .db
+        .get_escrowed_events(SequenceTable::PartiallyWitnessed, None)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        event_processor
+            .db
+            .get_nt_receipts_for_event(&id, &icp_dig)
+            .unwrap()
+            .map(|r| r.len()),
+        Some(2)
+    );
+    assert!(event_processor
+        .db
+        .get_escrowed_nt_receipts(None)
+        .unwrap()
+        .is_empty());
+
+    Ok(())
+}
+
+fn process_duplicity<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use super::duplicity::DuplicityEvidence;
+    use crate::event::{
+        event_data::{inception::InceptionEvent, interaction::InteractionEvent},
+        sections::seal::{DigestSeal, Seal},
+    };
+
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, _) = keypair()?;
+    let (_, other_priv_key) = keypair()?;
+
+    let icp = InceptionEvent::new(key_config(pub_key, next_pub_key), None, None)
+        .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let id = icp.event.prefix.clone();
+    let icp_dig = SelfAddressing::Blake3_256.derive(&icp.serialize()?);
+
+    // Two different interaction events at sn 1.
+    let interaction = |data: &[u8]| {
+        EventMessage::new(
+            Event {
+                prefix: id.clone(),
+                sn: 1,
+                event_data: EventData::Ixn(InteractionEvent {
+                    previous_event_hash: icp_dig.clone(),
+                    data: vec![Seal::Digest(DigestSeal {
+                        dig: SelfAddressing::Blake3_256.derive(data),
+                    })],
+                }),
+            },
+            SerializationFormats::JSON,
+        )
+    };
+    let ixn = interaction(b"first")?;
+    let conflicting_ixn = interaction(b"second")?;
+    let forged_ixn = interaction(b"third")?;
+
+    process(&event_processor, &sign(&icp, &priv_key)?)?;
+    let signed_ixn = sign(&ixn, &priv_key)?;
+    assert_eq!(
+        process(&event_processor, &signed_ixn)?
+            .state()
+            .map(|s| s.sn),
+        Some(1)
+    );
+
+    // Resubmitting the finalised event is a plain duplicate.
+    assert!(matches!(
+        process(&event_processor, &signed_ixn),
+        Ok(ProcessingOutcome::Duplicate { .. })
+    ));
+
+    // A conflicting event signed with the wrong key is only likely duplicitous.
+    assert!(matches!(
+        process(&event_processor, &sign(&forged_ixn, &other_priv_key)?),
+        Ok(ProcessingOutcome::Rejected {
+            reason: Error::LikelyDuplicitousEventError,
+            ..
+        })
+    ));
+    assert_eq!(
+        event_processor
+            .db
+            .get_sequence_entries(SequenceTable::LikelyDuplicitous, Some(&id))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // A properly signed conflicting event is duplicitous.
+    let signed_conflicting_ixn = sign(&conflicting_ixn, &priv_key)?;
+    assert!(matches!(
+        process(&event_processor, &signed_conflicting_ixn),
+        Ok(ProcessingOutcome::Duplicitous { .. })
+    ));
+    assert_eq!(event_processor.compute_state(&id)?.map(|s| s.sn), Some(1));
+
+    // Both versions are kept and exported as evidence.
+    let evidence = event_processor.get_duplicity_evidence(&id)?;
+    assert_eq!(evidence.len(), 1);
+    assert_eq!(evidence[0].sn, 1);
+    let exported = evidence[0].serialize();
+    assert_eq!(exported, [signed_ixn, signed_conflicting_ixn].concat());
+
+    let imported = DuplicityEvidence::parse(&exported)?;
+    assert_eq!(imported, evidence[0]);
+    assert!(event_processor.verify_duplicity_evidence(&imported)?);
+
+    Ok(())
+}
+
+fn process_recovery<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::event::{
+        event_data::{
+            inception::InceptionEvent, interaction::InteractionEvent, rotation::RotationEvent,
+        },
+        sections::WitnessConfig,
+    };
+
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, next_priv_key) = keypair()?;
+    let (next_next_pub_key, _) = keypair()?;
+
+    let icp = InceptionEvent::new(key_config(pub_key, next_pub_key.clone()), None, None)
+        .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let id = icp.event.prefix.clone();
+    let icp_dig = SelfAddressing::Blake3_256.derive(&icp.serialize()?);
+    process(&event_processor, &sign(&icp, &priv_key)?)?;
+
+    // Two interaction events are finalised after inception.
+    let mut previous = icp_dig.clone();
+    let mut ixn_digs = vec![];
+    for sn in 1..3 {
+        let ixn = EventMessage::new(
+            Event {
+                prefix: id.clone(),
+                sn,
+                event_data: EventData::Ixn(InteractionEvent {
+                    previous_event_hash: previous,
+                    data: vec![],
+                }),
+            },
+            SerializationFormats::JSON,
+        )?;
+        previous = SelfAddressing::Blake3_256.derive(&ixn.serialize()?);
+        ixn_digs.push((sn, previous.clone()));
+        process(&event_processor, &sign(&ixn, &priv_key)?)?;
+    }
+    assert_eq!(event_processor.compute_state(&id)?.map(|s| s.sn), Some(2));
+
+    // A rotation at sn 1 recovers from both of them.
+    let rot = EventMessage::new(
+        Event {
+            prefix: id.clone(),
+            sn: 1,
+            event_data: EventData::Rot(RotationEvent {
+                previous_event_hash: icp_dig,
+                key_config: key_config(next_pub_key, next_next_pub_key),
+                witness_config: WitnessConfig::default(),
+                data: vec![],
+            }),
+        },
+        SerializationFormats::JSON,
+    )?;
+    let rot_raw = rot.serialize()?;
+    let id_state = process(&event_processor, &sign(&rot, &next_priv_key)?)?
+        .state()
+        .cloned()
+        .unwrap();
+    assert_eq!(id_state.sn, 1);
+    assert_eq!(event_processor.compute_state(&id)?, Some(id_state));
+
+    // The KEL is rewritten and the replaced events are kept.
+    assert_eq!(
+        event_processor.db.last_event_at_sn(&id, 1).unwrap(),
+        Some(rot_raw)
+    );
+    assert_eq!(event_processor.db.last_event_at_sn(&id, 2).unwrap(), None);
+    let superseded = event_processor
+        .db
+        .get_sequence_entries(SequenceTable::Superseded, Some(&id))
+        .unwrap();
+    assert_eq!(
+        superseded
+            .into_iter()
+            .map(|(_, sn, dig)| (sn, dig))
+            .collect::<Vec<_>>(),
+        ixn_digs
+    );
+    for (_, dig) in &ixn_digs {
+        assert!(event_processor.db.get_event(&id, dig).unwrap().is_some());
+    }
+
+    // The recovery rotation itself can't be superseded.
+    assert!(matches!(
+        process(&event_processor, &sign(&rot, &next_priv_key)?),
+        Ok(ProcessingOutcome::Duplicate { .. })
+    ));
+
+    Ok(())
+}
+
+fn process_stream<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    let icp_raw = MULTISIG_ICP;
+    let rot_raw = MULTISIG_ROT;
+    let ixn_raw = MULTISIG_IXN;
+
+    // The interaction comes before the rotation and the stream
+    // is cut off in the middle of a message.
+    let stream = [icp_raw, ixn_raw, rot_raw, r#"{"vs":"KERI10JSON"#]
+        .concat()
+        .into_bytes();
+    let processed = event_processor.process_stream(&stream)?;
+    assert!(!processed.is_complete());
+    assert_eq!(processed.unparsed, br#"{"vs":"KERI10JSON"#);
+
+    // Every message is reported, the interaction as finalised.
+    assert_eq!(processed.outcomes.len(), 3);
+    assert_eq!(
+        processed
+            .outcomes
+            .iter()
+            .map(|o| o.state().map(|s| s.sn))
+            .collect::<Vec<_>>(),
+        vec![Some(0), Some(2), Some(2)]
+    );
+    let id = processed.outcomes[0].event().prefix.clone();
+    assert_eq!(event_processor.compute_state(&id)?.map(|s| s.sn), Some(2));
+    assert!(event_processor
+        .db
+        .get_escrowed_events(SequenceTable::OutOfOrder, None)
+        .unwrap()
+        .is_empty());
+
+    // Processing it again only finds duplicates.
+    let processed = event_processor.process_stream(&stream)?;
+    assert!(processed
+        .outcomes
+        .iter()
+        .all(|o| matches!(o, ProcessingOutcome::Duplicate { .. })));
+
+    // An escrowed event which can't be replayed fails retrying the
+    // escrows, the outcomes are still reported with the error.
+    let garbage = b"garbage";
+    let garbage_dig = SelfAddressing::Blake3_256.derive(garbage);
+    event_processor
+        .db
+        .log_event(&id, &garbage_dig, garbage, &[])
+        .unwrap();
+    event_processor
+        .db
+        .escrow_out_of_order_event(&id, 5, &garbage_dig)
+        .unwrap();
+    let processed = event_processor.process_stream(&stream)?;
+    assert_eq!(processed.outcomes.len(), 3);
+    assert!(matches!(processed.error, Some(Error::DeserializationError)));
+
+    Ok(())
+}
+
+fn process_stream_receipts<D: EventDatabase>(
+    event_processor: EventProcessor<D>,
+) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    let icp_raw = MULTISIG_ICP;
+    let icp = parse::message(icp_raw.as_bytes()).unwrap().1.event;
+    let id = icp.event.prefix.clone();
+    let dig = event_digest(&icp.serialize()?)?;
+
+    // Both receipts come before the event they receipt, only
+    // the one with a valid signature gets stored.
+    let (witness_pk, witness_sk) = keypair()?;
+    let witness = Basic::Ed25519NT.derive(witness_pk);
+    let (_, forger_sk) = keypair()?;
+    let valid = witness_receipt(
+        &id,
+        0,
+        dig.clone(),
+        vec![(witness.clone(), signature(&icp.serialize()?, &witness_sk)?)],
+    )?;
+    let forged = witness_receipt(
+        &id,
+        0,
+        dig.clone(),
+        vec![(witness.clone(), signature(&icp.serialize()?, &forger_sk)?)],
+    )?;
+    let stream = [
+        valid.serialize()?,
+        forged.serialize()?,
+        icp_raw.as_bytes().to_vec(),
+    ]
+    .concat();
+    let processed = event_processor.process_stream(&stream)?;
+    assert!(processed.is_complete());
+    assert!(processed.error.is_none());
+    assert!(matches!(
+        processed.outcomes[0],
+        ProcessingOutcome::Accepted { .. }
+    ));
+    assert!(matches!(
+        processed.outcomes[1],
+        ProcessingOutcome::Escrowed {
+            reason: EscrowReason::MissingEvent,
+            ..
+        }
+    ));
+    assert!(matches!(
+        processed.outcomes[2],
+        ProcessingOutcome::Accepted { .. }
+    ));
+
+    // A malformed message stops processing, it and everything
+    // after it are returned.
+    let rest = [b"-AABgarbage".to_vec(), valid.serialize()?].concat();
+    let stream = [forged.serialize()?, rest.clone()].concat();
+    let processed = event_processor.process_stream(&stream)?;
+    assert_eq!(processed.outcomes.len(), 1);
+    assert_eq!(processed.unparsed, &rest[..]);
+    assert!(processed.error.is_none());
+
+    Ok(())
+}
+
+fn historical_state<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use chrono::Utc;
+    use std::{thread, time::Duration};
+
+    let icp_raw = MULTISIG_ICP;
+    let rot_raw = MULTISIG_ROT;
+    let ixn_raw = MULTISIG_IXN;
+
+    let before_icp = Utc::now();
+    let id_state = event_processor
+        .process(parse::signed_message(icp_raw.as_bytes()).unwrap().1)?
+        .state()
+        .cloned()
+        .unwrap();
+    let id = id_state.prefix.clone();
+    let icp_keys = id_state.current.clone();
+    event_processor.process(parse::signed_message(rot_raw.as_bytes()).unwrap().1)?;
+    thread::sleep(Duration::from_millis(2));
+    let before_ixn = Utc::now();
+    thread::sleep(Duration::from_millis(2));
+    event_processor.process(parse::signed_message(ixn_raw.as_bytes()).unwrap().1)?;
+    assert_eq!(event_processor.compute_state(&id)?.map(|s| s.sn), Some(2));
+
+    // State as of a given sn.
+    let at_icp = event_processor.compute_state_at_sn(&id, 0)?.unwrap();
+    assert_eq!(at_icp.sn, 0);
+    assert_eq!(at_icp.current, icp_keys);
+
+    // State right after a given event.
+    let rot_dig = SelfAddressing::Blake3_256.derive(
+        &event_processor
+            .db
+            .last_event_at_sn(&id, 1)
+            .unwrap()
+            .unwrap(),
+    );
+    let at_rot = event_processor
+        .compute_state_at_event(&id, &rot_dig)?
+        .unwrap();
+    assert_eq!(at_rot.sn, 1);
+    assert_eq!(
+        event_processor.get_keys_at_event(&id, &rot_dig)?,
+        Some(at_rot.current.clone())
+    );
+    let unknown_dig = SelfAddressing::Blake3_256.derive(b"not an event");
+    assert_eq!(
+        event_processor.compute_state_at_event(&id, &unknown_dig)?,
+        None
+    );
+
+    // State as of a first-seen time.
+    assert_eq!(
+        event_processor.compute_state_at_time(&id, before_icp)?,
+        None
+    );
+    assert_eq!(
+        event_processor.compute_state_at_time(&id, before_ixn)?,
+        Some(at_rot)
+    );
+    assert_eq!(
+        event_processor
+            .compute_state_at_time(&id, Utc::now())?
+            .map(|s| s.sn),
+        Some(2)
+    );
+
+    Ok(())
+}
+
+fn state_cache<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::{database::WriteOp, state::IdentifierState};
+
+    let icp_raw = MULTISIG_ICP;
+    let rot_raw = MULTISIG_ROT;
+    let ixn_raw = MULTISIG_IXN;
+
+    let mut id = None;
+    for raw in &[icp_raw, rot_raw, ixn_raw] {
+        let outcome = event_processor.process(parse::signed_message(raw.as_bytes()).unwrap().1)?;
+        id = Some(outcome.event().prefix.clone());
+    }
+    let id = id.unwrap();
+
+    // Finalising an event caches the resulting state, with the
+    // digest of that event.
+    let state = event_processor.compute_state(&id)?.unwrap();
+    let ixn_dig = event_digest(&state.last)?;
+    assert_eq!(state.sn, 2);
+    assert_eq!(
+        event_processor.db.get_cached_state(&id).unwrap(),
+        Some((
+            IdentifierState {
+                last: vec![],
+                ..state.clone()
+            },
+            ixn_dig.clone()
+        ))
+    );
+    assert!(event_processor.check_state_cache(&id)?);
+
+    // A stale cache is detected and rebuilt from the KEL.
+    let stale = IdentifierState {
+        last: vec![],
+        ..event_processor.compute_state_at_sn(&id, 0)?.unwrap()
+    };
+    let icp_dig = event_digest(
+        &parse::message(icp_raw.as_bytes())
+            .unwrap()
+            .1
+            .event
+            .serialize()?,
+    )?;
+    event_processor
+        .db
+        .commit(
+            WriteOp::UpdateState {
+                state: stale,
+                dig: icp_dig,
+            }
+            .into(),
+        )
+        .unwrap();
+    // A cache behind the KEL is not trusted for the current state.
+    assert_eq!(event_processor.compute_state(&id)?, Some(state.clone()));
+    assert!(!event_processor.check_state_cache(&id)?);
+    assert_eq!(
+        event_processor.rebuild_state_cache(&id)?,
+        Some(state.clone())
+    );
+    assert!(event_processor.check_state_cache(&id)?);
+    assert_eq!(event_processor.compute_state(&id)?, Some(state.clone()));
+
+    // Neither is one built from an event at the same sn which isn't
+    // in the KEL anymore, as after a recovery.
+    let superseded = IdentifierState {
+        last: vec![],
+        current: event_processor
+            .compute_state_at_sn(&id, 0)?
+            .unwrap()
+            .current,
+        ..state.clone()
+    };
+    event_processor
+        .db
+        .commit(
+            WriteOp::UpdateState {
+                state: superseded,
+                dig: SelfAddressing::Blake3_256.derive(b"superseded"),
+            }
+            .into(),
+        )
+        .unwrap();
+    assert_eq!(event_processor.compute_state(&id)?, Some(state));
+    assert!(!event_processor.check_state_cache(&id)?);
+
+    Ok(())
+}
+
+fn process_digest_agility<D: EventDatabase>(event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::event::event_data::{inception::InceptionEvent, interaction::InteractionEvent};
+
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, _) = keypair()?;
+    let (witness_pub_key, witness_priv_key) = keypair()?;
+
+    // The controller commits with SHA3-256 throughout.
+    let icp = InceptionEvent::new(
+        KeyConfig::new(
+            vec![Basic::Ed25519.derive(pub_key)],
+            nxt_commitment(
+                1,
+                &[Basic::Ed25519.derive(next_pub_key)],
+                SelfAddressing::SHA3_256,
+            ),
+            Some(1),
+        ),
+        None,
+        None,
+    )
+    .incept_self_addressing(SelfAddressing::SHA3_256, SerializationFormats::JSON)?;
+    let id = icp.event.prefix.clone();
+    let icp_raw = icp.serialize()?;
+    let icp_dig = SelfAddressing::SHA3_256.derive(&icp_raw);
+
+    // A witness receipts the inception with SHA2-256 before it is known.
+    let witness = Basic::Ed25519NT.derive(witness_pub_key);
+    let receipt = witness_receipt(
+        &id,
+        0,
+        SelfAddressing::SHA2_256.derive(&icp_raw),
+        vec![(witness.clone(), signature(&icp_raw, &witness_priv_key)?)],
+    )?;
+    event_processor.process_witness_receipt(receipt)?;
+
+    // The inception is stored under its SHA3-256 digest and the receipt is kept.
+    match process(&event_processor, &sign(&icp, &priv_key)?)? {
+        ProcessingOutcome::Accepted {
+            event,
+            discarded_receipts,
+            ..
+        } => {
+            assert_eq!(event.digest, icp_dig);
+            assert!(discarded_receipts.is_empty());
+        }
+        _ => return Err(Error::SemanticError("event not accepted".into())),
+    };
+    assert_eq!(
+        event_processor
+            .db
+            .get_nt_receipts_for_event(&id, &icp_dig)
+            .unwrap()
+            .map(|receipts| receipts.len()),
+        Some(1)
+    );
+
+    // The interaction binds to the inception with the same algorithm.
+    let ixn = EventMessage::new(
+        Event {
+            prefix: id.clone(),
+            sn: 1,
+            event_data: EventData::Ixn(InteractionEvent {
+                previous_event_hash: icp_dig.clone(),
+                data: vec![],
+            }),
+        },
+        SerializationFormats::JSON,
+    )?;
+    let outcome = process(&event_processor, &sign(&ixn, &priv_key)?)?;
+    assert_eq!(outcome.state().map(|s| s.sn), Some(1));
+    let ixn_dig = outcome.event().digest.clone();
+    assert_eq!(ixn_dig.derivation, SelfAddressing::SHA3_256);
+
+    // Events can be found by a digest with any indexed algorithm.
+    let ixn_raw = ixn.serialize()?;
+    assert_eq!(
+        event_processor
+            .db
+            .resolve_digest(&id, &SelfAddressing::Blake3_256.derive(&ixn_raw))
+            .unwrap(),
+        Some(ixn_dig.clone())
+    );
+    assert_eq!(
+        event_processor
+            .get_signed_event(&id, &SelfAddressing::Blake2B512.derive(&icp_raw))?
+            .raw,
+        icp_raw
+    );
+
+    // Digests with an algorithm which isn't implemented are rejected
+    // before anything is derived with them.
+    use crate::event::sections::seal::{DigestSeal, Seal};
+    let unimplemented = |previous_event_hash, data| {
+        EventMessage::new(
+            Event {
+                prefix: id.clone(),
+                sn: 2,
+                event_data: EventData::Ixn(InteractionEvent {
+                    previous_event_hash,
+                    data,
+                }),
+            },
+            SerializationFormats::JSON,
+        )
+    };
+    let ixn = unimplemented(
+        SelfAddressingPrefix::new(SelfAddressing::Blake2B256, vec![0; 32]),
+        vec![],
+    )?;
+    assert!(matches!(
+        process(&event_processor, &sign(&ixn, &priv_key)?)?,
+        ProcessingOutcome::Rejected {
+            reason: Error::PolicyViolation(PolicyViolation::UnimplementedDigest(
+                SelfAddressing::Blake2B256
+            )),
+            ..
+        }
+    ));
+    let ixn = unimplemented(
+        ixn_dig.clone(),
+        vec![Seal::Digest(DigestSeal {
+            dig: SelfAddressingPrefix::new(SelfAddressing::Blake2S256, vec![0; 32]),
+        })],
+    )?;
+    assert!(matches!(
+        process(&event_processor, &sign(&ixn, &priv_key)?)?,
+        ProcessingOutcome::Rejected {
+            reason: Error::PolicyViolation(PolicyViolation::UnimplementedDigest(
+                SelfAddressing::Blake2S256
+            )),
+            ..
+        }
+    ));
+    assert_eq!(event_processor.compute_state(&id)?.map(|s| s.sn), Some(1));
+
+    Ok(())
+}
+
+#[test]
+fn test_processor_config() -> Result<(), Error> {
+    use crate::{
+        event::{
+            event_data::{delegated::DelegatedInceptionEvent, inception::InceptionEvent},
+            sections::{seal::LocationSeal, InceptionWitnessConfig},
+        },
+        prefix::IdentifierPrefix,
+    };
+
+    let icp_raw = MULTISIG_ICP;
+
+    // Processes a signed event with a fresh processor using the given config
+    // and returns the rule it violated, if any.
+    let violation =
+        |config: ProcessorConfig, signed: &[u8]| -> Result<Option<PolicyViolation>, Error> {
+            let event_processor = EventProcessor::new(MemoryEventDatabase::new(), config);
+            let outcome = event_processor.process(parse::signed_message(signed).unwrap().1)?;
+            Ok(match outcome {
+                ProcessingOutcome::Rejected {
+                    event,
+                    reason: Error::PolicyViolation(violation),
+                } => {
+                    // Rejected events are not logged.
+                    assert_eq!(
+                        event_processor
+                            .db
+                            .last_event_at_sn(&event.prefix, event.sn)
+                            .unwrap(),
+                        None
+                    );
+                    Some(violation)
+                }
+                _ => None,
+            })
+        };
+
+    // The default config accepts any valid event.
+    assert_eq!(
+        violation(ProcessorConfig::default(), icp_raw.as_bytes())?,
+        None
+    );
+
+    assert!(matches!(
+        violation(
+            ProcessorConfig {
+                max_event_size: Some(256),
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        Some(PolicyViolation::EventTooLarge { max: 256, .. })
+    ));
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                allowed_self_addressing_codes: Some(vec![SelfAddressing::SHA3_256]),
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        Some(PolicyViolation::SelfAddressingCode(
+            SelfAddressing::Blake3_256
+        ))
+    );
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                allowed_basic_codes: Some(vec![Basic::ECDSAsecp256k1]),
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        Some(PolicyViolation::BasicCode(Basic::Ed25519))
+    );
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                allowed_self_signing_codes: Some(vec![SelfSigning::ECDSAsecp256k1Sha256]),
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        Some(PolicyViolation::SelfSigningCode(SelfSigning::Ed25519Sha512))
+    );
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                max_keys: Some(2),
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        Some(PolicyViolation::TooManyKeys { count: 3, max: 2 })
+    );
+
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, _) = keypair()?;
+    let (witness_pub_key, witness_priv_key) = keypair()?;
+    let (other_witness_pub_key, _) = keypair()?;
+
+    // Witness rules are checked against the resulting state.
+    let witnessed_icp = InceptionEvent::new(
+        key_config(pub_key.clone(), next_pub_key.clone()),
+        Some(InceptionWitnessConfig {
+            tally: 1,
+            initial_witnesses: vec![
+                Basic::Ed25519NT.derive(witness_pub_key.clone()),
+                Basic::Ed25519NT.derive(other_witness_pub_key),
+            ],
+        }),
+        None,
+    )
+    .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let signed_witnessed_icp = sign(&witnessed_icp, &priv_key)?;
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                max_witnesses: Some(1),
+                ..ProcessorConfig::default()
+            },
+            &signed_witnessed_icp
+        )?,
+        Some(PolicyViolation::TooManyWitnesses { count: 2, max: 1 })
+    );
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                min_toad: 2,
+                ..ProcessorConfig::default()
+            },
+            &signed_witnessed_icp
+        )?,
+        Some(PolicyViolation::ToadTooLow { toad: 1, min: 2 })
+    );
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                max_witnesses: Some(2),
+                min_toad: 1,
+                ..ProcessorConfig::default()
+            },
+            &signed_witnessed_icp
+        )?,
+        None
+    );
+    // Identifiers without witnesses have no toad to check.
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                min_toad: 2,
+                ..ProcessorConfig::default()
+            },
+            icp_raw.as_bytes()
+        )?,
+        None
+    );
+
+    let non_transferable_icp = EventMessage::new(
+        Event {
+            prefix: IdentifierPrefix::Basic(Basic::Ed25519NT.derive(pub_key.clone())),
+            sn: 0,
+            event_data: EventData::Icp(InceptionEvent::new(
+                key_config(pub_key.clone(), next_pub_key.clone()),
+                None,
+                None,
+            )),
+        },
+        SerializationFormats::JSON,
+    )?;
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                accept_non_transferable: false,
+                ..ProcessorConfig::default()
+            },
+            &sign(&non_transferable_icp, &priv_key)?
+        )?,
+        Some(PolicyViolation::NonTransferable)
+    );
+
+    let dip = DelegatedInceptionEvent {
+        inception_data: InceptionEvent::new(
+            key_config(pub_key.clone(), next_pub_key.clone()),
+            None,
+            None,
+        ),
+        perm: vec![],
+        seal: LocationSeal {
+            prefix: witnessed_icp.event.prefix.clone(),
+            sn: 1,
+            ilk: "ixn".into(),
+            prior_digest: SelfAddressing::Blake3_256.derive(&witnessed_icp.serialize()?),
+        },
+    }
+    .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    assert_eq!(
+        violation(
+            ProcessorConfig {
+                accept_delegated: false,
+                ..ProcessorConfig::default()
+            },
+            &sign(&dip, &priv_key)?
+        )?,
+        Some(PolicyViolation::Delegated)
+    );
+
+    // Receipts are checked before they are stored or escrowed.
+    let event_processor = EventProcessor::new(
+        MemoryEventDatabase::new(),
+        ProcessorConfig {
+            allowed_basic_codes: Some(vec![Basic::Ed25519]),
+            ..ProcessorConfig::default()
+        },
+    );
+    let witnessed_icp_raw = witnessed_icp.serialize()?;
+    let receipt = witness_receipt(
+        &witnessed_icp.event.prefix,
+        0,
+        SelfAddressing::Blake3_256.derive(&witnessed_icp_raw),
+        vec![(
+            Basic::Ed25519NT.derive(witness_pub_key),
+            signature(&witnessed_icp_raw, &witness_priv_key)?,
+        )],
+    )?;
+    assert!(matches!(
+        event_processor.process_witness_receipt(receipt)?,
+        ProcessingOutcome::Rejected {
+            reason: Error::PolicyViolation(PolicyViolation::BasicCode(Basic::Ed25519NT)),
+            ..
+        }
+    ));
+    assert_eq!(
+        event_processor
+            .db
+            .get_escrowed_nt_receipts(Some(&witnessed_icp.event.prefix))
+            .unwrap(),
+        vec![]
+    );
+
+    // Events are only indexed by the digest algorithms allowed.
+    let event_processor = EventProcessor::new(
+        MemoryEventDatabase::new(),
+        ProcessorConfig {
+            allowed_self_addressing_codes: Some(vec![
+                SelfAddressing::Blake3_256,
+                SelfAddressing::SHA2_256,
+            ]),
+            ..ProcessorConfig::default()
+        },
+    );
+    let outcome = event_processor.process(parse::signed_message(icp_raw.as_bytes()).unwrap().1)?;
+    let id = outcome.event().prefix.clone();
+    let icp = parse::message(icp_raw.as_bytes())
+        .unwrap()
+        .1
+        .event
+        .serialize()?;
+    let resolve = |code: SelfAddressing| event_processor.db.resolve_digest(&id, &code.derive(&icp));
+    assert_eq!(
+        resolve(SelfAddressing::SHA2_256).unwrap(),
+        Some(outcome.event().digest.clone())
+    );
+    assert_eq!(resolve(SelfAddressing::SHA3_256).unwrap(), None);
+
+    Ok(())
+}
+
+fn process_observer<D: EventDatabase>(mut event_processor: EventProcessor<D>) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use super::{observer::ProcessorObserver, outcome::EventId};
+    use crate::{
+        event::{
+            event_data::{inception::InceptionEvent, interaction::InteractionEvent},
+            sections::seal::{DigestSeal, Seal},
+        },
+        prefix::IdentifierPrefix,
+        state::IdentifierState,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, PartialEq)]
+    enum Notification {
+        Finalised(EventId, u64),
+        Escrowed(EventId, EscrowReason),
+        Resolved(EventId, EscrowReason),
+        ReceiptAdded(EventId, IdentifierPrefix),
+        Duplicity(IdentifierPrefix, u64),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Notification>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<Notification> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl ProcessorObserver for Recorder {
+        fn event_finalised(&self, event: &EventId, state: &IdentifierState) {
+            let n = Notification::Finalised(event.clone(), state.sn);
+            self.0.lock().unwrap().push(n);
+        }
+
+        fn event_escrowed(&self, event: &EventId, reason: EscrowReason) {
+            let n = Notification::Escrowed(event.clone(), reason);
+            self.0.lock().unwrap().push(n);
+        }
+
+        fn escrow_resolved(&self, event: &EventId, reason: EscrowReason) {
+            let n = Notification::Resolved(event.clone(), reason);
+            self.0.lock().unwrap().push(n);
+        }
+
+        fn receipt_added(&self, event: &EventId, signer: &IdentifierPrefix) {
+            let n = Notification::ReceiptAdded(event.clone(), signer.clone());
+            self.0.lock().unwrap().push(n);
+        }
+
+        fn duplicity_detected(&self, evidence: &super::duplicity::DuplicityEvidence) {
+            let n = Notification::Duplicity(evidence.prefix.clone(), evidence.sn);
+            self.0.lock().unwrap().push(n);
+        }
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    event_processor.register_observer(recorder.clone());
+
+    let (pub_key, priv_key) = keypair()?;
+    let (next_pub_key, _) = keypair()?;
+    let (witness_pub_key, witness_priv_key) = keypair()?;
+
+    let id_of = |message: &EventMessage| -> Result<EventId, Error> {
+        Ok(EventId {
+            prefix: message.event.prefix.clone(),
+            sn: message.event.sn,
+            digest: SelfAddressing::Blake3_256.derive(&message.serialize()?),
+        })
+    };
+
+    let icp = InceptionEvent::new(key_config(pub_key, next_pub_key), None, None)
+        .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+    let interaction = |sn, previous: &EventMessage, data: &[u8]| -> Result<EventMessage, Error> {
+        EventMessage::new(
+            Event {
+                prefix: icp.event.prefix.clone(),
+                sn,
+                event_data: EventData::Ixn(InteractionEvent {
+                    previous_event_hash: SelfAddressing::Blake3_256.derive(&previous.serialize()?),
+                    data: vec![Seal::Digest(DigestSeal {
+                        dig: SelfAddressing::Blake3_256.derive(data),
+                    })],
+                }),
+            },
+            SerializationFormats::JSON,
+        )
+    };
+    let ixn = interaction(1, &icp, b"first")?;
+    let next_ixn = interaction(2, &ixn, b"second")?;
+    let conflicting_ixn = interaction(1, &icp, b"third")?;
+
+    process(&event_processor, &sign(&icp, &priv_key)?)?;
+    assert_eq!(
+        recorder.take(),
+        vec![Notification::Finalised(id_of(&icp)?, 0)]
+    );
+
+    // An event ahead of the KEL is escrowed.
+    process(&event_processor, &sign(&next_ixn, &priv_key)?)?;
+    assert_eq!(
+        recorder.take(),
+        vec![Notification::Escrowed(
+            id_of(&next_ixn)?,
+            EscrowReason::OutOfOrder
+        )]
+    );
+
+    // So is a receipt of an unknown event.
+    let ixn_raw = ixn.serialize()?;
+    let witness = Basic::Ed25519NT.derive(witness_pub_key);
+    let receipt = witness_receipt(
+        &icp.event.prefix,
+        1,
+        SelfAddressing::Blake3_256.derive(&ixn_raw),
+        vec![(witness.clone(), signature(&ixn_raw, &witness_priv_key)?)],
+    )?;
+    event_processor.process_witness_receipt(receipt)?;
+    assert_eq!(
+        recorder.take(),
+        vec![Notification::Escrowed(
+            id_of(&ixn)?,
+            EscrowReason::MissingEvent
+        )]
+    );
+
+    // The missing event resolves both escrows.
+    process(&event_processor, &sign(&ixn, &priv_key)?)?;
+    assert_eq!(
+        recorder.take(),
+        vec![
+            Notification::Finalised(id_of(&ixn)?, 1),
+            Notification::Resolved(id_of(&next_ixn)?, EscrowReason::OutOfOrder),
+            Notification::Finalised(id_of(&next_ixn)?, 2),
+            Notification::Resolved(id_of(&ixn)?, EscrowReason::MissingEvent),
+            Notification::ReceiptAdded(id_of(&ixn)?, IdentifierPrefix::Basic(witness)),
+        ]
+    );
+
+    process(&event_processor, &sign(&conflicting_ixn, &priv_key)?)?;
+    assert_eq!(
+        recorder.take(),
+        vec![Notification::Duplicity(icp.event.prefix.clone(), 1)]
+    );
+
+    Ok(())
+}
+
+fn concurrent_processing<D: EventDatabase + Send + Sync + 'static>(
+    event_processor: EventProcessor<D>,
+) -> Result<(), Error>
+where
+    D::Error: Debug,
+{
+    use crate::event::{
+        event_data::{inception::InceptionEvent, interaction::InteractionEvent},
+        sections::seal::{DigestSeal, Seal},
+    };
+    use std::{sync::Arc, thread};
+
+    const IDENTIFIERS: usize = 4;
+    const INTERACTIONS: u64 = 5;
+
+    let mut kels = vec![];
+    for _ in 0..IDENTIFIERS {
+        let (pub_key, priv_key) = keypair()?;
+        let (next_pub_key, _) = keypair()?;
+
+        let mut last = InceptionEvent::new(key_config(pub_key, next_pub_key), None, None)
+            .incept_self_addressing(SelfAddressing::Blake3_256, SerializationFormats::JSON)?;
+        let id = last.event.prefix.clone();
+        let interaction = |sn, last: &EventMessage, data: &[u8]| {
+            EventMessage::new(
+                Event {
+                    prefix: id.clone(),
+                    sn,
+                    event_data: EventData::Ixn(InteractionEvent {
+                        previous_event_hash: SelfAddressing::Blake3_256.derive(&last.serialize()?),
+                        data: vec![Seal::Digest(DigestSeal {
+                            dig: SelfAddressing::Blake3_256.derive(data),
+                        })],
+                    }),
+                },
+                SerializationFormats::JSON,
+            )
+        };
+        let mut kel = vec![sign(&last, &priv_key)?];
+        for sn in 1..=INTERACTIONS {
+            last = interaction(sn, &last, b"")?;
+            kel.push(sign(&last, &priv_key)?);
+        }
+        // conflicting events for the next sn
+        let tips = [
+            sign(&interaction(INTERACTIONS + 1, &last, b"first")?, &priv_key)?,
+            sign(&interaction(INTERACTIONS + 1, &last, b"second")?, &priv_key)?,
+        ];
+        kels.push((id, kel, tips));
+    }
+
+    // Every KEL is processed by three workers at once: two in order,
+    // racing to finalise the same events and then conflicting ones,
+    // and one in reverse order, which goes through the out of order
+    // escrow.
+    let event_processor = Arc::new(event_processor);
+    let mut workers = vec![];
+    for (_, kel, tips) in &kels {
+        for worker in 0..3 {
+            let event_processor = Arc::clone(&event_processor);
+            let mut kel = kel.clone();
+            match tips.get(worker) {
+                Some(tip) => kel.push(tip.clone()),
+                None => kel.reverse(),
+            }
+            workers.push(thread::spawn(move || -> Result<(), Error> {
+                for signed in kel {
+                    event_processor.process(parse::signed_message(&signed).unwrap().1)?;
+                }
+                Ok(())
+            }));
+        }
+    }
+    for worker in workers {
+        worker.join().unwrap()?;
+    }
+
+    // Each KEL holds one event per sn and no escrow is left behind.
+    for (id, _, _) in &kels {
+        assert_eq!(
+            event_processor.compute_state(id)?.map(|s| s.sn),
+            Some(INTERACTIONS + 1)
+        );
+        let sns: Vec<u64> = event_processor
+            .db
+            .get_sequence_entries(SequenceTable::KeyEventLog, Some(id))
+            .unwrap()
+            .into_iter()
+            .map(|(_, sn, _)| sn)
+            .collect();
+        assert_eq!(sns, (0..=INTERACTIONS + 1).collect::<Vec<_>>());
+        assert!(event_processor
+            .db
+            .get_escrowed_events(SequenceTable::OutOfOrder, Some(id))
+            .unwrap()
+            .is_empty());
+        assert!(event_processor.check_state_cache(id)?);
+    }
 
     Ok(())
 }
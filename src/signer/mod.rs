@@ -1,51 +1,144 @@
-use crate::error::Error;
+use crate::{derivation::self_signing::SelfSigning, error::Error, prefix::AttachedSignaturePrefix};
 use ursa::{
-    keys::{PrivateKey, PublicKey},
+    keys::{KeyGenOption, PrivateKey, PublicKey},
     signatures::{ed25519, SignatureScheme},
 };
 
+/// Crypto Box
+///
+/// Holds the current signing keys of a controller and the
+/// next keys it has committed to
+#[derive(Clone)]
 pub struct CryptoBox {
-    signer: Signer,
-    next_priv_key: PrivateKey,
-    pub next_pub_key: PublicKey,
+    signers: Vec<Signer>,
+    next_signers: Vec<Signer>,
 }
 
 impl CryptoBox {
     pub fn new() -> Result<Self, Error> {
-        let ed = ed25519::Ed25519Sha512::new();
-        let signer = Signer::new()?;
-        let (next_pub_key, next_priv_key) = ed.keypair(None).map_err(|e| Error::CryptoError(e))?;
+        Self::generate(1, 1)
+    }
+
+    /// Generate
+    ///
+    /// Creates a key manager with `current` signing keys
+    /// and `next` pre-rotated keys
+    pub fn generate(current: usize, next: usize) -> Result<Self, Error> {
+        if current == 0 || next == 0 {
+            return Err(Error::SemanticError(
+                "Key manager needs at least one key".into(),
+            ));
+        }
+        Ok(CryptoBox {
+            signers: generate_signers(current)?,
+            next_signers: generate_signers(next)?,
+        })
+    }
+
+    /// Import
+    ///
+    /// Rebuilds a key manager from private keys returned by
+    /// `CryptoBox::export`
+    pub fn import(current: Vec<PrivateKey>, next: Vec<PrivateKey>) -> Result<Self, Error> {
+        if current.is_empty() || next.is_empty() {
+            return Err(Error::SemanticError(
+                "Key manager needs at least one key".into(),
+            ));
+        }
         Ok(CryptoBox {
-            signer,
-            next_pub_key,
-            next_priv_key,
+            signers: import_signers(current)?,
+            next_signers: import_signers(next)?,
         })
     }
 
+    /// Export
+    ///
+    /// Returns the private current and next keys, in key
+    /// config order, to be stored and imported later
+    pub fn export(&self) -> (Vec<PrivateKey>, Vec<PrivateKey>) {
+        (
+            self.signers.iter().map(|s| s.priv_key.clone()).collect(),
+            self.next_signers
+                .iter()
+                .map(|s| s.priv_key.clone())
+                .collect(),
+        )
+    }
+
+    /// Sign
+    ///
+    /// Signs with the first current key only
     pub fn sign(&self, msg: &Vec<u8>) -> Result<Vec<u8>, Error> {
-        self.signer.sign(msg)
+        self.signers[0].sign(msg)
+    }
+
+    /// Sign Indexed
+    ///
+    /// Signs with every current key, each signature indexed
+    /// by the position of its key in the key config
+    pub fn sign_indexed(&self, msg: &Vec<u8>) -> Result<Vec<AttachedSignaturePrefix>, Error> {
+        self.signers
+            .iter()
+            .enumerate()
+            .map(|(i, signer)| {
+                Ok(AttachedSignaturePrefix::new(
+                    SelfSigning::Ed25519Sha512,
+                    signer.sign(msg)?,
+                    i as u16,
+                ))
+            })
+            .collect()
     }
 
     pub fn public_key(&self) -> PublicKey {
-        self.signer.pub_key.clone()
+        self.signers[0].pub_key.clone()
+    }
+
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.signers.iter().map(|s| s.pub_key.clone()).collect()
     }
 
+    pub fn next_public_keys(&self) -> Vec<PublicKey> {
+        self.next_signers
+            .iter()
+            .map(|s| s.pub_key.clone())
+            .collect()
+    }
+
+    /// Rotate
+    ///
+    /// Takes the next keys into use and pre-rotates
+    /// as many new ones
     pub fn rotate(&self) -> Result<Self, Error> {
-        let ed = ed25519::Ed25519Sha512::new();
-        let (next_pub_key, next_priv_key) = ed.keypair(None).map_err(|e| Error::CryptoError(e))?;
-        let new_signer = Signer {
-            priv_key: self.next_priv_key.clone(),
-            pub_key: self.next_pub_key.clone(),
-        };
+        self.rotate_to(self.next_signers.len())
+    }
 
+    /// Rotate To
+    ///
+    /// Takes the next keys into use and pre-rotates
+    /// `next` new ones
+    pub fn rotate_to(&self, next: usize) -> Result<Self, Error> {
+        if next == 0 {
+            return Err(Error::SemanticError(
+                "Key manager needs at least one key".into(),
+            ));
+        }
         Ok(CryptoBox {
-            signer: new_signer,
-            next_priv_key,
-            next_pub_key,
+            signers: self.next_signers.clone(),
+            next_signers: generate_signers(next)?,
         })
     }
 }
 
+fn generate_signers(count: usize) -> Result<Vec<Signer>, Error> {
+    (0..count).map(|_| Signer::new()).collect()
+}
+
+fn import_signers(keys: Vec<PrivateKey>) -> Result<Vec<Signer>, Error> {
+    keys.into_iter().map(Signer::from_private_key).collect()
+}
+
+#[derive(Clone)]
 struct Signer {
     priv_key: PrivateKey,
     pub pub_key: PublicKey,
@@ -59,6 +152,14 @@ impl Signer {
         Ok(Signer { pub_key, priv_key })
     }
 
+    pub fn from_private_key(priv_key: PrivateKey) -> Result<Self, Error> {
+        let (pub_key, priv_key) = ed25519::Ed25519Sha512::new()
+            .keypair(Some(KeyGenOption::FromSecretKey(priv_key)))
+            .map_err(Error::CryptoError)?;
+
+        Ok(Signer { pub_key, priv_key })
+    }
+
     pub fn sign(&self, msg: &Vec<u8>) -> Result<Vec<u8>, Error> {
         let signature = ed25519::Ed25519Sha512::new()
             .sign(&msg, &self.priv_key)
@@ -18,6 +18,10 @@ pub struct IdentifierState {
     pub delegated_keys: Vec<IdentifierPrefix>,
     pub tally: u64,
     pub witnesses: Vec<BasicPrefix>,
+    /// the Identifier which delegated this one, if any
+    pub delegator: Option<IdentifierPrefix>,
+    /// ilks of the events the delegator permits, empty for all
+    pub perm: Vec<String>,
 }
 
 impl IdentifierState {